@@ -0,0 +1,98 @@
+//! Baselines for the user hot paths without network or database: JSON encoding of users and the
+//! services over `MemoryUserStore`. Run with `cargo bench`; criterion compares every run with
+//! the previous one and keeps reports under `target/criterion`.
+
+use std::hint::black_box;
+use std::sync::Arc;
+
+use chrono::{TimeZone, Utc};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tangy_mango::config::SearchConfig;
+use tangy_mango::models::user::{CreateUserRequest, UserResponse};
+use tangy_mango::services::memory_user_store::MemoryUserStore;
+use tangy_mango::services::user_search_service::UserSearchService;
+use tangy_mango::services::user_seeder::{generate_users, SeedOptions};
+use tangy_mango::services::user_service::UserService;
+use tangy_mango::services::user_store::UserStore;
+
+const STORE_SIZES: [usize; 2] = [100, 10_000];
+
+/// A store holding `count` generated users, the same ones on every run.
+fn seeded_store(count: usize) -> Arc<MemoryUserStore> {
+    let until = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+    let store = MemoryUserStore::new();
+    store.insert_users(generate_users(&SeedOptions::new(count, 1, until))).unwrap();
+    Arc::new(store)
+}
+
+fn serialization(c: &mut Criterion) {
+    let until = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+    let users: Vec<UserResponse> = generate_users(&SeedOptions::new(1000, 1, until))
+        .into_iter()
+        .map(UserResponse::from)
+        .collect();
+
+    let mut group = c.benchmark_group("serialization");
+    for count in [1, 100, 1000] {
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::new("users_to_json", count), &users[..count], |b, users| {
+            b.iter(|| serde_json::to_vec(black_box(users)).unwrap())
+        });
+    }
+
+    let body = br#"{"email": "Ada.Lovelace@Example.com", "name": "Ada Lovelace"}"#;
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("create_request_from_json", |b| {
+        b.iter(|| serde_json::from_slice::<CreateUserRequest>(black_box(body)).unwrap())
+    });
+    group.finish();
+}
+
+fn user_service(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("user_service");
+
+    // Validation, normalization and the insert, into a store that grows as the benchmark runs
+    let service = UserService::new(seeded_store(STORE_SIZES[0]));
+    let mut next = 0u64;
+    group.bench_function("create_user", |b| {
+        b.to_async(&runtime).iter(|| {
+            next += 1;
+            let request = CreateUserRequest {
+                email: format!("bench.user{}@Example.com", next),
+                name: "Bench User".to_string(),
+            };
+            let service = &service;
+            async move { service.create_user(request).await.unwrap() }
+        })
+    });
+
+    for size in STORE_SIZES {
+        let store = seeded_store(size);
+        let service = UserService::new(store.clone());
+        let ids: Vec<_> = runtime.block_on(store.get_all_users()).unwrap().iter().map(|user| user.id).collect();
+
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_function(BenchmarkId::new("get_all_users", size), |b| {
+            b.to_async(&runtime).iter(|| service.get_all_users())
+        });
+
+        group.throughput(Throughput::Elements(1));
+        let mut index = 0;
+        group.bench_function(BenchmarkId::new("get_user_by_id", size), |b| {
+            b.to_async(&runtime).iter(|| {
+                index = (index + 1) % ids.len();
+                service.get_user_by_id(ids[index])
+            })
+        });
+
+        let search = UserSearchService::new(store, SearchConfig::default());
+        group.bench_function(BenchmarkId::new("search", size), |b| {
+            b.to_async(&runtime).iter(|| search.search("mari", None))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, serialization, user_service);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+#!/bin/sh
+# Regenerates the certificates the TLS tests use: a CA, two server certificates for localhost
+# (the second to test reloading) and a client certificate, all valid for 100 years.
+set -e
+cd "$(dirname "$0")"
+
+key() {
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$1.key"
+}
+
+key ca
+openssl req -x509 -new -key ca.key -out ca.pem -days 36500 -subj "/CN=tangy-mango test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign,cRLSign"
+
+# sign NAME SUBJECT EXTENSIONS
+sign() {
+    key "$1"
+    openssl req -new -key "$1.key" -subj "$2" -out "$1.csr"
+    printf '%s\n' "$3" > "$1.ext"
+    openssl x509 -req -in "$1.csr" -CA ca.pem -CAkey ca.key -CAcreateserial -days 36500 \
+        -extfile "$1.ext" -out "$1.pem"
+    rm "$1.csr" "$1.ext"
+}
+
+sign server "/CN=localhost" "subjectAltName=DNS:localhost,IP:127.0.0.1
+extendedKeyUsage=serverAuth"
+sign server-renewed "/CN=localhost" "subjectAltName=DNS:localhost,IP:127.0.0.1
+extendedKeyUsage=serverAuth"
+sign client "/O=tangy-mango/CN=billing-service" "subjectAltName=DNS:billing.internal
+extendedKeyUsage=clientAuth"
+rm -f ca.srl
@@ -0,0 +1,3 @@
+-- One active subscription to user events
+INSERT INTO webhook_subscriptions (id, url, secret, event_types)
+VALUES ('01890a5d-ac96-774b-bcce-b30209000001', 'https://hooks.example.com/users', 'fixture-secret', ARRAY['user.created', 'user.updated']);
@@ -0,0 +1,14 @@
+-- Create idempotency keys table; a NULL status_code marks a request still in progress
+CREATE TABLE idempotency_keys (
+    scope VARCHAR(64) NOT NULL,
+    key VARCHAR(255) NOT NULL,
+    fingerprint CHAR(64) NOT NULL,
+    status_code SMALLINT,
+    response_body JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ,
+    PRIMARY KEY (scope, key)
+);
+
+-- Create index on created_at for expiring old keys
+CREATE INDEX idx_idempotency_keys_created_at ON idempotency_keys(created_at);
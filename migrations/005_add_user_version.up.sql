@@ -0,0 +1,2 @@
+-- Add a version counter for ETags and optimistic concurrency control
+ALTER TABLE users ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
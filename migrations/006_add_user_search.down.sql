@@ -0,0 +1,6 @@
+DROP INDEX idx_users_email_trgm;
+DROP INDEX idx_users_name_trgm;
+DROP INDEX idx_users_search_vector;
+ALTER TABLE users DROP COLUMN search_vector;
+
+-- pg_trgm is left installed; other schemas in the database may use it
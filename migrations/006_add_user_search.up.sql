@@ -0,0 +1,15 @@
+-- Full-text and fuzzy search over users
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- The 'simple' configuration does no stemming, which suits names and emails. Emails are indexed
+-- whole and split into their parts so that searching for a domain or local part matches.
+ALTER TABLE users ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('simple', name), 'A') ||
+    setweight(to_tsvector('simple', email || ' ' || regexp_replace(email, '[@._+-]+', ' ', 'g')), 'B')
+) STORED;
+
+CREATE INDEX idx_users_search_vector ON users USING GIN (search_vector);
+
+-- Trigram indexes back typo-tolerant matching with the <% (word similarity) operator
+CREATE INDEX idx_users_name_trgm ON users USING GIN (name gin_trgm_ops);
+CREATE INDEX idx_users_email_trgm ON users USING GIN (email gin_trgm_ops);
@@ -0,0 +1,9 @@
+-- Back to case-sensitive uniqueness on the raw email. Stored emails stay normalized, and
+-- accounts that collided keep their distinct spellings, so the constraint can be restored.
+DROP INDEX idx_users_email_normalized;
+ALTER TABLE users ADD CONSTRAINT users_email_key UNIQUE (email);
+CREATE INDEX idx_users_email ON users(email);
+
+DROP TABLE email_collisions;
+ALTER TABLE users DROP COLUMN email_collision;
+ALTER TABLE users DROP COLUMN email_normalized;
@@ -0,0 +1,40 @@
+-- Case-insensitive email uniqueness
+--
+-- Uniqueness moves from the raw email to a normalized key. Accounts that already collide under
+-- the new rule (e.g. Bob@x.com and bob@x.com) cannot be merged automatically, so they are
+-- recorded in email_collisions and reported as warnings; the oldest account of each group keeps
+-- the address and the others are exempted from the unique index until resolved.
+ALTER TABLE users ADD COLUMN email_normalized TEXT GENERATED ALWAYS AS (lower(btrim(email))) STORED;
+ALTER TABLE users ADD COLUMN email_collision BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE email_collisions (
+    email_normalized TEXT PRIMARY KEY,
+    -- Oldest first; the first user keeps the address
+    user_ids UUID[] NOT NULL,
+    detected_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO email_collisions (email_normalized, user_ids)
+SELECT email_normalized, array_agg(id ORDER BY created_at, id)
+FROM users
+GROUP BY email_normalized
+HAVING COUNT(*) > 1;
+
+UPDATE users
+SET email_collision = TRUE
+FROM email_collisions
+WHERE users.email_normalized = email_collisions.email_normalized
+  AND users.id <> email_collisions.user_ids[1];
+
+DO $$
+DECLARE
+    collision RECORD;
+BEGIN
+    FOR collision IN SELECT email_normalized, user_ids FROM email_collisions LOOP
+        RAISE WARNING 'Users % share the email address %', collision.user_ids, collision.email_normalized;
+    END LOOP;
+END $$;
+
+ALTER TABLE users DROP CONSTRAINT users_email_key;
+DROP INDEX idx_users_email;
+CREATE UNIQUE INDEX idx_users_email_normalized ON users (email_normalized) WHERE NOT email_collision;
@@ -0,0 +1,18 @@
+-- Pending and completed email changes. Only hashes of the emailed tokens are stored.
+CREATE TABLE email_changes (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    old_email VARCHAR(255) NOT NULL,
+    new_email VARCHAR(255) NOT NULL,
+    confirm_token_hash CHAR(64) NOT NULL UNIQUE,
+    revert_token_hash CHAR(64) NOT NULL UNIQUE,
+    -- pending, confirmed, cancelled or reverted
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    confirm_expires_at TIMESTAMPTZ NOT NULL,
+    revert_expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ
+);
+
+-- A user has at most one change awaiting confirmation
+CREATE UNIQUE INDEX idx_email_changes_pending_user ON email_changes(user_id) WHERE status = 'pending';
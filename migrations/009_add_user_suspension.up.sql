@@ -0,0 +1,2 @@
+-- Suspended users keep their data but are flagged for operators and consumers of user events
+ALTER TABLE users ADD COLUMN suspended_at TIMESTAMPTZ;
@@ -0,0 +1,7 @@
+-- Back to collation-aware lowering. Fails if users now differ only in the case of non-ASCII
+-- letters; resolve those accounts first.
+ALTER TABLE users DROP COLUMN email_normalized;
+ALTER TABLE users ADD COLUMN email_normalized TEXT GENERATED ALWAYS AS (lower(btrim(email))) STORED;
+CREATE UNIQUE INDEX idx_users_email_normalized ON users (email_normalized) WHERE NOT email_collision;
+
+DROP FUNCTION email_key(TEXT);
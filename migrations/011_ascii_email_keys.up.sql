@@ -0,0 +1,13 @@
+-- ASCII-only email keys
+--
+-- lower() follows the database collation, so it folded non-ASCII letters that the application's
+-- key (models::email::email_key) leaves alone. Under the "C" collation lower() changes only A-Z,
+-- matching it exactly; queries compare against email_key() so both sides share one definition.
+-- Keys only become more distinct, so no new collisions can appear.
+CREATE FUNCTION email_key(email TEXT) RETURNS TEXT
+    LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE
+    AS $$ SELECT lower(btrim(email) COLLATE "C") $$;
+
+ALTER TABLE users DROP COLUMN email_normalized;
+ALTER TABLE users ADD COLUMN email_normalized TEXT GENERATED ALWAYS AS (email_key(email)) STORED;
+CREATE UNIQUE INDEX idx_users_email_normalized ON users (email_normalized) WHERE NOT email_collision;
@@ -0,0 +1,2 @@
+DROP INDEX idx_user_events_txid;
+ALTER TABLE user_events DROP COLUMN txid;
@@ -0,0 +1,8 @@
+-- Commit-safe ordering for the change feed
+--
+-- Event ids come from a sequence at insert, so a transaction can commit a lower id after a higher
+-- one has already been read, and readers paging by id would skip it for good. Readers instead
+-- page through (txid, id) and only below pg_snapshot_xmin() of a fresh snapshot: every
+-- transaction there has finished, so no row can still appear behind a reader's position.
+ALTER TABLE user_events ADD COLUMN txid xid8 NOT NULL DEFAULT pg_current_xact_id();
+CREATE INDEX idx_user_events_txid ON user_events (txid, id);
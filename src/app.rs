@@ -0,0 +1,263 @@
+//! The HTTP application: shared services, routes and the server around them. `serve`,
+//! `serve --mock` and the API tests all start it through [`run_server`], so tests exercise
+//! exactly what runs.
+
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use actix_web::dev::Server;
+use actix_web::middleware::{from_fn, Condition, Logger};
+use actix_web::{web, App, HttpServer, Route};
+use crate::chaos::{self, Chaos, ChaosRules, ChaosUserStore, Faults};
+use crate::config::Settings;
+use crate::dao::email_change_dao::EmailChangeDao;
+use crate::dao::idempotency_dao::IdempotencyDao;
+use crate::dao::rate_limit_dao::RateLimitDao;
+use crate::dao::user_dao::UserDao;
+use crate::dao::user_event_dao::UserEventDao;
+use crate::dao::webhook_dao::WebhookDao;
+use crate::db::DbPool;
+use crate::handlers::{chaos_handler, email_change_handler, health_handler, metrics_handler, user_handler, webhook_handler};
+use crate::load_shed::{self, LoadShedder, PoolMonitor};
+use crate::mock;
+use crate::rate_limit::{self, MemoryRateLimitStore, RateLimitStore, RateLimitStoreKind, RateLimiter};
+use crate::services::email_change_service::EmailChangeService;
+use crate::services::idempotency_service::IdempotencyService;
+use crate::services::mailer::LogMailer;
+use crate::services::memory_user_store::MemoryUserStore;
+use crate::services::user_event_stream::UserEventStream;
+use crate::services::user_import_service::UserImportService;
+use crate::services::user_search_service::UserSearchService;
+use crate::services::user_service::UserService;
+use crate::services::user_store::UserStore;
+use crate::services::webhook_service::WebhookService;
+use crate::shutdown::{self, Lifecycle};
+use crate::tls;
+
+/// Services shared by every worker. Background jobs that feed them, such as the user event
+/// listener, are started separately by the caller.
+#[derive(Clone)]
+pub struct AppState {
+    pub user_service: Arc<UserService>,
+    pub user_search_service: Arc<UserSearchService>,
+    /// Services with no in-memory counterpart; `None` in mock mode, where their routes answer 503
+    pub postgres: Option<PostgresServices>,
+    /// Fault injection around requests and the user store, with `[chaos] enabled` or in mock mode
+    pub chaos: Option<Arc<Chaos>>,
+    /// Per-client quotas, with `[rate_limit] enabled`
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Database pool wait, sampled by a background job the caller starts; `None` in mock mode
+    pub pool_monitor: Option<Arc<PoolMonitor>>,
+    /// 503s under pool pressure or beyond per-route concurrency limits, with `[load_shedding] enabled`
+    pub load_shedder: Option<Arc<LoadShedder>>,
+    /// Readiness and in-flight requests, for graceful shutdown
+    pub lifecycle: Arc<Lifecycle>,
+}
+
+#[derive(Clone)]
+pub struct PostgresServices {
+    pub user_import_service: Arc<UserImportService>,
+    pub email_change_service: Arc<EmailChangeService>,
+    pub idempotency_service: Arc<IdempotencyService>,
+    pub webhook_service: Arc<WebhookService>,
+    pub user_event_stream: Arc<UserEventStream>,
+}
+
+impl AppState {
+    pub fn new(settings: &Settings, pool: &DbPool) -> Self {
+        let user_dao = UserDao::new(pool.clone());
+        let chaos = settings
+            .chaos
+            .enabled
+            .then(|| Arc::new(Chaos::new(settings.chaos.rules.clone(), settings.chaos.admin_token.clone())));
+        let user_store = with_chaos(Arc::new(user_dao.clone()), chaos.as_ref());
+        let pool_monitor = Arc::new(PoolMonitor::new(pool.clone(), &settings.load_shedding));
+
+        Self {
+            user_service: Arc::new(UserService::new(user_store.clone())),
+            user_search_service: Arc::new(UserSearchService::new(user_store, settings.search.clone())),
+            postgres: Some(PostgresServices {
+                user_import_service: Arc::new(UserImportService::new(user_dao.clone(), settings.import.clone())),
+                email_change_service: Arc::new(EmailChangeService::new(
+                    user_dao,
+                    EmailChangeDao::new(pool.clone()),
+                    Arc::new(LogMailer),
+                    settings.email_change.clone(),
+                )),
+                idempotency_service: Arc::new(IdempotencyService::new(
+                    IdempotencyDao::new(pool.clone()),
+                    settings.idempotency.clone(),
+                )),
+                webhook_service: Arc::new(WebhookService::new(WebhookDao::new(pool.clone()))),
+                user_event_stream: Arc::new(UserEventStream::new(
+                    UserEventDao::new(pool.clone()),
+                    settings.event_stream.clone(),
+                )),
+            }),
+            chaos,
+            rate_limiter: settings.rate_limit.enabled.then(|| {
+                let store: Arc<dyn RateLimitStore> = match settings.rate_limit.store {
+                    RateLimitStoreKind::Memory => Arc::new(MemoryRateLimitStore::new()),
+                    RateLimitStoreKind::Postgres => Arc::new(RateLimitDao::with_own_pool(pool, &settings.rate_limit)),
+                };
+                Arc::new(RateLimiter::new(settings.rate_limit.clone(), store))
+            }),
+            load_shedder: settings
+                .load_shedding
+                .enabled
+                .then(|| Arc::new(LoadShedder::new(settings.load_shedding.clone(), Some(pool_monitor.clone())))),
+            pool_monitor: Some(pool_monitor),
+            lifecycle: Arc::new(Lifecycle::new(settings.shutdown.clone())),
+        }
+    }
+
+    /// Serves users from `store` alone, for `serve --mock`, with `faults` on every request.
+    /// Rate limits, when enabled, are counted in memory whatever the configured store.
+    pub fn mock(settings: &Settings, store: MemoryUserStore, faults: Faults) -> Self {
+        let rules = ChaosRules { active: true, http: faults, ..ChaosRules::default() };
+        let chaos = Arc::new(Chaos::new(rules, settings.chaos.admin_token.clone()));
+        let user_store = with_chaos(Arc::new(store), Some(&chaos));
+
+        Self {
+            user_service: Arc::new(UserService::new(user_store.clone())),
+            user_search_service: Arc::new(UserSearchService::new(user_store, settings.search.clone())),
+            postgres: None,
+            chaos: Some(chaos),
+            rate_limiter: settings.rate_limit.enabled.then(|| {
+                Arc::new(RateLimiter::new(settings.rate_limit.clone(), Arc::new(MemoryRateLimitStore::new())))
+            }),
+            pool_monitor: None,
+            load_shedder: settings
+                .load_shedding
+                .enabled
+                .then(|| Arc::new(LoadShedder::new(settings.load_shedding.clone(), None))),
+            lifecycle: Arc::new(Lifecycle::new(settings.shutdown.clone())),
+        }
+    }
+
+    /// Registers the services and every route.
+    pub fn configure(&self, cfg: &mut web::ServiceConfig) {
+        cfg.app_data(web::Data::from(self.user_service.clone()))
+            .app_data(web::Data::from(self.user_search_service.clone()))
+            .app_data(web::Data::from(self.lifecycle.clone()))
+            .route("/health/live", web::get().to(health_handler::live))
+            .route("/health/ready", web::get().to(health_handler::ready))
+            .route("/metrics", web::get().to(metrics_handler::metrics));
+        if let Some(pool_monitor) = &self.pool_monitor {
+            cfg.app_data(web::Data::from(pool_monitor.clone()));
+        }
+        if let Some(load_shedder) = &self.load_shedder {
+            cfg.app_data(web::Data::from(load_shedder.clone()));
+        }
+        if let Some(services) = &self.postgres {
+            cfg.app_data(web::Data::from(services.user_import_service.clone()))
+                .app_data(web::Data::from(services.email_change_service.clone()))
+                .app_data(web::Data::from(services.idempotency_service.clone()))
+                .app_data(web::Data::from(services.webhook_service.clone()))
+                .app_data(web::Data::from(services.user_event_stream.clone()));
+        }
+        if let Some(rate_limiter) = &self.rate_limiter {
+            cfg.app_data(web::Data::from(rate_limiter.clone()));
+        }
+        if let Some(chaos) = &self.chaos {
+            cfg.app_data(web::Data::from(chaos.clone())).service(
+                web::scope("/admin/chaos")
+                    .route("", web::get().to(chaos_handler::get_chaos))
+                    .route("", web::put().to(chaos_handler::put_chaos))
+                    .route("/enable", web::post().to(chaos_handler::enable_chaos))
+                    .route("/disable", web::post().to(chaos_handler::disable_chaos)),
+            );
+        }
+
+        let postgres = self.postgres.is_some();
+        cfg.configure(|cfg| routes(cfg, postgres));
+    }
+}
+
+fn with_chaos(store: Arc<dyn UserStore>, chaos: Option<&Arc<Chaos>>) -> Arc<dyn UserStore> {
+    match chaos {
+        Some(chaos) => Arc::new(ChaosUserStore::new(store, chaos.clone())),
+        None => store,
+    }
+}
+
+/// `route` itself when Postgres is available, otherwise a 503 for any method.
+fn needs_postgres(postgres: bool, route: Route) -> Route {
+    if postgres {
+        route
+    } else {
+        web::route().to(mock::unavailable)
+    }
+}
+
+fn routes(cfg: &mut web::ServiceConfig, postgres: bool) {
+    let pg = |route| needs_postgres(postgres, route);
+    cfg.service(
+        web::scope("/api/v1")
+            // Custom method in the AIP-136 style, registered ahead of the /users scope
+            .route("/users:batchGet", web::post().to(user_handler::batch_get_users))
+            .service(
+                web::scope("/users")
+                    .route("", web::post().to(user_handler::create_user))
+                    .route("", web::get().to(user_handler::get_users))
+                    .route("/stream", pg(web::get().to(user_handler::stream_users)))
+                    .route("/import", pg(web::post().to(user_handler::import_users)))
+                    .route("/export", web::get().to(user_handler::export_users))
+                    .route("/search", web::get().to(user_handler::search_users))
+                    .route("/lookup", web::get().to(user_handler::lookup_user))
+                    .route("/{id}", web::get().to(user_handler::get_user))
+                    .route("/{id}", web::put().to(user_handler::update_user))
+                    .route("/{id}", web::patch().to(user_handler::patch_user))
+                    .route("/{id}/email-change", pg(web::post().to(email_change_handler::request_email_change)))
+                    .route("/{id}/email-change", pg(web::get().to(email_change_handler::get_email_change)))
+            )
+            .service(
+                web::scope("/email-changes")
+                    .route("/confirm", pg(web::post().to(email_change_handler::confirm_email_change)))
+                    .route("/revert", pg(web::post().to(email_change_handler::revert_email_change)))
+            )
+            .service(
+                web::scope("/webhooks")
+                    .route("", pg(web::post().to(webhook_handler::create_webhook)))
+                    .route("", pg(web::get().to(webhook_handler::get_webhooks)))
+                    .route("/dead-letters", pg(web::get().to(webhook_handler::get_dead_letters)))
+                    .route("/dead-letters/{id}/replay", pg(web::post().to(webhook_handler::replay_dead_letter)))
+                    .route("/{id}", pg(web::get().to(webhook_handler::get_webhook)))
+                    .route("/{id}", pg(web::delete().to(webhook_handler::delete_webhook)))
+                    .route("/{id}/deliveries", pg(web::get().to(webhook_handler::get_webhook_deliveries)))
+            )
+    );
+}
+
+/// Serves the application on an already bound listener, over HTTPS when `tls` is given. The
+/// returned server runs once awaited or spawned; it ignores signals, leaving shutdown to
+/// [`shutdown::serve`] or the caller.
+pub fn run_server(
+    state: AppState,
+    listener: TcpListener,
+    workers: Option<usize>,
+    tls: Option<rustls::ServerConfig>,
+) -> std::io::Result<Server> {
+    let mut server = HttpServer::new(move || {
+        let state = state.clone();
+        App::new()
+            .wrap(Condition::new(state.chaos.is_some(), from_fn(chaos::inject_faults)))
+            // The last wrap runs first, so rate-limited requests never reach the adaptive limiter
+            .wrap(Condition::new(state.load_shedder.is_some(), from_fn(load_shed::shed_load)))
+            .wrap(Condition::new(state.rate_limiter.is_some(), from_fn(rate_limit::limit_requests)))
+            .wrap(Logger::default())
+            .wrap(from_fn(shutdown::track_requests))
+            .configure(move |cfg| state.configure(cfg))
+    })
+    .on_connect(tls::capture_client_identity)
+    .disable_signals();
+    if let Some(workers) = workers {
+        server = server.workers(workers);
+    }
+
+    let server = match tls {
+        Some(config) => server.listen_rustls_0_21(listener, config)?,
+        None => server.listen(listener)?,
+    };
+    Ok(server.run())
+}
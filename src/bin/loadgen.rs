@@ -0,0 +1,49 @@
+//! Load generator for a running tangy-mango server; see `tangy_mango::loadgen`.
+
+use std::time::Duration;
+
+use clap::Parser;
+use tangy_mango::loadgen::{self, LoadOptions, Scenario};
+
+#[derive(Debug, Parser)]
+#[command(name = "loadgen", about = "Measure throughput and latency of a running server")]
+struct Args {
+    /// Server root URL
+    #[arg(long, default_value = "http://localhost:8080")]
+    url: String,
+    #[arg(long, value_enum, default_value_t = Scenario::Mixed)]
+    scenario: Scenario,
+    /// Requests kept in flight at once
+    #[arg(short, long, default_value_t = 16)]
+    concurrency: usize,
+    /// How long to send requests for
+    #[arg(short, long, value_name = "SECS", default_value_t = 10)]
+    duration: u64,
+    /// Stop after this many requests, even before the duration is up
+    #[arg(short = 'n', long)]
+    requests: Option<u64>,
+    /// Print the report as JSON, e.g. to keep as a baseline
+    #[arg(long)]
+    json: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let options = LoadOptions {
+        base_url: args.url,
+        scenario: args.scenario,
+        concurrency: args.concurrency,
+        duration: Duration::from_secs(args.duration),
+        max_requests: args.requests,
+    };
+
+    match loadgen::run(&options).await {
+        Ok(report) if args.json => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+        Ok(report) => println!("{}", report),
+        Err(err) => {
+            eprintln!("Load test failed: {:#}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,639 @@
+//! Fault injection for resilience testing: added latency, failures and dropped connections,
+//! both on HTTP requests and on the user store the services read and write through. Mounted
+//! with `[chaos] enabled = true` in staging and tests, and always in `serve --mock`, the
+//! rules can be swapped or switched off at runtime through `/admin/chaos`.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::web::{self, Bytes};
+use actix_web::HttpResponse;
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, BoxStream};
+use futures_util::{StreamExt, TryStreamExt};
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use crate::dao::user_dao::UserPatchOutcome;
+use crate::errors::ServiceError;
+use crate::handlers::ErrorResponse;
+use crate::models::user::{CreateUserRequest, UpdateUserRequest, User};
+use crate::models::user_export::UserFilter;
+use crate::models::user_search::UserSearchMatch;
+use crate::services::user_store::{PatchFn, UserStore};
+
+/// A delay drawn uniformly from `min..=max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Latency {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl FromStr for Latency {
+    type Err = String;
+
+    /// Parses milliseconds, either fixed (`250`) or as a range (`100-500`).
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let millis = |part: &str| {
+            part.trim()
+                .parse::<u64>()
+                .map(Duration::from_millis)
+                .map_err(|_| format!("'{}' is not a number of milliseconds", part.trim()))
+        };
+
+        let (min, max) = match value.split_once('-') {
+            Some((min, max)) => (millis(min)?, millis(max)?),
+            None => {
+                let fixed = millis(value)?;
+                (fixed, fixed)
+            }
+        };
+        if min > max {
+            return Err(format!("the latency range {} is empty", value));
+        }
+        Ok(Latency { min, max })
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min.as_millis())
+        } else {
+            write!(f, "{}-{}", self.min.as_millis(), self.max.as_millis())
+        }
+    }
+}
+
+/// Written the way it is parsed, so rules read back from `GET /admin/chaos` can be sent again.
+impl Serialize for Latency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Accepts a number of milliseconds as well as the string forms of [`Latency::from_str`].
+impl<'de> Deserialize<'de> for Latency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct LatencyVisitor;
+
+        impl serde::de::Visitor<'_> for LatencyVisitor {
+            type Value = Latency;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("milliseconds, as a number or a MIN-MAX range")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, millis: u64) -> Result<Latency, E> {
+                let fixed = Duration::from_millis(millis);
+                Ok(Latency { min: fixed, max: fixed })
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, millis: i64) -> Result<Latency, E> {
+                let millis = u64::try_from(millis).map_err(|_| E::custom("latency cannot be negative"))?;
+                self.visit_u64(millis)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Latency, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(LatencyVisitor)
+    }
+}
+
+impl Latency {
+    fn sample(&self) -> Duration {
+        if self.min == self.max {
+            return self.min;
+        }
+        rand::thread_rng().gen_range(self.min..=self.max)
+    }
+}
+
+/// Parses a rate between 0 and 1.
+pub fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("'{}' is not a rate between 0 and 1", value)),
+    }
+}
+
+/// What happens to one HTTP request or store call.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Faults {
+    /// Delay before the request or call goes ahead, or fails
+    pub latency: Option<Latency>,
+    /// Share of calls, from 0 to 1, that fail: a 500 over HTTP, a pool timeout in the store
+    pub error_rate: f64,
+    /// Share of calls, from 0 to 1, whose connection is cut: the client's for HTTP, the
+    /// database's in the store
+    pub drop_rate: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fault {
+    Error,
+    Drop,
+}
+
+impl Faults {
+    fn problems(&self, name: &str, problems: &mut Vec<String>) {
+        for (rate, field) in [(self.error_rate, "error_rate"), (self.drop_rate, "drop_rate")] {
+            if !(0.0..=1.0).contains(&rate) {
+                problems.push(format!("{}.{} must be between 0 and 1", name, field));
+            }
+        }
+        if self.error_rate + self.drop_rate > 1.0 {
+            problems.push(format!("{}.error_rate and {}.drop_rate must not add up to more than 1", name, name));
+        }
+    }
+
+    /// Draws the fault for one call, if any.
+    fn pick(&self) -> Option<Fault> {
+        if self.error_rate <= 0.0 && self.drop_rate <= 0.0 {
+            return None;
+        }
+        let draw: f64 = rand::thread_rng().gen();
+        if draw < self.drop_rate {
+            Some(Fault::Drop)
+        } else if draw < self.drop_rate + self.error_rate {
+            Some(Fault::Error)
+        } else {
+            None
+        }
+    }
+
+    /// Waits out the latency, then draws the fault.
+    async fn inject(&self) -> Option<Fault> {
+        if let Some(latency) = self.latency {
+            tokio::time::sleep(latency.sample()).await;
+        }
+        self.pick()
+    }
+}
+
+/// Faults for the HTTP requests matching `method` and `path`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteFaults {
+    /// Any method when unset
+    #[serde(default)]
+    pub method: Option<String>,
+    /// A route pattern such as `/api/v1/users/{id}`, where `{...}` matches any one segment
+    /// and a final `*` any number of them
+    pub path: String,
+    pub faults: Faults,
+}
+
+impl RouteFaults {
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        self.method.as_deref().is_none_or(|expected| expected.eq_ignore_ascii_case(method.as_str()))
+            && path_matches(&self.path, path)
+    }
+}
+
+pub(crate) fn path_matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
+    let path: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    let segment_matches = |(pattern, segment): (&&str, &&str)| {
+        pattern == segment || (pattern.starts_with('{') && pattern.ends_with('}') && !segment.is_empty())
+    };
+
+    match pattern.split_last() {
+        Some((&"*", prefix)) => path.len() >= prefix.len() && prefix.iter().zip(&path).all(segment_matches),
+        _ => path.len() == pattern.len() && pattern.iter().zip(&path).all(segment_matches),
+    }
+}
+
+/// Everything the chaos layer injects, as configured under `[chaos.rules]` or sent to
+/// `PUT /admin/chaos`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosRules {
+    /// Whether anything is injected; switching off keeps the rules for later
+    pub active: bool,
+    /// Faults for HTTP requests matching no route rule
+    pub http: Faults,
+    /// Faults for every user store call, whichever request makes it
+    pub store: Faults,
+    /// Faults replacing `http` for the requests they match; the first match wins
+    pub routes: Vec<RouteFaults>,
+}
+
+impl ChaosRules {
+    /// Values that would make no sense to inject, one message each.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        self.http.problems("http", &mut problems);
+        self.store.problems("store", &mut problems);
+        for (index, route) in self.routes.iter().enumerate() {
+            let name = format!("routes[{}]", index);
+            if !route.path.starts_with('/') {
+                problems.push(format!("{}.path must start with /", name));
+            }
+            if let Some(method) = &route.method {
+                if Method::from_bytes(method.as_bytes()).is_err() {
+                    problems.push(format!("{}.method '{}' is not an HTTP method", name, method));
+                }
+            }
+            route.faults.problems(&format!("{}.faults", name), &mut problems);
+        }
+        problems
+    }
+}
+
+/// The rules in force, shared by the HTTP middleware, the wrapped user store and the admin
+/// endpoint.
+pub struct Chaos {
+    rules: RwLock<ChaosRules>,
+    /// Required as a bearer token by `/admin/chaos` when set
+    admin_token: Option<String>,
+}
+
+impl Chaos {
+    pub fn new(rules: ChaosRules, admin_token: Option<String>) -> Self {
+        Self { rules: RwLock::new(rules), admin_token }
+    }
+
+    pub fn rules(&self) -> ChaosRules {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// Replaces the rules, unless they have problems.
+    pub fn set_rules(&self, rules: ChaosRules) -> Result<(), Vec<String>> {
+        let problems = rules.problems();
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+        *self.rules.write().unwrap() = rules;
+        Ok(())
+    }
+
+    pub fn set_active(&self, active: bool) {
+        self.rules.write().unwrap().active = active;
+    }
+
+    /// Whether an `Authorization` header value grants access to `/admin/chaos`.
+    pub fn authorizes(&self, authorization: Option<&str>) -> bool {
+        let Some(expected) = &self.admin_token else {
+            return true;
+        };
+        let Some(token) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+            return false;
+        };
+        // Comparing digests keeps the time taken from revealing how much of the token matched
+        Sha256::digest(token.as_bytes()) == Sha256::digest(expected.as_bytes())
+    }
+
+    fn http_faults(&self, method: &Method, path: &str) -> Option<Faults> {
+        let rules = self.rules.read().unwrap();
+        if !rules.active {
+            return None;
+        }
+        let route = rules.routes.iter().find(|route| route.matches(method, path));
+        Some(route.map_or(&rules.http, |route| &route.faults).clone())
+    }
+
+    /// Delays or fails a store call as the rules say.
+    async fn before_store_call(&self) -> Result<(), sqlx::Error> {
+        let faults = {
+            let rules = self.rules.read().unwrap();
+            if !rules.active {
+                return Ok(());
+            }
+            rules.store.clone()
+        };
+
+        match faults.inject().await {
+            None => Ok(()),
+            Some(Fault::Error) => Err(sqlx::Error::PoolTimedOut),
+            Some(Fault::Drop) => Err(sqlx::Error::Io(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "connection dropped by fault injection",
+            ))),
+        }
+    }
+}
+
+/// Middleware delaying, failing or dropping requests as the registered [`Chaos`] says. The
+/// admin endpoint is left alone, so faults can always be switched off again, and so are
+/// health checks, which would otherwise take the instance out of rotation.
+pub(crate) async fn inject_faults(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let faults = request
+        .app_data::<web::Data<Chaos>>()
+        .filter(|_| !request.path().starts_with("/admin/") && !request.path().starts_with("/health/"))
+        .and_then(|chaos| chaos.http_faults(request.method(), request.path()));
+
+    if let Some(faults) = faults {
+        let response = match faults.inject().await {
+            None => None,
+            Some(Fault::Error) => {
+                Some(HttpResponse::InternalServerError().json(ErrorResponse::new("Internal server error")))
+            }
+            // A body that fails before its first byte makes the server abort the connection
+            Some(Fault::Drop) => Some(HttpResponse::Ok().streaming(stream::once(async {
+                Err::<Bytes, _>(io::Error::new(io::ErrorKind::ConnectionAborted, "dropped by fault injection"))
+            }))),
+        };
+        if let Some(response) = response {
+            return Ok(request.into_response(response).map_into_right_body());
+        }
+    }
+
+    Ok(next.call(request).await?.map_into_left_body())
+}
+
+/// A [`UserStore`] whose calls are delayed or failed by the store rules of a [`Chaos`] before
+/// reaching the store it wraps.
+pub struct ChaosUserStore {
+    inner: Arc<dyn UserStore>,
+    chaos: Arc<Chaos>,
+}
+
+impl ChaosUserStore {
+    pub fn new(inner: Arc<dyn UserStore>, chaos: Arc<Chaos>) -> Self {
+        Self { inner, chaos }
+    }
+}
+
+impl UserStore for ChaosUserStore {
+    fn create_user(&self, request: CreateUserRequest) -> BoxFuture<'_, Result<User, sqlx::Error>> {
+        Box::pin(async move {
+            self.chaos.before_store_call().await?;
+            self.inner.create_user(request).await
+        })
+    }
+
+    fn update_user<'a>(
+        &'a self,
+        id: Uuid,
+        request: &'a UpdateUserRequest,
+        expected_versions: Option<&'a [i64]>,
+    ) -> BoxFuture<'a, Result<Option<User>, sqlx::Error>> {
+        Box::pin(async move {
+            self.chaos.before_store_call().await?;
+            self.inner.update_user(id, request, expected_versions).await
+        })
+    }
+
+    fn patch_user<'a>(
+        &'a self,
+        id: Uuid,
+        expected_versions: Option<&'a [i64]>,
+        apply: PatchFn<'a>,
+    ) -> BoxFuture<'a, Result<UserPatchOutcome<ServiceError>, sqlx::Error>> {
+        Box::pin(async move {
+            self.chaos.before_store_call().await?;
+            self.inner.patch_user(id, expected_versions, apply).await
+        })
+    }
+
+    fn get_user_by_id(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(async move {
+            self.chaos.before_store_call().await?;
+            self.inner.get_user_by_id(id).await
+        })
+    }
+
+    fn get_users_by_ids<'a>(&'a self, ids: &'a [Uuid]) -> BoxFuture<'a, Result<Vec<User>, sqlx::Error>> {
+        Box::pin(async move {
+            self.chaos.before_store_call().await?;
+            self.inner.get_users_by_ids(ids).await
+        })
+    }
+
+    fn get_user_by_email<'a>(&'a self, email: &'a str) -> BoxFuture<'a, Result<Option<User>, sqlx::Error>> {
+        Box::pin(async move {
+            self.chaos.before_store_call().await?;
+            self.inner.get_user_by_email(email).await
+        })
+    }
+
+    fn set_suspended(&self, id: Uuid, suspended: bool) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(async move {
+            self.chaos.before_store_call().await?;
+            self.inner.set_suspended(id, suspended).await
+        })
+    }
+
+    fn delete_user(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(async move {
+            self.chaos.before_store_call().await?;
+            self.inner.delete_user(id).await
+        })
+    }
+
+    fn get_all_users(&self) -> BoxFuture<'_, Result<Vec<User>, sqlx::Error>> {
+        Box::pin(async move {
+            self.chaos.before_store_call().await?;
+            self.inner.get_all_users().await
+        })
+    }
+
+    /// Faults apply once, before the first user is read.
+    fn stream_users(&self, filter: &UserFilter) -> BoxStream<'static, Result<User, sqlx::Error>> {
+        let chaos = self.chaos.clone();
+        let users = self.inner.stream_users(filter);
+        stream::once(async move { chaos.before_store_call().await.map(|()| users) })
+            .try_flatten()
+            .boxed()
+    }
+
+    fn search_users<'a>(
+        &'a self,
+        query: &'a str,
+        terms: &'a [String],
+        similarity_threshold: f64,
+        limit: i64,
+    ) -> BoxFuture<'a, Result<Vec<UserSearchMatch>, sqlx::Error>> {
+        Box::pin(async move {
+            self.chaos.before_store_call().await?;
+            self.inner.search_users(query, terms, similarity_threshold, limit).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::memory_user_store::MemoryUserStore;
+
+    fn failing(error_rate: f64, drop_rate: f64) -> Faults {
+        Faults { latency: None, error_rate, drop_rate }
+    }
+
+    #[test]
+    fn test_latency_parsing() {
+        let fixed: Latency = "250".parse().unwrap();
+        assert_eq!((fixed.min, fixed.max), (Duration::from_millis(250), Duration::from_millis(250)));
+        assert_eq!(fixed.sample(), Duration::from_millis(250));
+
+        let range: Latency = "100-500".parse().unwrap();
+        assert_eq!((range.min, range.max), (Duration::from_millis(100), Duration::from_millis(500)));
+        for _ in 0..20 {
+            assert!((range.min..=range.max).contains(&range.sample()));
+        }
+
+        assert!("500-100".parse::<Latency>().is_err());
+        assert!("fast".parse::<Latency>().is_err());
+        assert!("-5".parse::<Latency>().is_err());
+    }
+
+    #[test]
+    fn test_latency_serde_round_trip() {
+        let rules: ChaosRules = serde_json::from_str(
+            r#"{"active": true, "http": {"latency": 250}, "store": {"latency": "100-500", "drop_rate": 0.5}}"#,
+        )
+        .unwrap();
+        assert_eq!(rules.http.latency, Some("250".parse().unwrap()));
+        assert_eq!(rules.store.latency, Some("100-500".parse().unwrap()));
+
+        let json = serde_json::to_value(&rules).unwrap();
+        assert_eq!(json["store"]["latency"], "100-500");
+        assert_eq!(serde_json::from_value::<ChaosRules>(json).unwrap(), rules);
+
+        assert!(serde_json::from_str::<Faults>(r#"{"latency": -5}"#).is_err());
+        assert!(serde_json::from_str::<Faults>(r#"{"error-rate": 0.5}"#).is_err());
+    }
+
+    #[test]
+    fn test_rate_parsing() {
+        assert_eq!(parse_rate("0.25"), Ok(0.25));
+        assert_eq!(parse_rate("1"), Ok(1.0));
+        assert!(parse_rate("1.5").is_err());
+        assert!(parse_rate("-0.1").is_err());
+        assert!(parse_rate("NaN").is_err());
+    }
+
+    #[test]
+    fn test_faults_pick() {
+        assert_eq!(Faults::default().pick(), None);
+        assert_eq!(failing(1.0, 0.0).pick(), Some(Fault::Error));
+        assert_eq!(failing(0.0, 1.0).pick(), Some(Fault::Drop));
+        for _ in 0..20 {
+            assert!(failing(0.5, 0.5).pick().is_some());
+        }
+    }
+
+    #[test]
+    fn test_path_matching() {
+        assert!(path_matches("/api/v1/users", "/api/v1/users"));
+        assert!(path_matches("/api/v1/users", "/api/v1/users/"));
+        assert!(!path_matches("/api/v1/users", "/api/v1/users/search"));
+        assert!(path_matches("/api/v1/users/{id}", "/api/v1/users/01890a5d-ac96-774b-bcce-b302099a8057"));
+        assert!(!path_matches("/api/v1/users/{id}", "/api/v1/users"));
+        assert!(path_matches("/api/v1/webhooks/*", "/api/v1/webhooks"));
+        assert!(path_matches("/api/v1/webhooks/*", "/api/v1/webhooks/dead-letters/1/replay"));
+        assert!(!path_matches("/api/v1/webhooks/*", "/api/v1/users"));
+        assert!(path_matches("/*", "/api/v1/users"));
+    }
+
+    #[test]
+    fn test_route_rules_take_precedence_in_order() {
+        let route = |method: Option<&str>, path: &str, error_rate| RouteFaults {
+            method: method.map(str::to_string),
+            path: path.to_string(),
+            faults: failing(error_rate, 0.0),
+        };
+        let chaos = Chaos::new(
+            ChaosRules {
+                active: true,
+                http: failing(0.1, 0.0),
+                store: Faults::default(),
+                routes: vec![
+                    route(Some("post"), "/api/v1/users", 0.2),
+                    route(None, "/api/v1/users/*", 0.3),
+                ],
+            },
+            None,
+        );
+
+        let error_rate = |method: Method, path: &str| chaos.http_faults(&method, path).unwrap().error_rate;
+        assert_eq!(error_rate(Method::POST, "/api/v1/users"), 0.2);
+        assert_eq!(error_rate(Method::GET, "/api/v1/users"), 0.3);
+        assert_eq!(error_rate(Method::GET, "/api/v1/webhooks"), 0.1);
+
+        chaos.set_active(false);
+        assert_eq!(chaos.http_faults(&Method::GET, "/api/v1/webhooks"), None);
+        assert_eq!(chaos.rules().routes.len(), 2);
+    }
+
+    #[test]
+    fn test_rules_problems() {
+        assert!(ChaosRules::default().problems().is_empty());
+
+        let rules = ChaosRules {
+            http: failing(0.7, 0.7),
+            store: failing(1.5, 0.0),
+            routes: vec![RouteFaults {
+                method: Some("GET POST".to_string()),
+                path: "api/v1/users".to_string(),
+                faults: Faults::default(),
+            }],
+            ..ChaosRules::default()
+        };
+        assert_eq!(
+            rules.problems(),
+            [
+                "http.error_rate and http.drop_rate must not add up to more than 1",
+                "store.error_rate must be between 0 and 1",
+                "store.error_rate and store.drop_rate must not add up to more than 1",
+                "routes[0].path must start with /",
+                "routes[0].method 'GET POST' is not an HTTP method",
+            ]
+        );
+
+        let chaos = Chaos::new(ChaosRules::default(), None);
+        assert!(chaos.set_rules(rules).is_err());
+        assert_eq!(chaos.rules(), ChaosRules::default());
+    }
+
+    #[test]
+    fn test_admin_token() {
+        let open = Chaos::new(ChaosRules::default(), None);
+        assert!(open.authorizes(None));
+
+        let guarded = Chaos::new(ChaosRules::default(), Some("secret".to_string()));
+        assert!(guarded.authorizes(Some("Bearer secret")));
+        assert!(!guarded.authorizes(Some("Bearer secret2")));
+        assert!(!guarded.authorizes(Some("secret")));
+        assert!(!guarded.authorizes(None));
+    }
+
+    #[tokio::test]
+    async fn test_store_faults() {
+        let chaos = Arc::new(Chaos::new(ChaosRules::default(), None));
+        let store = ChaosUserStore::new(Arc::new(MemoryUserStore::new()), chaos.clone());
+        let request = CreateUserRequest { email: "ada@example.com".to_string(), name: "Ada".to_string() };
+        let user = store.create_user(request).await.unwrap();
+
+        chaos
+            .set_rules(ChaosRules { active: true, store: failing(1.0, 0.0), ..ChaosRules::default() })
+            .unwrap();
+        assert!(matches!(store.get_user_by_id(user.id).await, Err(sqlx::Error::PoolTimedOut)));
+
+        chaos
+            .set_rules(ChaosRules { active: true, store: failing(0.0, 1.0), ..ChaosRules::default() })
+            .unwrap();
+        let streamed: Vec<_> = store.stream_users(&UserFilter::default()).collect().await;
+        assert!(matches!(streamed.as_slice(), [Err(sqlx::Error::Io(_))]));
+
+        chaos.set_active(false);
+        assert_eq!(store.get_user_by_id(user.id).await.unwrap().unwrap().email, "ada@example.com");
+        assert_eq!(store.stream_users(&UserFilter::default()).collect::<Vec<_>>().await.len(), 1);
+    }
+}
@@ -0,0 +1,620 @@
+//! Command line interface of the `tangy-mango` binary.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use uuid::Uuid;
+use crate::config::Settings;
+use crate::dao::user_dao::UserDao;
+use crate::db::{self, DbPool, MigrationState, MigrationStatus};
+use crate::chaos::{parse_rate, Faults, Latency};
+use crate::models::user::{CreateUserRequest, UserResponse};
+use crate::models::user_export::UserFilter;
+use crate::models::user_import::{ImportFormat, ImportMode, ImportOptions, ImportReport};
+use crate::services::user_import_service::UserImportService;
+use crate::services::user_seeder::{Locale, SeedOptions, SeedReport, UserSeeder};
+use crate::services::user_service::UserService;
+use crate::tls::{self, CertReloader};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Parser)]
+#[command(name = "tangy-mango", version, about = "User management service")]
+pub struct Cli {
+    /// Defaults to `serve` when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the HTTP server, applying pending migrations first
+    Serve(ServeArgs),
+    /// Bulk-import users from a CSV or NDJSON file
+    Import(ImportArgs),
+    /// Inspect and apply or revert database migrations
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Manage individual users
+    User {
+        #[command(subcommand)]
+        action: UserAction,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Fill the database with sample users for development
+    Seed(SeedArgs),
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ServeArgs {
+    /// Start without migrating, e.g. on replicas when migrations run as a separate deploy step
+    #[arg(long)]
+    pub no_migrate: bool,
+    /// Serve users from memory, without Postgres; routes that need it answer 503
+    #[arg(long, conflicts_with = "no_migrate")]
+    pub mock: bool,
+    /// JSON array of users to start the mock store with, such as saved GET /api/v1/users output;
+    /// 50 generated users without it
+    #[arg(long, value_name = "FILE", requires = "mock")]
+    pub mock_data: Option<PathBuf>,
+    /// Delay every mock response by this many milliseconds, fixed or as a MIN-MAX range
+    #[arg(long, value_name = "MS", requires = "mock")]
+    pub mock_latency: Option<Latency>,
+    /// Answer this share of mock requests, from 0 to 1, with a 500
+    #[arg(long, value_name = "RATE", requires = "mock", default_value_t = 0.0, value_parser = parse_rate)]
+    pub mock_error_rate: f64,
+}
+
+impl ServeArgs {
+    pub fn mock_faults(&self) -> Faults {
+        Faults {
+            latency: self.mock_latency,
+            error_rate: self.mock_error_rate,
+            drop_rate: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateAction {
+    /// List migrations and whether each is applied
+    Status,
+    /// Apply all pending migrations
+    Up,
+    /// Revert the most recently applied migrations
+    Down {
+        /// Number of migrations to revert
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+    /// Revert the latest migration and apply it again
+    Redo,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UserAction {
+    /// Create a user and print it
+    Create {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        name: String,
+    },
+    /// Print a user, looked up by id or email
+    Get {
+        id_or_email: String,
+    },
+    /// List users, oldest first
+    List {
+        /// Only users whose email contains this text, ignoring case
+        #[arg(long)]
+        email: Option<String>,
+        /// Only users whose name contains this text, ignoring case
+        #[arg(long)]
+        name: Option<String>,
+        /// Maximum number of users to print
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+    /// Suspend a user, or lift the suspension with --lift
+    Suspend {
+        id: Uuid,
+        #[arg(long)]
+        lift: bool,
+    },
+    /// Permanently delete a user
+    Delete {
+        id: Uuid,
+        /// Confirm the deletion; required, since it cannot be undone
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Validate Config.toml and check that the database is reachable and migrated
+    Check,
+}
+
+#[derive(Debug, Args)]
+pub struct SeedArgs {
+    /// Number of users to generate
+    #[arg(long, default_value_t = 50)]
+    pub count: usize,
+    /// Random seed; the same seed and --until always produce the same users
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+    /// Comma-separated locales for names, e.g. en_US,de_DE (all when omitted)
+    #[arg(long, value_delimiter = ',', value_parser = parse_locale)]
+    pub locale: Vec<Locale>,
+    /// Spread creation times over this many days
+    #[arg(long, default_value_t = 365)]
+    pub days: u32,
+    /// Latest creation time, RFC 3339 (defaults to now)
+    #[arg(long)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+fn parse_locale(value: &str) -> Result<Locale, String> {
+    Locale::parse(value).ok_or_else(|| {
+        let known: Vec<&str> = Locale::ALL.iter().map(Locale::as_str).collect();
+        format!("expected one of {}", known.join(", "))
+    })
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// File to import, or `-` to read standard input
+    pub path: String,
+    /// Input format: csv or ndjson (inferred from the file extension when omitted)
+    #[arg(long, value_parser = parse_format)]
+    pub format: Option<ImportFormat>,
+    /// all-or-nothing rolls back on any failed row; per-row skips failed rows
+    #[arg(long, value_parser = parse_mode, default_value = "all-or-nothing")]
+    pub mode: ImportMode,
+    /// Validate and report without writing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+fn parse_format(value: &str) -> Result<ImportFormat, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "csv" => Ok(ImportFormat::Csv),
+        "ndjson" | "jsonl" => Ok(ImportFormat::Ndjson),
+        _ => Err("expected csv or ndjson".to_string()),
+    }
+}
+
+fn parse_mode(value: &str) -> Result<ImportMode, String> {
+    match value.to_ascii_lowercase().replace('_', "-").as_str() {
+        "all-or-nothing" => Ok(ImportMode::AllOrNothing),
+        "per-row" => Ok(ImportMode::PerRow),
+        _ => Err("expected all-or-nothing or per-row".to_string()),
+    }
+}
+
+/// Runs `import`, streaming the file through the same service as the HTTP endpoint.
+pub async fn run_import(settings: &Settings, pool: DbPool, args: &ImportArgs) -> anyhow::Result<ImportReport> {
+    let format = match args.format {
+        Some(format) => format,
+        None => ImportFormat::from_path(&args.path)
+            .ok_or_else(|| anyhow!("Cannot infer the format of {}; pass --format", args.path))?,
+    };
+
+    let reader: Box<dyn AsyncRead + Unpin> = if args.path == "-" {
+        Box::new(tokio::io::stdin())
+    } else {
+        Box::new(
+            tokio::fs::File::open(&args.path)
+                .await
+                .with_context(|| format!("Failed to open {}", args.path))?,
+        )
+    };
+
+    let options = ImportOptions {
+        mode: args.mode,
+        dry_run: args.dry_run,
+    };
+    let service = UserImportService::new(UserDao::new(pool), settings.import.clone());
+
+    Ok(service.import(format, options, read_chunks(reader)).await?)
+}
+
+/// Runs `migrate`, printing what was done to standard output.
+pub async fn run_migrate(pool: &DbPool, action: &MigrateAction) -> anyhow::Result<()> {
+    match action {
+        MigrateAction::Status => {
+            let statuses = db::migration_status(pool).await?;
+            print!("{}", format_status(&statuses));
+        }
+        MigrateAction::Up => migrate_up(pool).await?,
+        MigrateAction::Down { steps } => migrate_down(pool, *steps).await?,
+        MigrateAction::Redo => {
+            // `up` applies everything pending, so redo would otherwise do more than it says
+            let statuses = db::migration_status(pool).await?;
+            if statuses.iter().any(|status| status.state == MigrationState::Pending) {
+                bail!("There are pending migrations; run `migrate up` first");
+            }
+            migrate_down(pool, 1).await?;
+            migrate_up(pool).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs `user`, printing the affected users to standard output.
+pub async fn run_user(pool: DbPool, action: &UserAction) -> anyhow::Result<()> {
+    let service = UserService::new(Arc::new(UserDao::new(pool)));
+
+    match action {
+        UserAction::Create { email, name } => {
+            let request = CreateUserRequest {
+                email: email.clone(),
+                name: name.clone(),
+            };
+            let user = service.create_user(request).await?;
+            print_user(&user)?;
+        }
+        UserAction::Get { id_or_email } => {
+            let user = match Uuid::parse_str(id_or_email) {
+                Ok(id) => service.get_user_by_id(id).await?.ok_or_else(|| anyhow!("User not found"))?,
+                Err(_) => service.get_user_by_email(id_or_email).await?,
+            };
+            print_user(&user)?;
+        }
+        UserAction::List { email, name, limit } => {
+            let filter = UserFilter {
+                email_contains: email.clone(),
+                name_contains: name.clone(),
+                ..UserFilter::default()
+            };
+            let users: Vec<UserResponse> = service.export_users(&filter)?.take(*limit).try_collect().await?;
+            print!("{}", format_user_table(&users));
+        }
+        UserAction::Suspend { id, lift } => {
+            let user = service.set_suspended(*id, !lift).await?;
+            print_user(&user)?;
+        }
+        UserAction::Delete { id, yes } => {
+            if !yes {
+                bail!("Deleting a user cannot be undone; pass --yes to confirm");
+            }
+            let user = service.delete_user(*id).await?;
+            println!("Deleted user {} ({})", user.id, user.email);
+        }
+    }
+    Ok(())
+}
+
+/// Runs `config check`, printing each problem found. Fails if there was any.
+pub async fn run_config(settings: &Settings, action: &ConfigAction) -> anyhow::Result<()> {
+    match action {
+        ConfigAction::Check => {
+            let mut problems = settings.problems();
+
+            // Missing paths are already reported; anything else must load as `serve` would load it
+            let tls_configured = !problems.iter().any(|problem| problem.starts_with("server.tls."));
+            if let Some(config) = settings.server.tls.as_ref().filter(|_| tls_configured) {
+                let loaded = CertReloader::load(config).and_then(|certs| tls::server_config(config, certs));
+                if let Err(err) = loaded {
+                    problems.push(format!("server.tls: {:#}", err));
+                }
+            }
+
+            match db::create_pool(settings).await {
+                Ok(pool) => {
+                    let statuses = db::migration_status(&pool).await?;
+                    let pending = statuses
+                        .iter()
+                        .filter(|status| status.state == MigrationState::Pending)
+                        .count();
+                    if pending > 0 {
+                        problems.push(format!("{} pending migrations; run `migrate up`", pending));
+                    }
+                    pool.close().await;
+                }
+                Err(err) => problems.push(format!(
+                    "Cannot connect to database {} at {}:{}: {}",
+                    settings.database.database_name, settings.database.host, settings.database.port, err
+                )),
+            }
+
+            if problems.is_empty() {
+                println!("Configuration OK");
+                return Ok(());
+            }
+            for problem in &problems {
+                println!("- {}", problem);
+            }
+            bail!("Configuration check failed");
+        }
+    }
+}
+
+/// Runs `seed`, generating users deterministically and inserting those that do not exist yet.
+pub async fn run_seed(pool: DbPool, args: &SeedArgs) -> anyhow::Result<SeedReport> {
+    let mut options = SeedOptions::new(args.count, args.seed, args.until.unwrap_or_else(Utc::now));
+    options.days = args.days;
+    if !args.locale.is_empty() {
+        options.locales = args.locale.clone();
+    }
+
+    Ok(UserSeeder::new(UserDao::new(pool)).seed(&options).await?)
+}
+
+fn print_user(user: &UserResponse) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(user)?);
+    Ok(())
+}
+
+fn format_user_table(users: &[UserResponse]) -> String {
+    let mut output = String::new();
+    for user in users {
+        let status = match user.suspended_at {
+            Some(at) => format!("suspended {}", at.to_rfc3339()),
+            None => "active".to_string(),
+        };
+        output.push_str(&format!("{}  {:<32} {:<24} {}\n", user.id, user.email, user.name, status));
+    }
+    output
+}
+
+async fn migrate_up(pool: &DbPool) -> anyhow::Result<()> {
+    let pending: Vec<i64> = db::migration_status(pool)
+        .await?
+        .into_iter()
+        .filter(|status| status.state == MigrationState::Pending)
+        .map(|status| status.version)
+        .collect();
+
+    db::run_migrations(pool).await.context("Failed to apply migrations")?;
+    if pending.is_empty() {
+        println!("Already up to date");
+    }
+    for version in pending {
+        println!("Applied {}", version);
+    }
+    Ok(())
+}
+
+async fn migrate_down(pool: &DbPool, steps: usize) -> anyhow::Result<()> {
+    let reverted = db::revert_migrations(pool, steps)
+        .await
+        .context("Failed to revert migrations")?;
+    if reverted.is_empty() {
+        println!("Nothing to revert");
+    }
+    for version in reverted {
+        println!("Reverted {}", version);
+    }
+    Ok(())
+}
+
+fn format_status(statuses: &[MigrationStatus]) -> String {
+    let mut output = String::new();
+    for status in statuses {
+        let state = match &status.state {
+            MigrationState::Pending => "pending".to_string(),
+            MigrationState::Applied { installed_on } => format!("applied {}", installed_on.to_rfc3339()),
+            MigrationState::Modified { installed_on } => {
+                format!("applied {} (script changed since)", installed_on.to_rfc3339())
+            }
+            MigrationState::Failed => "failed (needs manual repair)".to_string(),
+            MigrationState::Unknown { installed_on } => {
+                format!("applied {} (not in this build)", installed_on.to_rfc3339())
+            }
+        };
+        let irreversible = if status.reversible { "" } else { " [irreversible]" };
+        output.push_str(&format!("{:>4}  {:<32} {}{}\n", status.version, status.description, state, irreversible));
+    }
+    output
+}
+
+/// Adapts a reader into the chunk stream the import service consumes.
+fn read_chunks<R>(reader: R) -> impl futures_util::Stream<Item = std::io::Result<Vec<u8>>> + Unpin
+where
+    R: AsyncRead + Unpin,
+{
+    Box::pin(stream::try_unfold(reader, |mut reader| async move {
+        let mut chunk = vec![0; READ_CHUNK_SIZE];
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((chunk, reader)))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    #[test]
+    fn test_serve_is_the_default_command() {
+        let cli = Cli::try_parse_from(["tangy-mango"]).unwrap();
+        assert!(cli.command.is_none());
+
+        let cli = Cli::try_parse_from(["tangy-mango", "serve"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Serve(ServeArgs { no_migrate: false, mock: false, .. }))));
+
+        let cli = Cli::try_parse_from(["tangy-mango", "serve", "--no-migrate"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Serve(ServeArgs { no_migrate: true, .. }))));
+    }
+
+    #[test]
+    fn test_mock_arguments() {
+        let cli = Cli::try_parse_from([
+            "tangy-mango", "serve", "--mock", "--mock-data", "users.json", "--mock-latency", "100-300",
+            "--mock-error-rate", "0.1",
+        ])
+        .unwrap();
+        let Some(Command::Serve(args)) = cli.command else {
+            panic!("expected serve");
+        };
+        assert!(args.mock);
+        assert_eq!(args.mock_data, Some(PathBuf::from("users.json")));
+        assert_eq!(args.mock_latency, Some("100-300".parse().unwrap()));
+        assert_eq!(args.mock_error_rate, 0.1);
+        assert_eq!(args.mock_faults(), Faults { latency: args.mock_latency, error_rate: 0.1, drop_rate: 0.0 });
+
+        assert!(Cli::try_parse_from(["tangy-mango", "serve", "--mock-latency", "100"]).is_err());
+        assert!(Cli::try_parse_from(["tangy-mango", "serve", "--mock", "--no-migrate"]).is_err());
+        assert!(Cli::try_parse_from(["tangy-mango", "serve", "--mock", "--mock-error-rate", "2"]).is_err());
+        assert!(Cli::try_parse_from(["tangy-mango", "serve", "--mock", "--mock-latency", "9-1"]).is_err());
+    }
+
+    #[test]
+    fn test_migrate_arguments() {
+        let cli = Cli::try_parse_from(["tangy-mango", "migrate", "down"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Migrate { action: MigrateAction::Down { steps: 1 } })));
+
+        let cli = Cli::try_parse_from(["tangy-mango", "migrate", "down", "--steps", "3"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Migrate { action: MigrateAction::Down { steps: 3 } })));
+
+        assert!(Cli::try_parse_from(["tangy-mango", "migrate"]).is_err());
+        assert!(Cli::try_parse_from(["tangy-mango", "migrate", "sideways"]).is_err());
+    }
+
+    #[test]
+    fn test_format_status() {
+        let installed_on = chrono::TimeZone::with_ymd_and_hms(&chrono::Utc, 2024, 1, 15, 10, 30, 0).unwrap();
+        let statuses = [
+            MigrationStatus {
+                version: 1,
+                description: "create users table".to_string(),
+                reversible: true,
+                state: MigrationState::Applied { installed_on },
+            },
+            MigrationStatus {
+                version: 2,
+                description: "create webhooks".to_string(),
+                reversible: false,
+                state: MigrationState::Pending,
+            },
+        ];
+
+        let output = format_status(&statuses);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("   1  create users table"));
+        assert!(lines[0].ends_with("applied 2024-01-15T10:30:00+00:00"));
+        assert!(lines[1].ends_with("pending [irreversible]"));
+    }
+
+    #[test]
+    fn test_user_arguments() {
+        let cli = Cli::try_parse_from([
+            "tangy-mango", "user", "create", "--email", "ada@example.com", "--name", "Ada",
+        ])
+        .unwrap();
+        assert!(matches!(cli.command, Some(Command::User { action: UserAction::Create { .. } })));
+
+        let cli = Cli::try_parse_from(["tangy-mango", "user", "list"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::User { action: UserAction::List { limit: 100, email: None, name: None } })
+        ));
+
+        let id = Uuid::new_v4().to_string();
+        let cli = Cli::try_parse_from(["tangy-mango", "user", "suspend", &id, "--lift"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::User { action: UserAction::Suspend { lift: true, .. } })));
+
+        let cli = Cli::try_parse_from(["tangy-mango", "user", "delete", &id]).unwrap();
+        assert!(matches!(cli.command, Some(Command::User { action: UserAction::Delete { yes: false, .. } })));
+
+        assert!(Cli::try_parse_from(["tangy-mango", "user", "delete", "not-a-uuid"]).is_err());
+        assert!(Cli::try_parse_from(["tangy-mango", "user", "create", "--email", "ada@example.com"]).is_err());
+    }
+
+    #[test]
+    fn test_config_and_seed_arguments() {
+        let cli = Cli::try_parse_from(["tangy-mango", "config", "check"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Config { action: ConfigAction::Check })));
+
+        let cli = Cli::try_parse_from(["tangy-mango", "seed"]).unwrap();
+        let Some(Command::Seed(args)) = cli.command else {
+            panic!("expected seed command");
+        };
+        assert_eq!((args.count, args.seed, args.days), (50, 1, 365));
+        assert!(args.locale.is_empty());
+        assert!(args.until.is_none());
+
+        let cli = Cli::try_parse_from([
+            "tangy-mango", "seed", "--count", "5", "--locale", "de_DE,fr", "--until", "2024-06-01T00:00:00Z",
+        ])
+        .unwrap();
+        let Some(Command::Seed(args)) = cli.command else {
+            panic!("expected seed command");
+        };
+        assert_eq!(args.locale, vec![Locale::DeDe, Locale::FrFr]);
+        assert_eq!(args.until.unwrap().to_rfc3339(), "2024-06-01T00:00:00+00:00");
+
+        assert!(Cli::try_parse_from(["tangy-mango", "seed", "--locale", "xx"]).is_err());
+    }
+
+    #[test]
+    fn test_format_user_table() {
+        let created_at = chrono::TimeZone::with_ymd_and_hms(&chrono::Utc, 2024, 1, 15, 10, 30, 0).unwrap();
+        let user = |suspended_at| UserResponse {
+            id: Uuid::nil(),
+            email: "ada@example.com".to_string(),
+            name: "Ada".to_string(),
+            version: 1,
+            created_at,
+            updated_at: created_at,
+            suspended_at,
+        };
+
+        let output = format_user_table(&[user(None), user(Some(created_at))]);
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("00000000-0000-0000-0000-000000000000  ada@example.com"));
+        assert!(lines[0].ends_with("active"));
+        assert!(lines[1].ends_with("suspended 2024-01-15T10:30:00+00:00"));
+    }
+
+    #[test]
+    fn test_import_arguments() {
+        let cli = Cli::try_parse_from(["tangy-mango", "import", "users.csv"]).unwrap();
+        let Some(Command::Import(args)) = cli.command else {
+            panic!("expected import command");
+        };
+        assert_eq!(args.path, "users.csv");
+        assert_eq!(args.format, None);
+        assert_eq!(args.mode, ImportMode::AllOrNothing);
+        assert!(!args.dry_run);
+
+        let cli = Cli::try_parse_from([
+            "tangy-mango", "import", "-", "--format", "ndjson", "--mode", "per-row", "--dry-run",
+        ])
+        .unwrap();
+        let Some(Command::Import(args)) = cli.command else {
+            panic!("expected import command");
+        };
+        assert_eq!(args.format, Some(ImportFormat::Ndjson));
+        assert_eq!(args.mode, ImportMode::PerRow);
+        assert!(args.dry_run);
+
+        assert!(Cli::try_parse_from(["tangy-mango", "import", "users.csv", "--mode", "some"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_chunks_yields_whole_input() {
+        let input: &[u8] = b"email,name\nada@example.com,Ada\n";
+        let chunks: Vec<Vec<u8>> = read_chunks(input).map(Result::unwrap).collect().await;
+        assert_eq!(chunks.concat(), input);
+    }
+}
@@ -1,16 +1,73 @@
+use std::path::PathBuf;
+
+use actix_web::http::header::HeaderName;
 use config::{Config, ConfigError, File};
 use serde::Deserialize;
+use crate::chaos::ChaosRules;
+use crate::rate_limit::{RateLimitKey, RateLimitRule, RateLimitStoreKind};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Settings {
     pub server: ServerConfig,
     pub database: DatabaseConfig,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    #[serde(default)]
+    pub event_stream: EventStreamConfig,
+    #[serde(default)]
+    pub idempotency: IdempotencyConfig,
+    #[serde(default)]
+    pub import: ImportConfig,
+    #[serde(default)]
+    pub search: SearchConfig,
+    #[serde(default)]
+    pub email_change: EmailChangeConfig,
+    #[serde(default)]
+    pub leader_election: LeaderElectionConfig,
+    #[serde(default)]
+    pub chaos: ChaosConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub load_shedding: LoadSheddingConfig,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Serves HTTPS instead of plain HTTP when set
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert_path: PathBuf,
+    /// PEM private key for the leaf certificate, PKCS#8, PKCS#1 or SEC1
+    pub key_path: PathBuf,
+    /// PEM bundle of CAs whose client certificates are accepted; without it none are requested
+    pub client_ca_path: Option<PathBuf>,
+    /// Refuses connections without a valid client certificate instead of serving them anonymously
+    pub require_client_cert: bool,
+    /// How often the certificate and key files are checked for changes
+    pub reload_interval_secs: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert_path: PathBuf::new(),
+            key_path: PathBuf::new(),
+            client_ca_path: None,
+            require_client_cert: false,
+            reload_interval_secs: 10,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -23,6 +80,242 @@ pub struct DatabaseConfig {
     pub max_connections: u32,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub max_attempts: u32,
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    pub request_timeout_secs: u64,
+    pub poll_interval_ms: u64,
+    pub batch_size: i64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            initial_backoff_secs: 10,
+            max_backoff_secs: 3600,
+            request_timeout_secs: 10,
+            poll_interval_ms: 1000,
+            batch_size: 50,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct EventStreamConfig {
+    pub keep_alive_secs: u64,
+    pub retry_ms: u64,
+    pub replay_batch_size: i64,
+    pub channel_capacity: usize,
+}
+
+impl Default for EventStreamConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive_secs: 15,
+            retry_ms: 3000,
+            replay_batch_size: 500,
+            channel_capacity: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    pub ttl_hours: u64,
+    pub cleanup_interval_secs: u64,
+    /// How long a request may hold its key unfinished before a retry takes it over, for
+    /// requests that died between claiming and completing it
+    pub in_progress_timeout_secs: u64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            ttl_hours: 24,
+            cleanup_interval_secs: 3600,
+            in_progress_timeout_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ImportConfig {
+    pub batch_size: usize,
+    pub max_reported_errors: usize,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 1000,
+            max_reported_errors: 100,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct SearchConfig {
+    pub default_limit: i64,
+    pub max_limit: i64,
+    /// Minimum pg_trgm word similarity (0 to 1) for a typo-tolerant match
+    pub similarity_threshold: f64,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            default_limit: 20,
+            max_limit: 100,
+            similarity_threshold: 0.4,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct EmailChangeConfig {
+    /// How long the confirmation link sent to the new address stays valid
+    pub confirm_ttl_minutes: i64,
+    /// How long the old address can undo the change, whether confirmed or not
+    pub revert_ttl_hours: i64,
+    /// Base URL of the pages the emailed links open; they post the token back to the API
+    pub link_base_url: String,
+}
+
+impl Default for EmailChangeConfig {
+    fn default() -> Self {
+        Self {
+            confirm_ttl_minutes: 60,
+            revert_ttl_hours: 168,
+            link_base_url: "http://localhost:8080".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct LeaderElectionConfig {
+    /// How often followers retry for leadership and the leader checks it still holds it
+    pub check_interval_secs: u64,
+}
+
+impl Default for LeaderElectionConfig {
+    fn default() -> Self {
+        Self { check_interval_secs: 5 }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// How long readiness fails before the server stops accepting connections, for load
+    /// balancers to stop routing here
+    pub readiness_grace_secs: u64,
+    /// How long in-flight requests get to finish before their connections are closed
+    pub drain_timeout_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            readiness_grace_secs: 5,
+            drain_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// `memory` counts per instance; `postgres` shares buckets between instances
+    pub store: RateLimitStoreKind,
+    /// Identifies clients by `X-Forwarded-For`/`Forwarded` instead of the peer address; only
+    /// safe behind a proxy that sets them
+    pub trust_forwarded_for: bool,
+    /// Header carrying the API key for rules keyed by `api_key`
+    pub api_key_header: String,
+    /// API keys counted on their own; requests with any other key are counted by IP
+    pub api_keys: Vec<String>,
+    /// How often full buckets are deleted from Postgres
+    pub cleanup_interval_secs: u64,
+    /// Connections of the `postgres` store's own pool, kept apart from the handlers' pool
+    pub pool_max_connections: u32,
+    /// Wait for a `postgres` store connection before letting the request through unlimited
+    pub pool_acquire_timeout_ms: u64,
+    /// Quotas by route; the first match wins and unmatched routes are not limited
+    pub routes: Vec<RateLimitRule>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            store: RateLimitStoreKind::Memory,
+            trust_forwarded_for: false,
+            api_key_header: "X-Api-Key".to_string(),
+            api_keys: Vec::new(),
+            cleanup_interval_secs: 300,
+            pool_max_connections: 2,
+            pool_acquire_timeout_ms: 100,
+            routes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct LoadSheddingConfig {
+    /// Refuses requests with 503 under pool pressure or beyond a route's concurrency limit
+    pub enabled: bool,
+    /// Pool wait beyond which the pool is under pressure
+    pub max_pool_wait_ms: u64,
+    /// How often pool wait is measured
+    pub pool_sample_interval_ms: u64,
+    /// `Retry-After` on refused requests
+    pub retry_after_secs: u64,
+    /// Concurrent requests each route starts out allowed
+    pub initial_limit: u32,
+    pub min_limit: u32,
+    pub max_limit: u32,
+    /// Requests slower than this shrink their route's limit
+    pub latency_target_ms: u64,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_pool_wait_ms: 100,
+            pool_sample_interval_ms: 250,
+            retry_after_secs: 1,
+            initial_limit: 20,
+            min_limit: 1,
+            max_limit: 200,
+            latency_target_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ChaosConfig {
+    /// Mounts the fault-injection layer and /admin/chaos; for staging and tests, never production
+    pub enabled: bool,
+    /// Bearer token /admin/chaos requires; must be set when enabled
+    pub admin_token: Option<String>,
+    /// Faults to start with, injected only once `active`
+    pub rules: ChaosRules,
+}
+
 impl Settings {
     pub fn new() -> Result<Self, ConfigError> {
         let s = Config::builder()
@@ -32,6 +325,99 @@ impl Settings {
         s.try_deserialize()
     }
 
+    /// Values that deserialize fine but would fail or misbehave at runtime, one message each.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut require = |ok: bool, message: &str| {
+            if !ok {
+                problems.push(message.to_string());
+            }
+        };
+
+        if let Some(tls) = &self.server.tls {
+            require(!tls.cert_path.as_os_str().is_empty(), "server.tls.cert_path must be set");
+            require(!tls.key_path.as_os_str().is_empty(), "server.tls.key_path must be set");
+            require(
+                !tls.require_client_cert || tls.client_ca_path.is_some(),
+                "server.tls.client_ca_path must be set when server.tls.require_client_cert is true",
+            );
+            require(tls.reload_interval_secs > 0, "server.tls.reload_interval_secs must be at least 1");
+        }
+        require(self.database.max_connections > 0, "database.max_connections must be at least 1");
+        require(self.webhooks.max_attempts > 0, "webhooks.max_attempts must be at least 1");
+        require(
+            self.webhooks.initial_backoff_secs <= self.webhooks.max_backoff_secs,
+            "webhooks.initial_backoff_secs must not exceed webhooks.max_backoff_secs",
+        );
+        require(self.webhooks.poll_interval_ms > 0, "webhooks.poll_interval_ms must be at least 1");
+        require(self.webhooks.batch_size > 0, "webhooks.batch_size must be at least 1");
+        require(self.event_stream.replay_batch_size > 0, "event_stream.replay_batch_size must be at least 1");
+        require(self.event_stream.channel_capacity > 0, "event_stream.channel_capacity must be at least 1");
+        require(self.idempotency.cleanup_interval_secs > 0, "idempotency.cleanup_interval_secs must be at least 1");
+        require(
+            self.idempotency.in_progress_timeout_secs > 0,
+            "idempotency.in_progress_timeout_secs must be at least 1",
+        );
+        require(self.import.batch_size > 0, "import.batch_size must be at least 1");
+        require(self.search.default_limit > 0, "search.default_limit must be at least 1");
+        require(
+            self.search.default_limit <= self.search.max_limit,
+            "search.default_limit must not exceed search.max_limit",
+        );
+        require(
+            (0.0..=1.0).contains(&self.search.similarity_threshold),
+            "search.similarity_threshold must be between 0 and 1",
+        );
+        require(self.email_change.confirm_ttl_minutes > 0, "email_change.confirm_ttl_minutes must be at least 1");
+        require(self.email_change.revert_ttl_hours > 0, "email_change.revert_ttl_hours must be at least 1");
+        require(
+            self.email_change.link_base_url.starts_with("http://")
+                || self.email_change.link_base_url.starts_with("https://"),
+            "email_change.link_base_url must be an http or https URL",
+        );
+        require(
+            self.leader_election.check_interval_secs > 0,
+            "leader_election.check_interval_secs must be at least 1",
+        );
+        require(
+            !self.chaos.enabled || self.chaos.admin_token.as_deref().is_some_and(|token| !token.is_empty()),
+            "chaos.admin_token must be set when chaos.enabled is true",
+        );
+        require(
+            self.rate_limit.cleanup_interval_secs > 0,
+            "rate_limit.cleanup_interval_secs must be at least 1",
+        );
+        require(self.rate_limit.pool_max_connections > 0, "rate_limit.pool_max_connections must be at least 1");
+        require(self.rate_limit.pool_acquire_timeout_ms > 0, "rate_limit.pool_acquire_timeout_ms must be at least 1");
+        require(
+            HeaderName::from_bytes(self.rate_limit.api_key_header.as_bytes()).is_ok(),
+            "rate_limit.api_key_header must be an HTTP header name",
+        );
+        require(
+            !self.rate_limit.routes.iter().any(|rule| rule.key == RateLimitKey::ApiKey)
+                || !self.rate_limit.api_keys.is_empty(),
+            "rate_limit.api_keys must list the known keys when a route is keyed by api_key",
+        );
+        require(self.load_shedding.max_pool_wait_ms > 0, "load_shedding.max_pool_wait_ms must be at least 1");
+        require(
+            self.load_shedding.pool_sample_interval_ms > 0,
+            "load_shedding.pool_sample_interval_ms must be at least 1",
+        );
+        require(self.load_shedding.min_limit > 0, "load_shedding.min_limit must be at least 1");
+        require(
+            self.load_shedding.min_limit <= self.load_shedding.initial_limit
+                && self.load_shedding.initial_limit <= self.load_shedding.max_limit,
+            "load_shedding.initial_limit must be between load_shedding.min_limit and load_shedding.max_limit",
+        );
+        require(self.load_shedding.latency_target_ms > 0, "load_shedding.latency_target_ms must be at least 1");
+        problems.extend(self.chaos.rules.problems().into_iter().map(|problem| format!("chaos.rules.{}", problem)));
+        for (index, rule) in self.rate_limit.routes.iter().enumerate() {
+            problems.extend(rule.problems().into_iter().map(|problem| format!("rate_limit.routes[{}].{}", index, problem)));
+        }
+
+        problems
+    }
+
     pub fn database_url(&self) -> String {
         format!(
             "postgres://{}:{}@{}:{}/{}",
@@ -53,6 +439,7 @@ mod tests {
             server: ServerConfig {
                 host: "127.0.0.1".to_string(),
                 port: 8080,
+                tls: None,
             },
             database: DatabaseConfig {
                 host: "localhost".to_string(),
@@ -62,6 +449,17 @@ mod tests {
                 database_name: "test_db".to_string(),
                 max_connections: 10,
             },
+            webhooks: WebhookConfig::default(),
+            event_stream: EventStreamConfig::default(),
+            idempotency: IdempotencyConfig::default(),
+            import: ImportConfig::default(),
+            search: SearchConfig::default(),
+            email_change: EmailChangeConfig::default(),
+            leader_election: LeaderElectionConfig::default(),
+            chaos: ChaosConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            load_shedding: LoadSheddingConfig::default(),
         }
     }
 
@@ -70,6 +468,7 @@ mod tests {
         let server_config = ServerConfig {
             host: "127.0.0.1".to_string(),
             port: 8080,
+            tls: None,
         };
 
         assert_eq!(server_config.host, "127.0.0.1");
@@ -113,6 +512,29 @@ mod tests {
         assert!(database_url.contains("p@ssw0rd!"));
     }
 
+    #[test]
+    fn test_default_settings_have_no_problems() {
+        assert!(create_test_settings().problems().is_empty());
+    }
+
+    #[test]
+    fn test_problems_name_the_offending_keys() {
+        let mut settings = create_test_settings();
+        settings.search.default_limit = 500;
+        settings.email_change.link_base_url = "localhost:8080".to_string();
+        settings.leader_election.check_interval_secs = 0;
+        settings.chaos.enabled = true;
+        settings.chaos.rules.store.error_rate = 2.0;
+
+        let problems = settings.problems();
+        assert_eq!(problems.len(), 6);
+        assert!(problems[0].starts_with("search.default_limit"));
+        assert!(problems[1].starts_with("email_change.link_base_url"));
+        assert!(problems[2].starts_with("leader_election.check_interval_secs"));
+        assert!(problems[3].starts_with("chaos.admin_token"));
+        assert!(problems[4].starts_with("chaos.rules.store.error_rate"));
+    }
+
     #[test]
     fn test_settings_clone() {
         let settings = create_test_settings();
@@ -124,6 +546,187 @@ mod tests {
         assert_eq!(settings.database.port, cloned_settings.database.port);
     }
 
+    #[test]
+    fn test_webhook_config_defaults_when_section_missing() {
+        let settings: Settings = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                [server]
+                host = "127.0.0.1"
+                port = 8080
+
+                [database]
+                host = "localhost"
+                port = 5432
+                username = "postgres"
+                password = "password"
+                database_name = "tangy_mango"
+                max_connections = 10
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert_eq!(settings.webhooks.max_attempts, 8);
+        assert_eq!(settings.webhooks.initial_backoff_secs, 10);
+        assert_eq!(settings.event_stream.keep_alive_secs, 15);
+        assert_eq!(settings.idempotency.ttl_hours, 24);
+        assert_eq!(settings.import.batch_size, 1000);
+        assert_eq!(settings.shutdown.drain_timeout_secs, 30);
+    }
+
+    #[test]
+    fn test_chaos_rules_from_toml() {
+        let settings: Settings = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                [server]
+                host = "127.0.0.1"
+                port = 8080
+
+                [database]
+                host = "localhost"
+                port = 5432
+                username = "postgres"
+                password = "password"
+                database_name = "tangy_mango"
+                max_connections = 10
+
+                [chaos]
+                enabled = true
+                admin_token = "change-me"
+
+                [chaos.rules]
+                http = { latency = "100-500", error_rate = 0.05 }
+                store = { latency = 250 }
+
+                [[chaos.rules.routes]]
+                method = "GET"
+                path = "/api/v1/users/{id}"
+                faults = { drop_rate = 0.5 }
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let rules = &settings.chaos.rules;
+        assert!(!rules.active);
+        assert_eq!(rules.http.latency, Some("100-500".parse().unwrap()));
+        assert_eq!(rules.http.error_rate, 0.05);
+        assert_eq!(rules.store.latency, Some("250".parse().unwrap()));
+        assert_eq!(rules.routes[0].path, "/api/v1/users/{id}");
+        assert_eq!(rules.routes[0].faults.drop_rate, 0.5);
+        assert!(settings.problems().is_empty());
+    }
+
+    #[test]
+    fn test_tls_config_from_toml() {
+        let settings: Settings = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                [server]
+                host = "127.0.0.1"
+                port = 8443
+
+                [server.tls]
+                cert_path = "/etc/tangy-mango/tls.crt"
+                key_path = "/etc/tangy-mango/tls.key"
+                require_client_cert = true
+
+                [database]
+                host = "localhost"
+                port = 5432
+                username = "postgres"
+                password = "password"
+                database_name = "tangy_mango"
+                max_connections = 10
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let tls = settings.server.tls.as_ref().unwrap();
+        assert_eq!(tls.cert_path, PathBuf::from("/etc/tangy-mango/tls.crt"));
+        assert_eq!(tls.reload_interval_secs, 10);
+        assert_eq!(
+            settings.problems(),
+            ["server.tls.client_ca_path must be set when server.tls.require_client_cert is true"]
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_config_from_toml() {
+        let settings: Settings = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                [server]
+                host = "127.0.0.1"
+                port = 8080
+
+                [database]
+                host = "localhost"
+                port = 5432
+                username = "postgres"
+                password = "password"
+                database_name = "tangy_mango"
+                max_connections = 10
+
+                [rate_limit]
+                enabled = true
+                store = "postgres"
+                api_keys = ["billing"]
+
+                [[rate_limit.routes]]
+                method = "POST"
+                path = "/api/v1/users"
+                key = "api_key"
+                requests = 10
+                window_secs = 60
+
+                [[rate_limit.routes]]
+                path = "/api/v1/*"
+                requests = 100
+                window_secs = 0
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let rate_limit = &settings.rate_limit;
+        assert_eq!(rate_limit.store, RateLimitStoreKind::Postgres);
+        assert_eq!(rate_limit.api_key_header, "X-Api-Key");
+        assert_eq!(rate_limit.routes[0].key, crate::rate_limit::RateLimitKey::ApiKey);
+        assert_eq!(rate_limit.routes[1].method, None);
+        assert_eq!(rate_limit.routes[1].key, crate::rate_limit::RateLimitKey::Ip);
+        assert_eq!(settings.problems(), ["rate_limit.routes[1].window_secs must be at least 1"]);
+    }
+
+    #[test]
+    fn test_load_shedding_limits_must_be_ordered() {
+        let mut settings = create_test_settings();
+        settings.load_shedding.initial_limit = 500;
+        settings.load_shedding.pool_sample_interval_ms = 0;
+        assert_eq!(
+            settings.problems(),
+            [
+                "load_shedding.pool_sample_interval_ms must be at least 1",
+                "load_shedding.initial_limit must be between load_shedding.min_limit and load_shedding.max_limit",
+            ]
+        );
+    }
+
     #[test]
     fn test_different_port_configurations() {
         let mut settings = create_test_settings();
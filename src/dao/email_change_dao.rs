@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgRow, PgPool, Postgres, Row, Transaction};
+use crate::clock::{Clock, SystemClock};
+use crate::ids::{IdGenerator, UuidV7Generator};
+use crate::models::email_change::{EmailChange, EmailChangeStatus};
+
+const EMAIL_CHANGE_COLUMNS: &str = "id, user_id, old_email, new_email, status, confirm_expires_at, \
+     revert_expires_at, created_at, completed_at";
+
+/// Hashed tokens and deadlines of a new email change.
+#[derive(Debug, Clone)]
+pub struct NewEmailChange<'a> {
+    pub user_id: Uuid,
+    pub old_email: &'a str,
+    pub new_email: &'a str,
+    pub confirm_token_hash: &'a str,
+    pub revert_token_hash: &'a str,
+    pub confirm_expires_at: DateTime<Utc>,
+    pub revert_expires_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct EmailChangeDao {
+    pool: PgPool,
+    clock: Arc<dyn Clock>,
+    ids: Arc<dyn IdGenerator>,
+}
+
+impl EmailChangeDao {
+    /// Uses the system clock and UUIDv7 ids.
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_clock_and_ids(pool, Arc::new(SystemClock), Arc::new(UuidV7Generator))
+    }
+
+    pub fn with_clock_and_ids(pool: PgPool, clock: Arc<dyn Clock>, ids: Arc<dyn IdGenerator>) -> Self {
+        Self { pool, clock, ids }
+    }
+
+    pub async fn begin(&self) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+        self.pool.begin().await
+    }
+
+    /// Stores a pending change, cancelling any change the user still had pending so that only
+    /// the most recently sent confirmation link works.
+    pub async fn create_change(&self, change: &NewEmailChange<'_>) -> Result<EmailChange, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let now = self.clock.now();
+
+        sqlx::query(
+            "UPDATE email_changes SET status = $2, completed_at = $3 WHERE user_id = $1 AND status = $4"
+        )
+        .bind(change.user_id)
+        .bind(EmailChangeStatus::Cancelled.as_str())
+        .bind(now)
+        .bind(EmailChangeStatus::Pending.as_str())
+        .execute(&mut *tx)
+        .await?;
+
+        let row = sqlx::query(&format!(
+            r#"
+            INSERT INTO email_changes (id, user_id, old_email, new_email, confirm_token_hash, revert_token_hash,
+                                       status, confirm_expires_at, revert_expires_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING {}
+            "#,
+            EMAIL_CHANGE_COLUMNS
+        ))
+        .bind(self.ids.new_id())
+        .bind(change.user_id)
+        .bind(change.old_email)
+        .bind(change.new_email)
+        .bind(change.confirm_token_hash)
+        .bind(change.revert_token_hash)
+        .bind(EmailChangeStatus::Pending.as_str())
+        .bind(change.confirm_expires_at)
+        .bind(change.revert_expires_at)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Self::map_change(&row))
+    }
+
+    pub async fn get_pending_change(&self, user_id: Uuid) -> Result<Option<EmailChange>, sqlx::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM email_changes WHERE user_id = $1 AND status = $2",
+            EMAIL_CHANGE_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(EmailChangeStatus::Pending.as_str())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(Self::map_change))
+    }
+
+    /// Finds the change a confirmation token was issued for, locking it until `tx` ends.
+    pub async fn lock_by_confirm_token(
+        tx: &mut Transaction<'_, Postgres>,
+        token_hash: &str,
+    ) -> Result<Option<EmailChange>, sqlx::Error> {
+        Self::lock_by(tx, "confirm_token_hash", token_hash).await
+    }
+
+    /// Finds the change a revert token was issued for, locking it until `tx` ends.
+    pub async fn lock_by_revert_token(
+        tx: &mut Transaction<'_, Postgres>,
+        token_hash: &str,
+    ) -> Result<Option<EmailChange>, sqlx::Error> {
+        Self::lock_by(tx, "revert_token_hash", token_hash).await
+    }
+
+    async fn lock_by(
+        tx: &mut Transaction<'_, Postgres>,
+        column: &'static str,
+        token_hash: &str,
+    ) -> Result<Option<EmailChange>, sqlx::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM email_changes WHERE {} = $1 FOR UPDATE",
+            EMAIL_CHANGE_COLUMNS, column
+        ))
+        .bind(token_hash)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        Ok(row.as_ref().map(Self::map_change))
+    }
+
+    /// Moves a change to a final status inside `tx`.
+    pub async fn complete(
+        tx: &mut Transaction<'_, Postgres>,
+        id: Uuid,
+        status: EmailChangeStatus,
+        completed_at: DateTime<Utc>,
+    ) -> Result<EmailChange, sqlx::Error> {
+        let row = sqlx::query(&format!(
+            "UPDATE email_changes SET status = $2, completed_at = $3 WHERE id = $1 RETURNING {}",
+            EMAIL_CHANGE_COLUMNS
+        ))
+        .bind(id)
+        .bind(status.as_str())
+        .bind(completed_at)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        Ok(Self::map_change(&row))
+    }
+
+    fn map_change(row: &PgRow) -> EmailChange {
+        let status: String = row.get("status");
+        EmailChange {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            old_email: row.get("old_email"),
+            new_email: row.get("new_email"),
+            status: EmailChangeStatus::parse(&status).unwrap_or(EmailChangeStatus::Pending),
+            confirm_expires_at: row.get("confirm_expires_at"),
+            revert_expires_at: row.get("revert_expires_at"),
+            created_at: row.get("created_at"),
+            completed_at: row.get("completed_at"),
+        }
+    }
+}
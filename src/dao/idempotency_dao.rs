@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{postgres::PgRow, PgPool, Row};
+use crate::clock::{Clock, SystemClock};
+use crate::models::idempotency::IdempotencyRecord;
+
+#[derive(Clone)]
+pub struct IdempotencyDao {
+    pool: PgPool,
+    clock: Arc<dyn Clock>,
+}
+
+impl IdempotencyDao {
+    /// Uses the system clock.
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_clock(pool, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(pool: PgPool, clock: Arc<dyn Clock>) -> Self {
+        Self { pool, clock }
+    }
+
+    /// Claims `key` for a new in-progress request. A key older than `ttl` is treated as free
+    /// and taken over, as is one still in progress after `in_progress_timeout`, whose request
+    /// died before completing or releasing it. Returns the claim's `created_at`, which
+    /// completing or releasing must name, or `None` if a live record holds the key.
+    pub async fn try_claim(
+        &self,
+        scope: &str,
+        key: &str,
+        fingerprint: &str,
+        ttl: Duration,
+        in_progress_timeout: Duration,
+    ) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        let now = self.clock.now();
+        sqlx::query_scalar(
+            r#"
+            INSERT INTO idempotency_keys (scope, key, fingerprint, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (scope, key) DO UPDATE
+            SET fingerprint = EXCLUDED.fingerprint,
+                status_code = NULL,
+                response_body = NULL,
+                created_at = EXCLUDED.created_at,
+                completed_at = NULL
+            WHERE idempotency_keys.created_at < $5
+               OR (idempotency_keys.completed_at IS NULL AND idempotency_keys.created_at < $6)
+            RETURNING created_at
+            "#
+        )
+        .bind(scope)
+        .bind(key)
+        .bind(fingerprint)
+        .bind(now)
+        .bind(now - ttl)
+        .bind(now - in_progress_timeout)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn get(&self, scope: &str, key: &str) -> Result<Option<IdempotencyRecord>, sqlx::Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT scope, key, fingerprint, status_code, response_body, created_at, completed_at
+            FROM idempotency_keys
+            WHERE scope = $1 AND key = $2
+            "#
+        )
+        .bind(scope)
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(Self::map_record))
+    }
+
+    /// Stores the response of the claim made at `claimed_at`. Returns `false`, storing nothing,
+    /// when a retry has taken the key over since.
+    pub async fn complete(
+        &self,
+        scope: &str,
+        key: &str,
+        claimed_at: DateTime<Utc>,
+        status_code: i16,
+        response_body: &serde_json::Value,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE idempotency_keys
+            SET status_code = $4, response_body = $5, completed_at = $6
+            WHERE scope = $1 AND key = $2 AND created_at = $3 AND completed_at IS NULL
+            "#
+        )
+        .bind(scope)
+        .bind(key)
+        .bind(claimed_at)
+        .bind(status_code)
+        .bind(response_body)
+        .bind(self.clock.now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Drops the in-progress claim made at `claimed_at` so the request can be retried. Returns
+    /// `false` when a retry has taken the key over since.
+    pub async fn release(&self, scope: &str, key: &str, claimed_at: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "DELETE FROM idempotency_keys WHERE scope = $1 AND key = $2 AND created_at = $3 AND completed_at IS NULL"
+        )
+        .bind(scope)
+        .bind(key)
+        .bind(claimed_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Deletes keys older than `ttl`, returning how many were deleted.
+    pub async fn delete_expired(&self, ttl: Duration) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE created_at < $1")
+            .bind(self.clock.now() - ttl)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    fn map_record(row: &PgRow) -> IdempotencyRecord {
+        IdempotencyRecord {
+            scope: row.get("scope"),
+            key: row.get("key"),
+            fingerprint: row.get("fingerprint"),
+            status_code: row.get("status_code"),
+            response_body: row.get("response_body"),
+            created_at: row.get("created_at"),
+            completed_at: row.get("completed_at"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use crate::clock::FixedClock;
+
+    crate::db_test!(async fn test_stale_in_progress_claims_are_taken_over(pool: PgPool) {
+        let start = Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap();
+        let clock = Arc::new(FixedClock::new(start));
+        let dao = IdempotencyDao::with_clock(pool, clock.clone());
+        let (ttl, timeout) = (Duration::hours(24), Duration::seconds(60));
+
+        let first = dao.try_claim("users", "retry-key", "first", ttl, timeout).await.unwrap().unwrap();
+        clock.advance(Duration::seconds(30));
+        assert!(dao.try_claim("users", "retry-key", "first", ttl, timeout).await.unwrap().is_none());
+
+        // The first request stalled without completing or releasing the key
+        clock.advance(Duration::seconds(31));
+        let second = dao.try_claim("users", "retry-key", "second", ttl, timeout).await.unwrap().unwrap();
+        let record = dao.get("users", "retry-key").await.unwrap().unwrap();
+        assert_eq!((record.fingerprint.trim_end(), record.created_at), ("second", second));
+
+        // Finishing late, the first request can no longer touch the second one's claim
+        assert!(!dao.release("users", "retry-key", first).await.unwrap());
+        assert!(!dao.complete("users", "retry-key", first, 500, &serde_json::json!({})).await.unwrap());
+        assert!(dao.get("users", "retry-key").await.unwrap().unwrap().completed_at.is_none());
+
+        // Completed keys are kept for the whole TTL
+        assert!(dao.complete("users", "retry-key", second, 201, &serde_json::json!({})).await.unwrap());
+        assert_eq!(dao.get("users", "retry-key").await.unwrap().unwrap().completed_at, Some(clock.now()));
+        clock.advance(Duration::hours(1));
+        assert!(dao.try_claim("users", "retry-key", "third", ttl, timeout).await.unwrap().is_none());
+        clock.advance(Duration::hours(24));
+        assert_eq!(dao.delete_expired(ttl).await.unwrap(), 1);
+    });
+}
@@ -1 +1,6 @@
-pub mod user_dao;
\ No newline at end of file
+pub mod email_change_dao;
+pub mod idempotency_dao;
+pub mod rate_limit_dao;
+pub mod user_dao;
+pub mod user_event_dao;
+pub mod webhook_dao;
\ No newline at end of file
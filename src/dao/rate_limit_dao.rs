@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use sqlx::{postgres::PgPoolOptions, PgPool, Row};
+use crate::config::RateLimitConfig;
+use crate::rate_limit::{Quota, Taken};
+
+/// Token buckets shared by every instance, in `rate_limit_buckets`. Buckets are refilled by
+/// Postgres' clock so instances with drifting clocks agree.
+#[derive(Clone)]
+pub struct RateLimitDao {
+    pool: PgPool,
+}
+
+impl RateLimitDao {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Runs on a small pool of its own, connecting as `pool` does. Bucket lookups then never
+    /// queue behind handlers for a connection, so an exhausted pool cannot hold requests up
+    /// before load shedding gets to refuse them.
+    pub fn with_own_pool(pool: &PgPool, config: &RateLimitConfig) -> Self {
+        let pool = PgPoolOptions::new()
+            .max_connections(config.pool_max_connections)
+            .acquire_timeout(Duration::from_millis(config.pool_acquire_timeout_ms))
+            .connect_lazy_with(pool.connect_options().as_ref().clone());
+        Self::new(pool)
+    }
+
+    /// Refills `key`'s bucket and takes a token in one statement, so concurrent requests on
+    /// any instance never share a token. A missing bucket is a full one.
+    pub async fn take(&self, key: &str, quota: Quota) -> Result<Taken, sqlx::Error> {
+        let capacity = quota.capacity();
+        let rate = quota.tokens_per_sec();
+        let row = sqlx::query(
+            r#"
+            INSERT INTO rate_limit_buckets AS bucket (key, tokens, updated_at, full_at)
+            VALUES ($1, $2 - 1, NOW(), NOW() + make_interval(secs => 1 / $3))
+            ON CONFLICT (key) DO UPDATE
+            SET tokens = LEAST($2, bucket.tokens + EXTRACT(EPOCH FROM NOW() - bucket.updated_at)::float8 * $3) - 1,
+                updated_at = NOW(),
+                full_at = NOW() + make_interval(secs => ($2 - LEAST($2,
+                    bucket.tokens + EXTRACT(EPOCH FROM NOW() - bucket.updated_at)::float8 * $3) + 1) / $3)
+            WHERE LEAST($2, bucket.tokens + EXTRACT(EPOCH FROM NOW() - bucket.updated_at)::float8 * $3) >= 1
+            RETURNING tokens
+            "#
+        )
+        .bind(key)
+        .bind(capacity)
+        .bind(rate)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(row) = row {
+            return Ok(Taken { allowed: true, tokens: row.get("tokens") });
+        }
+
+        // Refused: the bucket is left as it was, so report what it has refilled to by now
+        let tokens: Option<f64> = sqlx::query_scalar(
+            r#"
+            SELECT LEAST($2, tokens + EXTRACT(EPOCH FROM NOW() - updated_at)::float8 * $3)
+            FROM rate_limit_buckets
+            WHERE key = $1
+            "#
+        )
+        .bind(key)
+        .bind(capacity)
+        .bind(rate)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(Taken { allowed: false, tokens: tokens.unwrap_or(0.0) })
+    }
+
+    /// Deletes buckets that have refilled, returning how many were deleted.
+    pub async fn delete_full(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM rate_limit_buckets WHERE full_at < NOW()")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    crate::db_test!(async fn test_buckets_are_shared_and_cleaned_up(pool: PgPool) {
+        let dao = RateLimitDao::new(pool.clone());
+        let quota = Quota { requests: 2, window: Duration::from_secs(3600) };
+
+        let first = dao.take("POST /api/v1/users ip:192.0.2.7", quota).await.unwrap();
+        assert!(first.allowed);
+        assert!((first.tokens - 1.0).abs() < 0.01);
+        // Another instance shares the bucket
+        let other_instance = RateLimitDao::new(pool.clone());
+        assert!(other_instance.take("POST /api/v1/users ip:192.0.2.7", quota).await.unwrap().allowed);
+        let refused = dao.take("POST /api/v1/users ip:192.0.2.7", quota).await.unwrap();
+        assert!(!refused.allowed);
+        assert!(refused.tokens < 1.0);
+        assert!(dao.take("POST /api/v1/users ip:192.0.2.8", quota).await.unwrap().allowed);
+
+        assert_eq!(dao.delete_full().await.unwrap(), 0);
+        sqlx::query("UPDATE rate_limit_buckets SET full_at = NOW() - INTERVAL '1 second'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(dao.delete_full().await.unwrap(), 2);
+        assert!(dao.take("POST /api/v1/users ip:192.0.2.7", quota).await.unwrap().allowed);
+    });
+}
@@ -0,0 +1,91 @@
+use sqlx::{postgres::{PgListener, PgRow}, PgPool, Row};
+use crate::models::user_event::{FeedPosition, UserEvent};
+
+/// Postgres channel notified (with the event id) whenever a user event is committed.
+pub const USER_EVENTS_CHANNEL: &str = "user_events";
+
+#[derive(Clone)]
+pub struct UserEventDao {
+    pool: PgPool,
+}
+
+impl UserEventDao {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Returns events after `position` in feed order. Only events whose transactions are older
+    /// than every transaction still running are returned, so no event can later commit behind
+    /// the last one read.
+    pub async fn get_events_after(&self, position: FeedPosition, limit: i64) -> Result<Vec<UserEvent>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, txid::text::bigint AS txid, event_type, user_id, payload, created_at
+            FROM user_events
+            WHERE (txid, id) > ($1::bigint::text::xid8, $2)
+              AND txid < pg_snapshot_xmin(pg_current_snapshot())
+            ORDER BY txid, id
+            LIMIT $3
+            "#
+        )
+        .bind(position.txid)
+        .bind(position.id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(Self::map_event).collect())
+    }
+
+    /// Position of event `id`, or of the closest earlier event when there is no such event;
+    /// the start of the feed when there is neither.
+    pub async fn get_position(&self, id: i64) -> Result<FeedPosition, sqlx::Error> {
+        let row = sqlx::query(
+            "SELECT txid::text::bigint AS txid, id FROM user_events WHERE id <= $1 ORDER BY id DESC LIMIT 1"
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(Self::map_position).unwrap_or_default())
+    }
+
+    /// Position of the latest event readers can see, or the start of the feed without one.
+    pub async fn get_latest_position(&self) -> Result<FeedPosition, sqlx::Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT txid::text::bigint AS txid, id
+            FROM user_events
+            WHERE txid < pg_snapshot_xmin(pg_current_snapshot())
+            ORDER BY txid DESC, id DESC
+            LIMIT 1
+            "#
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(Self::map_position).unwrap_or_default())
+    }
+
+    /// Opens a dedicated connection listening on [`USER_EVENTS_CHANNEL`].
+    pub async fn listen(&self) -> Result<PgListener, sqlx::Error> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(USER_EVENTS_CHANNEL).await?;
+        Ok(listener)
+    }
+
+    fn map_event(row: &PgRow) -> UserEvent {
+        UserEvent {
+            id: row.get("id"),
+            txid: row.get("txid"),
+            event_type: row.get("event_type"),
+            user_id: row.get("user_id"),
+            payload: row.get("payload"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    fn map_position(row: &PgRow) -> FeedPosition {
+        FeedPosition { txid: row.get("txid"), id: row.get("id") }
+    }
+}
@@ -0,0 +1,438 @@
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgRow, PgPool, Row};
+use crate::models::webhook::{
+    DeadLetter, DeliveryAttempt, DeliveryStatus, WebhookDelivery, WebhookSubscription,
+};
+
+/// A delivery claimed by the dispatcher together with the target it must be sent to.
+#[derive(Debug, Clone)]
+pub struct ClaimedDelivery {
+    pub delivery: WebhookDelivery,
+    pub url: String,
+    pub secret: String,
+}
+
+/// Result of a single HTTP attempt, as persisted in the delivery history.
+#[derive(Debug, Clone)]
+pub struct AttemptRecord {
+    pub delivery_id: Uuid,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i64,
+}
+
+#[derive(Clone)]
+pub struct WebhookDao {
+    pool: PgPool,
+}
+
+const SUBSCRIPTION_COLUMNS: &str = "id, url, secret, event_types, active, created_at, updated_at";
+
+const DELIVERY_COLUMNS: &str = "id, subscription_id, event_id, event_type, payload, status, attempts, \
+     next_attempt_at, last_status_code, last_error, created_at, updated_at";
+
+impl WebhookDao {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create_subscription(
+        &self,
+        url: &str,
+        secret: &str,
+        event_types: &[String],
+    ) -> Result<WebhookSubscription, sqlx::Error> {
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+
+        let row = sqlx::query(&format!(
+            r#"
+            INSERT INTO webhook_subscriptions (id, url, secret, event_types, active, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, TRUE, $5, $6)
+            RETURNING {}
+            "#,
+            SUBSCRIPTION_COLUMNS
+        ))
+        .bind(id)
+        .bind(url)
+        .bind(secret)
+        .bind(event_types)
+        .bind(now)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(Self::map_subscription(&row))
+    }
+
+    pub async fn get_subscription(&self, id: Uuid) -> Result<Option<WebhookSubscription>, sqlx::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM webhook_subscriptions WHERE id = $1",
+            SUBSCRIPTION_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(Self::map_subscription))
+    }
+
+    pub async fn get_all_subscriptions(&self) -> Result<Vec<WebhookSubscription>, sqlx::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM webhook_subscriptions ORDER BY created_at DESC",
+            SUBSCRIPTION_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(Self::map_subscription).collect())
+    }
+
+    pub async fn delete_subscription(&self, id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM webhook_subscriptions WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Fans undispatched outbox events out into one delivery per matching active subscription
+    /// and marks them dispatched. Returns the number of events relayed.
+    pub async fn relay_pending_events(&self, limit: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            WITH events AS (
+                SELECT id, event_type, payload, created_at
+                FROM user_events
+                WHERE dispatched_at IS NULL
+                ORDER BY id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            ), fanout AS (
+                INSERT INTO webhook_deliveries (id, subscription_id, event_id, event_type, payload)
+                SELECT gen_random_uuid(), s.id, e.id, e.event_type,
+                       jsonb_build_object(
+                           'id', e.id,
+                           'type', e.event_type,
+                           'created_at', e.created_at,
+                           'data', e.payload
+                       )
+                FROM events e
+                JOIN webhook_subscriptions s
+                  ON s.active AND (e.event_type = ANY(s.event_types) OR '*' = ANY(s.event_types))
+            )
+            UPDATE user_events SET dispatched_at = NOW()
+            WHERE id IN (SELECT id FROM events)
+            "#
+        )
+        .bind(limit)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Claims due pending deliveries by pushing their `next_attempt_at` past `lease_until`,
+    /// so concurrent dispatchers never pick up the same delivery twice.
+    pub async fn claim_due_deliveries(
+        &self,
+        limit: i64,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Vec<ClaimedDelivery>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            UPDATE webhook_deliveries d
+            SET next_attempt_at = $2, updated_at = NOW()
+            FROM webhook_subscriptions s
+            WHERE s.id = d.subscription_id
+              AND d.id IN (
+                  SELECT id FROM webhook_deliveries
+                  WHERE status = 'pending' AND next_attempt_at <= NOW()
+                  ORDER BY next_attempt_at
+                  LIMIT $1
+                  FOR UPDATE SKIP LOCKED
+              )
+            RETURNING d.id, d.subscription_id, d.event_id, d.event_type, d.payload, d.status,
+                      d.attempts, d.next_attempt_at, d.last_status_code, d.last_error,
+                      d.created_at, d.updated_at, s.url, s.secret
+            "#
+        )
+        .bind(limit)
+        .bind(lease_until)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let deliveries = rows.iter().map(|row| ClaimedDelivery {
+            delivery: Self::map_delivery(row),
+            url: row.get("url"),
+            secret: row.get("secret"),
+        }).collect();
+
+        Ok(deliveries)
+    }
+
+    pub async fn record_success(&self, record: &AttemptRecord) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        Self::insert_attempt(&mut tx, record).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = 'succeeded', attempts = $2, last_status_code = $3, last_error = NULL, updated_at = NOW()
+            WHERE id = $1
+            "#
+        )
+        .bind(record.delivery_id)
+        .bind(record.attempt)
+        .bind(record.status_code)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await
+    }
+
+    /// Records a failed attempt. With `retry_at` the delivery is rescheduled, otherwise it is
+    /// moved to the dead-letter table.
+    pub async fn record_failure(
+        &self,
+        record: &AttemptRecord,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        Self::insert_attempt(&mut tx, record).await?;
+
+        let status = if retry_at.is_some() {
+            DeliveryStatus::Pending
+        } else {
+            DeliveryStatus::Dead
+        };
+
+        sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = $2, attempts = $3, last_status_code = $4, last_error = $5,
+                next_attempt_at = COALESCE($6, next_attempt_at), updated_at = NOW()
+            WHERE id = $1
+            "#
+        )
+        .bind(record.delivery_id)
+        .bind(status.as_str())
+        .bind(record.attempt)
+        .bind(record.status_code)
+        .bind(&record.error)
+        .bind(retry_at)
+        .execute(&mut *tx)
+        .await?;
+
+        if retry_at.is_none() {
+            sqlx::query(
+                r#"
+                INSERT INTO webhook_dead_letters (id, delivery_id, subscription_id, last_error)
+                SELECT $1, id, subscription_id, last_error FROM webhook_deliveries WHERE id = $2
+                "#
+            )
+            .bind(Uuid::new_v4())
+            .bind(record.delivery_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+
+    async fn insert_attempt(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        record: &AttemptRecord,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO webhook_delivery_attempts (delivery_id, attempt, status_code, error, duration_ms)
+            VALUES ($1, $2, $3, $4, $5)
+            "#
+        )
+        .bind(record.delivery_id)
+        .bind(record.attempt)
+        .bind(record.status_code)
+        .bind(&record.error)
+        .bind(record.duration_ms)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_deliveries_for_subscription(
+        &self,
+        subscription_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM webhook_deliveries WHERE subscription_id = $1 ORDER BY created_at DESC LIMIT $2",
+            DELIVERY_COLUMNS
+        ))
+        .bind(subscription_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(Self::map_delivery).collect())
+    }
+
+    pub async fn get_attempts_for_deliveries(
+        &self,
+        delivery_ids: &[Uuid],
+    ) -> Result<Vec<DeliveryAttempt>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, delivery_id, attempt, status_code, error, duration_ms, attempted_at
+            FROM webhook_delivery_attempts
+            WHERE delivery_id = ANY($1)
+            ORDER BY attempted_at
+            "#
+        )
+        .bind(delivery_ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let attempts = rows.into_iter().map(|row| DeliveryAttempt {
+            id: row.get("id"),
+            delivery_id: row.get("delivery_id"),
+            attempt: row.get("attempt"),
+            status_code: row.get("status_code"),
+            error: row.get("error"),
+            duration_ms: row.get("duration_ms"),
+            attempted_at: row.get("attempted_at"),
+        }).collect();
+
+        Ok(attempts)
+    }
+
+    pub async fn get_dead_letters(&self, limit: i64) -> Result<Vec<DeadLetter>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, delivery_id, subscription_id, last_error, created_at, replayed_at
+            FROM webhook_dead_letters
+            ORDER BY created_at DESC
+            LIMIT $1
+            "#
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(Self::map_dead_letter).collect())
+    }
+
+    /// Puts a dead-lettered delivery back in the queue with a fresh retry budget.
+    /// Returns `None` if the dead letter does not exist or was already replayed.
+    pub async fn replay_dead_letter(&self, id: Uuid) -> Result<Option<DeadLetter>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query(
+            r#"
+            UPDATE webhook_dead_letters SET replayed_at = NOW()
+            WHERE id = $1 AND replayed_at IS NULL
+            RETURNING id, delivery_id, subscription_id, last_error, created_at, replayed_at
+            "#
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let dead_letter = match row {
+            Some(row) => Self::map_dead_letter(&row),
+            None => return Ok(None),
+        };
+
+        sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = 'pending', attempts = 0, next_attempt_at = NOW(), updated_at = NOW()
+            WHERE id = $1
+            "#
+        )
+        .bind(dead_letter.delivery_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(dead_letter))
+    }
+
+    fn map_subscription(row: &PgRow) -> WebhookSubscription {
+        WebhookSubscription {
+            id: row.get("id"),
+            url: row.get("url"),
+            secret: row.get("secret"),
+            event_types: row.get("event_types"),
+            active: row.get("active"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+
+    fn map_delivery(row: &PgRow) -> WebhookDelivery {
+        let status: String = row.get("status");
+
+        WebhookDelivery {
+            id: row.get("id"),
+            subscription_id: row.get("subscription_id"),
+            event_id: row.get("event_id"),
+            event_type: row.get("event_type"),
+            payload: row.get("payload"),
+            status: DeliveryStatus::parse(&status).unwrap_or(DeliveryStatus::Pending),
+            attempts: row.get("attempts"),
+            next_attempt_at: row.get("next_attempt_at"),
+            last_status_code: row.get("last_status_code"),
+            last_error: row.get("last_error"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+
+    fn map_dead_letter(row: &PgRow) -> DeadLetter {
+        DeadLetter {
+            id: row.get("id"),
+            delivery_id: row.get("delivery_id"),
+            subscription_id: row.get("subscription_id"),
+            last_error: row.get("last_error"),
+            created_at: row.get("created_at"),
+            replayed_at: row.get("replayed_at"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attempt_record_creation() {
+        let record = AttemptRecord {
+            delivery_id: Uuid::new_v4(),
+            attempt: 3,
+            status_code: Some(503),
+            error: Some("Service Unavailable".to_string()),
+            duration_ms: 120,
+        };
+
+        assert_eq!(record.attempt, 3);
+        assert_eq!(record.status_code, Some(503));
+    }
+
+    #[test]
+    fn test_delivery_columns_cover_model_fields() {
+        for column in ["payload", "status", "attempts", "next_attempt_at", "last_error"] {
+            assert!(DELIVERY_COLUMNS.contains(column));
+        }
+        assert!(SUBSCRIPTION_COLUMNS.contains("secret"));
+    }
+
+    // Queries themselves are exercised against a real database in integration environments.
+}
@@ -1,8 +1,43 @@
-use sqlx::{postgres::PgPoolOptions, PgPool};
-use crate::config::Settings;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::postgres::{PgConnection, PgPoolOptions};
+use sqlx::{ConnectOptions, Connection, PgPool, Row};
+use crate::config::{LeaderElectionConfig, Settings};
 
 pub type DbPool = PgPool;
 
+/// Migrations embedded from `migrations/`. Each has an `.up.sql` and a `.down.sql` script.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Advisory lock name serializing schema changes across instances.
+const MIGRATIONS_LOCK: &str = "migrations";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationState {
+    Pending,
+    Applied { installed_on: DateTime<Utc> },
+    /// Applied, but the script has been edited since
+    Modified { installed_on: DateTime<Utc> },
+    /// Started but did not complete; needs manual repair before migrating further
+    Failed,
+    /// Recorded in the database but not known to this build, e.g. after a downgrade
+    Unknown { installed_on: DateTime<Utc> },
+}
+
+/// One row of `migrate status`.
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub reversible: bool,
+    pub state: MigrationState,
+}
+
 pub async fn create_pool(settings: &Settings) -> Result<DbPool, sqlx::Error> {
     let database_url = settings.database_url();
     
@@ -12,20 +47,249 @@ pub async fn create_pool(settings: &Settings) -> Result<DbPool, sqlx::Error> {
         .await
 }
 
-pub async fn run_migrations(pool: &DbPool) -> Result<(), sqlx::migrate::MigrateError> {
-    sqlx::migrate!("./migrations").run(pool).await
+/// Applies every pending migration. Instances starting together wait for each other, so only
+/// the first applies anything and the rest find the schema up to date.
+pub async fn run_migrations(pool: &DbPool) -> Result<(), MigrateError> {
+    let lock = AdvisoryLock::acquire(pool, MIGRATIONS_LOCK).await?;
+    let result = MIGRATOR.run(pool).await;
+    lock.release().await;
+    result
+}
+
+/// Reverts the `steps` most recently applied migrations, newest first, and returns their
+/// versions.
+pub async fn revert_migrations(pool: &DbPool, steps: usize) -> Result<Vec<i64>, MigrateError> {
+    let mut applied: Vec<i64> = applied_migrations(pool).await?.into_iter().map(|row| row.version).collect();
+    applied.sort_unstable();
+
+    let keep = applied.len().saturating_sub(steps);
+    let reverted: Vec<i64> = applied[keep..].iter().rev().copied().collect();
+    if reverted.is_empty() {
+        return Ok(reverted);
+    }
+
+    // Versions start at 1, so 0 reverts everything
+    let target = if keep == 0 { 0 } else { applied[keep - 1] };
+    let lock = AdvisoryLock::acquire(pool, MIGRATIONS_LOCK).await?;
+    let result = MIGRATOR.undo(pool, target).await;
+    lock.release().await;
+    result.map(|()| reverted)
+}
+
+/// Compares the embedded migrations with those recorded in the database, in version order.
+pub async fn migration_status(pool: &DbPool) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let mut applied: HashMap<i64, AppliedRow> = applied_migrations(pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.version, row))
+        .collect();
+
+    let mut statuses: Vec<MigrationStatus> = MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .map(|migration| {
+            let state = match applied.remove(&migration.version) {
+                None => MigrationState::Pending,
+                Some(row) if !row.success => MigrationState::Failed,
+                Some(row) if row.checksum != migration.checksum.as_ref() => {
+                    MigrationState::Modified { installed_on: row.installed_on }
+                }
+                Some(row) => MigrationState::Applied { installed_on: row.installed_on },
+            };
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                reversible: migration.migration_type.is_reversible(),
+                state,
+            }
+        })
+        .collect();
+
+    statuses.extend(applied.into_values().map(|row| MigrationStatus {
+        version: row.version,
+        description: row.description,
+        reversible: false,
+        state: if row.success {
+            MigrationState::Unknown { installed_on: row.installed_on }
+        } else {
+            MigrationState::Failed
+        },
+    }));
+    statuses.sort_by_key(|status| status.version);
+    Ok(statuses)
+}
+
+/// A session-level Postgres advisory lock, held on a dedicated connection outside the pool.
+///
+/// Postgres releases the lock as soon as that connection ends, including when the holding
+/// process crashes or loses its network, which is what lets another instance take over.
+pub struct AdvisoryLock {
+    name: &'static str,
+    key: i64,
+    connection: PgConnection,
+}
+
+impl AdvisoryLock {
+    /// Waits until the lock named `name` is free and takes it.
+    pub async fn acquire(pool: &DbPool, name: &'static str) -> Result<Self, sqlx::Error> {
+        let mut connection = pool.connect_options().connect().await?;
+        let key = lock_key(name);
+
+        let acquired: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+            .bind(key)
+            .fetch_one(&mut connection)
+            .await?;
+        if !acquired {
+            log::info!("Waiting for another instance to release the {} lock", name);
+            sqlx::query("SELECT pg_advisory_lock($1)")
+                .bind(key)
+                .execute(&mut connection)
+                .await?;
+        }
+
+        Ok(Self { name, key, connection })
+    }
+
+    /// Takes the lock named `name` if no other session holds it.
+    pub async fn try_acquire(pool: &DbPool, name: &'static str) -> Result<Option<Self>, sqlx::Error> {
+        let mut connection = pool.connect_options().connect().await?;
+        let key = lock_key(name);
+
+        let acquired: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+            .bind(key)
+            .fetch_one(&mut connection)
+            .await?;
+
+        Ok(acquired.then_some(Self { name, key, connection }))
+    }
+
+    /// Returns once the lock's connection stops answering, checking every `interval`. From then
+    /// on the lock must be assumed lost.
+    pub async fn lost(&mut self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(err) = self.connection.ping().await {
+                log::warn!("Connection holding the {} lock failed: {}", self.name, err);
+                return;
+            }
+        }
+    }
+
+    /// Unlocks and closes the connection. Failures are only logged, since closing the
+    /// connection releases the lock regardless.
+    pub async fn release(mut self) {
+        let unlocked = sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(self.key)
+            .execute(&mut self.connection)
+            .await;
+        if let Err(err) = unlocked {
+            log::warn!("Failed to release the {} lock: {}", self.name, err);
+        }
+        let _ = self.connection.close().await;
+    }
+}
+
+/// Stable 64-bit advisory lock key for a lock name.
+pub fn lock_key(name: &str) -> i64 {
+    let digest = Sha256::digest(format!("tangy-mango:{}", name).as_bytes());
+    i64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Runs a background job on exactly one instance at a time.
+///
+/// Every instance calls [`LeaderElection::run`]; the one that takes the job's advisory lock
+/// runs the job and the others retry every `check_interval_secs`. If the leader dies, its
+/// lock is released with its connection and the next retry elsewhere takes over. A leader
+/// that loses its connection stops the job within one check interval, so two instances may
+/// briefly overlap; jobs must tolerate that, as the webhook dispatcher does with its leases.
+pub struct LeaderElection {
+    pool: DbPool,
+    name: &'static str,
+    check_interval: Duration,
+}
+
+impl LeaderElection {
+    pub fn new(pool: DbPool, name: &'static str, config: &LeaderElectionConfig) -> Self {
+        Self {
+            pool,
+            name,
+            check_interval: Duration::from_secs(config.check_interval_secs),
+        }
+    }
+
+    /// Contends for leadership forever, starting a fresh `job` each time it is won and
+    /// cancelling it when leadership is lost.
+    pub async fn run<F, Fut>(self, job: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        loop {
+            match AdvisoryLock::try_acquire(&self.pool, self.name).await {
+                Ok(Some(mut lock)) => {
+                    log::info!("This instance now runs {}", self.name);
+                    tokio::select! {
+                        _ = job() => log::warn!("{} stopped on its own", self.name),
+                        _ = lock.lost(self.check_interval) => {
+                            log::warn!("Lost the {} lock; stopping it on this instance", self.name)
+                        }
+                    }
+                    lock.release().await;
+                }
+                Ok(None) => log::debug!("{} is running on another instance", self.name),
+                Err(err) => log::warn!("Failed to contend for {}: {}", self.name, err),
+            }
+            tokio::time::sleep(self.check_interval).await;
+        }
+    }
+}
+
+struct AppliedRow {
+    version: i64,
+    description: String,
+    checksum: Vec<u8>,
+    success: bool,
+    installed_on: DateTime<Utc>,
+}
+
+/// Rows of sqlx's bookkeeping table, which does not exist before the first migration.
+async fn applied_migrations(pool: &DbPool) -> Result<Vec<AppliedRow>, MigrateError> {
+    let exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(pool)
+        .await?;
+    if !exists {
+        return Ok(Vec::new());
+    }
+
+    let rows = sqlx::query(
+        "SELECT version, description, checksum, success, installed_on FROM _sqlx_migrations ORDER BY version"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| AppliedRow {
+            version: row.get("version"),
+            description: row.get("description"),
+            checksum: row.get("checksum"),
+            success: row.get("success"),
+            installed_on: row.get("installed_on"),
+        })
+        .collect())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{Settings, ServerConfig, DatabaseConfig};
+    use crate::config::{Settings, ServerConfig, DatabaseConfig, WebhookConfig, EventStreamConfig, IdempotencyConfig, ImportConfig, SearchConfig, EmailChangeConfig, LeaderElectionConfig, ChaosConfig, ShutdownConfig, RateLimitConfig, LoadSheddingConfig};
 
     fn create_test_settings() -> Settings {
         Settings {
             server: ServerConfig {
                 host: "127.0.0.1".to_string(),
                 port: 8080,
+                tls: None,
             },
             database: DatabaseConfig {
                 host: "localhost".to_string(),
@@ -35,10 +299,22 @@ mod tests {
                 database_name: "test_db".to_string(),
                 max_connections: 5,
             },
+            webhooks: WebhookConfig::default(),
+            event_stream: EventStreamConfig::default(),
+            idempotency: IdempotencyConfig::default(),
+            import: ImportConfig::default(),
+            search: SearchConfig::default(),
+            email_change: EmailChangeConfig::default(),
+            leader_election: LeaderElectionConfig::default(),
+            chaos: ChaosConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            load_shedding: LoadSheddingConfig::default(),
         }
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_db_pool_type_alias() {
         // Test that DbPool is correctly aliased to PgPool
         // This is a compile-time test - if it compiles, the alias works
@@ -77,6 +353,30 @@ mod tests {
         assert_eq!(settings_low.database.max_connections, 1);
     }
 
+    #[test]
+    fn test_lock_keys_are_stable_and_distinct() {
+        assert_eq!(lock_key("migrations"), lock_key("migrations"));
+        assert_ne!(lock_key("migrations"), lock_key("webhook-dispatcher"));
+    }
+
+    #[test]
+    fn test_every_migration_is_reversible() {
+        let mut up = Vec::new();
+        let mut down = Vec::new();
+        for migration in MIGRATOR.iter() {
+            if migration.migration_type.is_down_migration() {
+                down.push(migration.version);
+            } else {
+                assert!(migration.migration_type.is_reversible(), "{} has no down script", migration.version);
+                up.push(migration.version);
+            }
+        }
+
+        down.sort_unstable();
+        assert_eq!(up, down);
+        assert_eq!(up, (1..=up.len() as i64).collect::<Vec<_>>());
+    }
+
     // Note: Actual database connection tests would require a running PostgreSQL instance
     // and are better suited for integration tests rather than unit tests.
     // The tests above focus on configuration and setup logic.
@@ -0,0 +1,34 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ServiceError {
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    PreconditionFailed(String),
+    #[error("{0}")]
+    Unprocessable(String),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_error_messages() {
+        assert_eq!(ServiceError::Validation("bad url".to_string()).to_string(), "bad url");
+        assert_eq!(ServiceError::NotFound("Webhook").to_string(), "Webhook not found");
+    }
+
+    #[test]
+    fn test_database_error_conversion() {
+        let err: ServiceError = sqlx::Error::RowNotFound.into();
+        assert!(matches!(err, ServiceError::Database(_)));
+    }
+}
@@ -0,0 +1,82 @@
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use crate::chaos::{Chaos, ChaosRules};
+use super::ErrorResponse;
+
+/// A 401 unless the request carries the configured admin token.
+fn unauthorized(chaos: &Chaos, request: &HttpRequest) -> Option<HttpResponse> {
+    let authorization = request.headers().get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+    if chaos.authorizes(authorization) {
+        return None;
+    }
+    Some(
+        HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .json(ErrorResponse::new("A valid admin token is required")),
+    )
+}
+
+pub async fn get_chaos(chaos: web::Data<Chaos>, request: HttpRequest) -> Result<HttpResponse> {
+    if let Some(response) = unauthorized(&chaos, &request) {
+        return Ok(response);
+    }
+    Ok(HttpResponse::Ok().json(chaos.rules()))
+}
+
+/// Replaces every rule at once; the body has the shape `GET` returns.
+pub async fn put_chaos(
+    chaos: web::Data<Chaos>,
+    request: HttpRequest,
+    rules: web::Json<ChaosRules>,
+) -> Result<HttpResponse> {
+    if let Some(response) = unauthorized(&chaos, &request) {
+        return Ok(response);
+    }
+    match chaos.set_rules(rules.into_inner()) {
+        Ok(()) => {
+            log::warn!("Chaos rules replaced: {:?}", chaos.rules());
+            Ok(HttpResponse::Ok().json(chaos.rules()))
+        }
+        Err(problems) => Ok(HttpResponse::BadRequest().json(ErrorResponse::new(problems.join("; ")))),
+    }
+}
+
+pub async fn enable_chaos(chaos: web::Data<Chaos>, request: HttpRequest) -> Result<HttpResponse> {
+    set_active(&chaos, &request, true)
+}
+
+pub async fn disable_chaos(chaos: web::Data<Chaos>, request: HttpRequest) -> Result<HttpResponse> {
+    set_active(&chaos, &request, false)
+}
+
+fn set_active(chaos: &Chaos, request: &HttpRequest, active: bool) -> Result<HttpResponse> {
+    if let Some(response) = unauthorized(chaos, request) {
+        return Ok(response);
+    }
+    chaos.set_active(active);
+    log::warn!("Chaos rules {}", if active { "enabled" } else { "disabled" });
+    Ok(HttpResponse::Ok().json(chaos.rules()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    #[actix_web::test]
+    async fn test_admin_token_is_required_when_configured() {
+        let chaos = web::Data::new(Chaos::new(ChaosRules::default(), Some("secret".to_string())));
+
+        let response = enable_chaos(chaos.clone(), TestRequest::default().to_http_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!chaos.rules().active);
+
+        let request = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer secret"))
+            .to_http_request();
+        let response = enable_chaos(chaos.clone(), request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(chaos.rules().active);
+    }
+}
@@ -0,0 +1,149 @@
+//! Helpers for HTTP conditional requests (RFC 9110 section 13) on versioned resources.
+
+use std::time::SystemTime;
+
+use actix_web::http::header::{self, EntityTag, Header, HttpDate, IfMatch, IfModifiedSince, IfNoneMatch};
+use actix_web::{HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use super::ErrorResponse;
+
+/// Strong ETag for a resource version.
+pub(crate) fn etag_for(version: i64) -> EntityTag {
+    EntityTag::new_strong(version.to_string())
+}
+
+pub(crate) fn http_date(timestamp: DateTime<Utc>) -> HttpDate {
+    HttpDate::from(SystemTime::from(timestamp))
+}
+
+/// Whether a GET can be answered with `304 Not Modified`. `If-None-Match` takes precedence;
+/// `If-Modified-Since` is only consulted when it is absent.
+pub(crate) fn is_not_modified(request: &HttpRequest, etag: &EntityTag, last_modified: DateTime<Utc>) -> bool {
+    if request.headers().contains_key(header::IF_NONE_MATCH) {
+        return match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+            Err(_) => false,
+        };
+    }
+
+    if request.headers().contains_key(header::IF_MODIFIED_SINCE) {
+        if let Ok(IfModifiedSince(since)) = IfModifiedSince::parse(request) {
+            // HTTP dates have whole-second precision
+            let since = DateTime::<Utc>::from(SystemTime::from(since));
+            return last_modified.timestamp() <= since.timestamp();
+        }
+    }
+
+    false
+}
+
+/// Versions a write must match according to `If-Match`: `Ok(None)` for `*`, otherwise the
+/// versions named by strong ETags. A missing header yields `428 Precondition Required` and a
+/// header naming no usable version yields `412 Precondition Failed`.
+pub(crate) fn required_versions(request: &HttpRequest) -> Result<Option<Vec<i64>>, HttpResponse> {
+    if !request.headers().contains_key(header::IF_MATCH) {
+        return Err(HttpResponse::PreconditionRequired().json(ErrorResponse::new(
+            "If-Match header with the user's ETag is required",
+        )));
+    }
+
+    match IfMatch::parse(request) {
+        Ok(IfMatch::Any) => Ok(None),
+        Ok(IfMatch::Items(tags)) => {
+            // If-Match uses strong comparison, so weak tags never match
+            let versions: Vec<i64> = tags
+                .iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(|tag| tag.tag().parse().ok())
+                .collect();
+
+            if versions.is_empty() {
+                Err(HttpResponse::PreconditionFailed().json(ErrorResponse::new(
+                    "If-Match does not match the current user version",
+                )))
+            } else {
+                Ok(Some(versions))
+            }
+        }
+        Err(_) => Err(HttpResponse::BadRequest().json(ErrorResponse::new("Malformed If-Match header"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use chrono::TimeZone;
+
+    fn last_modified() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn test_etag_for_version() {
+        assert_eq!(etag_for(3).to_string(), "\"3\"");
+        assert!(!etag_for(3).weak);
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = etag_for(2);
+
+        let matching = TestRequest::default().insert_header(("If-None-Match", "\"1\", \"2\"")).to_http_request();
+        assert!(is_not_modified(&matching, &etag, last_modified()));
+
+        let weak = TestRequest::default().insert_header(("If-None-Match", "W/\"2\"")).to_http_request();
+        assert!(is_not_modified(&weak, &etag, last_modified()));
+
+        let any = TestRequest::default().insert_header(("If-None-Match", "*")).to_http_request();
+        assert!(is_not_modified(&any, &etag, last_modified()));
+
+        let stale = TestRequest::default().insert_header(("If-None-Match", "\"1\"")).to_http_request();
+        assert!(!is_not_modified(&stale, &etag, last_modified()));
+
+        let none = TestRequest::default().to_http_request();
+        assert!(!is_not_modified(&none, &etag, last_modified()));
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let etag = etag_for(1);
+
+        let same = TestRequest::default()
+            .insert_header(("If-Modified-Since", "Mon, 15 Jan 2024 10:30:00 GMT"))
+            .to_http_request();
+        assert!(is_not_modified(&same, &etag, last_modified()));
+
+        let earlier = TestRequest::default()
+            .insert_header(("If-Modified-Since", "Mon, 15 Jan 2024 10:29:59 GMT"))
+            .to_http_request();
+        assert!(!is_not_modified(&earlier, &etag, last_modified()));
+    }
+
+    #[test]
+    fn test_if_none_match_takes_precedence() {
+        let request = TestRequest::default()
+            .insert_header(("If-None-Match", "\"1\""))
+            .insert_header(("If-Modified-Since", "Mon, 15 Jan 2024 10:30:00 GMT"))
+            .to_http_request();
+
+        assert!(!is_not_modified(&request, &etag_for(2), last_modified()));
+    }
+
+    #[test]
+    fn test_required_versions() {
+        let missing = TestRequest::default().to_http_request();
+        assert_eq!(required_versions(&missing).unwrap_err().status(), StatusCode::PRECONDITION_REQUIRED);
+
+        let any = TestRequest::default().insert_header(("If-Match", "*")).to_http_request();
+        assert_eq!(required_versions(&any).unwrap(), None);
+
+        let listed = TestRequest::default().insert_header(("If-Match", "\"4\", \"5\"")).to_http_request();
+        assert_eq!(required_versions(&listed).unwrap(), Some(vec![4, 5]));
+
+        let weak = TestRequest::default().insert_header(("If-Match", "W/\"4\"")).to_http_request();
+        assert_eq!(required_versions(&weak).unwrap_err().status(), StatusCode::PRECONDITION_FAILED);
+    }
+}
@@ -0,0 +1,47 @@
+use actix_web::{web, HttpResponse, Result};
+use uuid::Uuid;
+use crate::models::email_change::{EmailChangeRequest, EmailChangeTokenRequest};
+use crate::services::email_change_service::EmailChangeService;
+use super::service_error_response;
+
+/// Starts moving a user to a new address: `POST /api/v1/users/{id}/email-change`.
+pub async fn request_email_change(
+    email_change_service: web::Data<EmailChangeService>,
+    path: web::Path<Uuid>,
+    request: web::Json<EmailChangeRequest>,
+) -> Result<HttpResponse> {
+    match email_change_service.request_change(path.into_inner(), request.into_inner()).await {
+        Ok(change) => Ok(HttpResponse::Accepted().json(change)),
+        Err(err) => Ok(service_error_response("Failed to request email change", err)),
+    }
+}
+
+pub async fn get_email_change(
+    email_change_service: web::Data<EmailChangeService>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse> {
+    match email_change_service.get_pending_change(path.into_inner()).await {
+        Ok(change) => Ok(HttpResponse::Ok().json(change)),
+        Err(err) => Ok(service_error_response("Failed to get email change", err)),
+    }
+}
+
+pub async fn confirm_email_change(
+    email_change_service: web::Data<EmailChangeService>,
+    request: web::Json<EmailChangeTokenRequest>,
+) -> Result<HttpResponse> {
+    match email_change_service.confirm(&request.token).await {
+        Ok(user) => Ok(HttpResponse::Ok().json(user)),
+        Err(err) => Ok(service_error_response("Failed to confirm email change", err)),
+    }
+}
+
+pub async fn revert_email_change(
+    email_change_service: web::Data<EmailChangeService>,
+    request: web::Json<EmailChangeTokenRequest>,
+) -> Result<HttpResponse> {
+    match email_change_service.revert(&request.token).await {
+        Ok(user) => Ok(HttpResponse::Ok().json(user)),
+        Err(err) => Ok(service_error_response("Failed to revert email change", err)),
+    }
+}
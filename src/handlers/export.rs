@@ -0,0 +1,184 @@
+//! Content negotiation and row encoding for streamed user exports.
+
+use actix_web::http::header::{self, Accept, Header};
+use actix_web::{HttpRequest, HttpResponse};
+use serde_json::Value;
+use crate::models::user::UserResponse;
+use crate::models::user_export::{ExportFormat, UserField};
+use super::ErrorResponse;
+
+/// Picks the export format from `Accept`, honouring quality values. A missing header means
+/// JSON; a header naming no supported type yields `406 Not Acceptable`.
+pub(crate) fn negotiate_format(request: &HttpRequest) -> Result<ExportFormat, HttpResponse> {
+    if !request.headers().contains_key(header::ACCEPT) {
+        return Ok(ExportFormat::Json);
+    }
+
+    Accept::parse(request)
+        .ok()
+        .and_then(|accept| {
+            accept
+                .ranked()
+                .iter()
+                .find_map(|mime| ExportFormat::from_media_range(mime.essence_str()))
+        })
+        .ok_or_else(|| {
+            HttpResponse::NotAcceptable().json(ErrorResponse::new(
+                "Export is available as application/json, application/x-ndjson or text/csv",
+            ))
+        })
+}
+
+/// Renders users one at a time in the negotiated format, limited to the selected fields.
+pub(crate) struct ExportEncoder {
+    format: ExportFormat,
+    fields: Vec<UserField>,
+}
+
+impl ExportEncoder {
+    pub(crate) fn new(format: ExportFormat, fields: Vec<UserField>) -> Self {
+        Self { format, fields }
+    }
+
+    /// Output preceding the first row: the CSV header line or the opening bracket.
+    pub(crate) fn header(&self) -> String {
+        match self.format {
+            ExportFormat::Csv => {
+                let names: Vec<&str> = self.fields.iter().map(UserField::as_str).collect();
+                format!("{}\r\n", names.join(","))
+            }
+            ExportFormat::Ndjson => String::new(),
+            ExportFormat::Json => "[".to_string(),
+        }
+    }
+
+    /// One user; `index` is its position in the export, used to place JSON separators.
+    pub(crate) fn row(&self, user: &UserResponse, index: u64) -> String {
+        match self.format {
+            ExportFormat::Csv => {
+                let values: Vec<String> = self
+                    .fields
+                    .iter()
+                    .map(|field| match field.value(user) {
+                        Value::String(value) => csv_field(&value),
+                        value => value.to_string(),
+                    })
+                    .collect();
+                format!("{}\r\n", values.join(","))
+            }
+            ExportFormat::Ndjson => format!("{}\n", self.json_object(user)),
+            ExportFormat::Json if index == 0 => self.json_object(user),
+            ExportFormat::Json => format!(",{}", self.json_object(user)),
+        }
+    }
+
+    /// Output following the last row.
+    pub(crate) fn footer(&self) -> String {
+        match self.format {
+            ExportFormat::Json => "]\n".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// A JSON object with the selected fields in the requested order.
+    fn json_object(&self, user: &UserResponse) -> String {
+        let members: Vec<String> = self
+            .fields
+            .iter()
+            .map(|field| format!("{}:{}", Value::from(field.as_str()), field.value(user)))
+            .collect();
+        format!("{{{}}}", members.join(","))
+    }
+}
+
+/// Quotes a CSV field when it contains a delimiter, quote or line break (RFC 4180).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn create_test_user(name: &str) -> UserResponse {
+        let timestamp = Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap();
+        UserResponse {
+            id: Uuid::nil(),
+            email: "test@example.com".to_string(),
+            name: name.to_string(),
+            version: 1,
+            created_at: timestamp,
+            updated_at: timestamp,
+            suspended_at: None,
+        }
+    }
+
+    fn encode_all(encoder: &ExportEncoder, users: &[UserResponse]) -> String {
+        let mut output = encoder.header();
+        for (index, user) in users.iter().enumerate() {
+            output.push_str(&encoder.row(user, index as u64));
+        }
+        output.push_str(&encoder.footer());
+        output
+    }
+
+    #[test]
+    fn test_negotiate_format() {
+        let missing = TestRequest::default().to_http_request();
+        assert_eq!(negotiate_format(&missing).unwrap(), ExportFormat::Json);
+
+        let csv = TestRequest::default().insert_header(("Accept", "text/csv")).to_http_request();
+        assert_eq!(negotiate_format(&csv).unwrap(), ExportFormat::Csv);
+
+        let ranked = TestRequest::default()
+            .insert_header(("Accept", "application/json;q=0.5, application/x-ndjson"))
+            .to_http_request();
+        assert_eq!(negotiate_format(&ranked).unwrap(), ExportFormat::Ndjson);
+
+        let unsupported = TestRequest::default().insert_header(("Accept", "text/html")).to_http_request();
+        assert_eq!(negotiate_format(&unsupported).unwrap_err().status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[test]
+    fn test_csv_export_quotes_fields() {
+        let encoder = ExportEncoder::new(ExportFormat::Csv, vec![UserField::Name, UserField::Version]);
+        let output = encode_all(&encoder, &[create_test_user("Plain"), create_test_user("Hopper, \"Grace\"")]);
+
+        assert_eq!(output, "name,version\r\nPlain,1\r\n\"Hopper, \"\"Grace\"\"\",1\r\n");
+    }
+
+    #[test]
+    fn test_json_export_is_a_valid_array() {
+        let encoder = ExportEncoder::new(ExportFormat::Json, vec![UserField::Email, UserField::CreatedAt]);
+
+        let empty: Value = serde_json::from_str(&encode_all(&encoder, &[])).unwrap();
+        assert_eq!(empty, serde_json::json!([]));
+
+        let output = encode_all(&encoder, &[create_test_user("A"), create_test_user("B")]);
+        let parsed: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(parsed.as_array().unwrap().len(), 2);
+        assert_eq!(parsed[0]["created_at"], "2024-01-15T10:30:00Z");
+        // Fields keep the requested order
+        assert!(output.starts_with("[{\"email\":"));
+    }
+
+    #[test]
+    fn test_ndjson_export_has_one_object_per_line() {
+        let encoder = ExportEncoder::new(ExportFormat::Ndjson, UserField::ALL.to_vec());
+        let output = encode_all(&encoder, &[create_test_user("Line\nBreak"), create_test_user("B")]);
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let first: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["name"], "Line\nBreak");
+        assert_eq!(first["version"], 1);
+    }
+}
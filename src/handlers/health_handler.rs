@@ -0,0 +1,18 @@
+use actix_web::{web, HttpResponse, Result};
+use serde_json::json;
+use crate::shutdown::Lifecycle;
+
+/// Whether the process is up; only a hung server fails it.
+pub async fn live() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(json!({"status": "live"})))
+}
+
+/// Whether to route traffic here; fails from the start of shutdown so load balancers move on
+/// before connections are refused.
+pub async fn ready(lifecycle: web::Data<Lifecycle>) -> Result<HttpResponse> {
+    if lifecycle.is_ready() {
+        Ok(HttpResponse::Ok().json(json!({"status": "ready"})))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(json!({"status": "shutting_down"})))
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt::Write;
+
+use actix_web::{web, HttpResponse, Result};
+use crate::load_shed::{LoadShedder, PoolMonitor};
+use crate::shutdown::Lifecycle;
+
+/// Prometheus text exposition of request concurrency, database pool pressure and shed load.
+/// Pool metrics are absent in mock mode and shedding metrics without `[load_shedding] enabled`.
+pub async fn metrics(
+    lifecycle: web::Data<Lifecycle>,
+    pool: Option<web::Data<PoolMonitor>>,
+    shedder: Option<web::Data<LoadShedder>>,
+) -> Result<HttpResponse> {
+    let mut body = String::new();
+    metric(&mut body, "http_requests_in_flight", "gauge", "Requests being served");
+    sample(&mut body, "http_requests_in_flight", "", lifecycle.in_flight());
+
+    if let Some(pool) = pool {
+        let stats = pool.stats();
+        metric(&mut body, "db_pool_connections", "gauge", "Open database connections by state");
+        sample(&mut body, "db_pool_connections", "{state=\"idle\"}", stats.idle);
+        sample(&mut body, "db_pool_connections", "{state=\"in_use\"}", stats.size.saturating_sub(stats.idle));
+        metric(&mut body, "db_pool_max_connections", "gauge", "Database connections the pool may open");
+        sample(&mut body, "db_pool_max_connections", "", stats.max_connections);
+        metric(&mut body, "db_pool_wait_seconds", "gauge", "Wait for a database connection at the last sample");
+        sample(&mut body, "db_pool_wait_seconds", "", stats.last_wait.as_secs_f64());
+        metric(&mut body, "db_pool_under_pressure", "gauge", "Whether pool wait is over the shedding threshold");
+        sample(&mut body, "db_pool_under_pressure", "", u8::from(stats.under_pressure));
+        metric(&mut body, "db_pool_slow_samples_total", "counter", "Pool wait samples at or over the threshold");
+        sample(&mut body, "db_pool_slow_samples_total", "", stats.slow_samples);
+    }
+
+    if let Some(shedder) = shedder {
+        let routes = shedder.route_stats();
+        metric(&mut body, "route_concurrency_limit", "gauge", "Concurrent requests each route currently allows");
+        for route in &routes {
+            sample(&mut body, "route_concurrency_limit", &route_label(&route.route, None), route.limit);
+        }
+        metric(&mut body, "route_requests_in_flight", "gauge", "Admitted requests being served by route");
+        for route in &routes {
+            sample(&mut body, "route_requests_in_flight", &route_label(&route.route, None), route.in_flight);
+        }
+        metric(&mut body, "shed_requests_total", "counter", "Requests refused with 503 by route and reason");
+        for route in &routes {
+            let pool_pressure = route_label(&route.route, Some("pool_pressure"));
+            sample(&mut body, "shed_requests_total", &pool_pressure, route.shed_pool_pressure);
+            let concurrency_limit = route_label(&route.route, Some("concurrency_limit"));
+            sample(&mut body, "shed_requests_total", &concurrency_limit, route.shed_concurrency_limit);
+        }
+    }
+
+    Ok(HttpResponse::Ok().content_type("text/plain; version=0.0.4; charset=utf-8").body(body))
+}
+
+fn metric(body: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(body, "# HELP tangy_mango_{} {}", name, help);
+    let _ = writeln!(body, "# TYPE tangy_mango_{} {}", name, kind);
+}
+
+fn sample(body: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(body, "tangy_mango_{}{} {}", name, labels, value);
+}
+
+fn route_label(route: &str, reason: Option<&str>) -> String {
+    let route = route.replace('\\', "\\\\").replace('"', "\\\"");
+    match reason {
+        Some(reason) => format!("{{route=\"{}\",reason=\"{}\"}}", route, reason),
+        None => format!("{{route=\"{}\"}}", route),
+    }
+}
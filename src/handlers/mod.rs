@@ -1 +1,64 @@
-pub mod user_handler;
\ No newline at end of file
+pub mod chaos_handler;
+pub mod conditional;
+pub mod email_change_handler;
+pub mod export;
+pub mod health_handler;
+pub mod metrics_handler;
+pub mod user_handler;
+pub mod webhook_handler;
+
+use actix_web::HttpResponse;
+use crate::errors::ServiceError;
+
+// Error response structure
+#[derive(serde::Serialize)]
+pub(crate) struct ErrorResponse {
+    pub(crate) error: String,
+}
+
+impl ErrorResponse {
+    pub(crate) fn new(error: impl Into<String>) -> Self {
+        Self { error: error.into() }
+    }
+}
+
+/// Maps a service error to its HTTP response, logging anything that is not the client's fault.
+pub(crate) fn service_error_response(context: &str, err: ServiceError) -> HttpResponse {
+    match err {
+        ServiceError::Validation(message) => HttpResponse::BadRequest().json(ErrorResponse::new(message)),
+        ServiceError::NotFound(_) => HttpResponse::NotFound().json(ErrorResponse::new(err.to_string())),
+        ServiceError::Conflict(message) => HttpResponse::Conflict().json(ErrorResponse::new(message)),
+        ServiceError::PreconditionFailed(message) => {
+            HttpResponse::PreconditionFailed().json(ErrorResponse::new(message))
+        }
+        ServiceError::Unprocessable(message) => {
+            HttpResponse::UnprocessableEntity().json(ErrorResponse::new(message))
+        }
+        ServiceError::Database(db_err) => {
+            log::error!("{}: {}", context, db_err);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Internal server error"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    #[test]
+    fn test_service_error_status_codes() {
+        let cases = [
+            (ServiceError::Validation("bad".to_string()), StatusCode::BAD_REQUEST),
+            (ServiceError::NotFound("Webhook"), StatusCode::NOT_FOUND),
+            (ServiceError::Conflict("taken".to_string()), StatusCode::CONFLICT),
+            (ServiceError::PreconditionFailed("stale".to_string()), StatusCode::PRECONDITION_FAILED),
+            (ServiceError::Unprocessable("bad patch".to_string()), StatusCode::UNPROCESSABLE_ENTITY),
+            (ServiceError::Database(sqlx::Error::PoolTimedOut), StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+
+        for (err, expected) in cases {
+            assert_eq!(service_error_response("test", err).status(), expected);
+        }
+    }
+}
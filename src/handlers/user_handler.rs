@@ -1,37 +1,128 @@
-use actix_web::{web, HttpResponse, Result};
+use actix_web::http::header::{self, ETag, LastModified};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, Result};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
 use uuid::Uuid;
-use crate::models::user::CreateUserRequest;
+use crate::models::idempotency::IdempotencyOutcome;
+use crate::models::user::{
+    BatchGetUsersRequest, CreateUserRequest, UpdateUserRequest, UserPatch, UserResponse, JSON_PATCH_CONTENT_TYPE,
+    MERGE_PATCH_CONTENT_TYPE,
+};
+use crate::models::user_event::UserEvent;
+use crate::models::user_export::{UserField, UserFilter};
+use crate::models::user_import::{
+    ImportFormat, ImportMode, ImportOptions, CSV_CONTENT_TYPE, NDJSON_CONTENT_TYPE,
+};
+use crate::services::idempotency_service::{self, IdempotencyService};
+use crate::services::user_event_stream::{UserEventStream, UserStreamMessage};
+use crate::services::user_import_service::UserImportService;
+use crate::services::user_search_service::UserSearchService;
 use crate::services::user_service::UserService;
+use super::export::{self, ExportEncoder};
+use super::{conditional, service_error_response, ErrorResponse};
 
-// Error response structure
-#[derive(serde::Serialize)]
-struct ErrorResponse {
-    error: String,
-}
+const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+const CREATE_USER_SCOPE: &str = "POST /api/v1/users";
+const ACCEPT_PATCH_HEADER: &str = "Accept-Patch";
+/// Rows already fetched are coalesced into response chunks of up to this many users.
+const EXPORT_BATCH_ROWS: usize = 500;
 
 pub async fn create_user(
     user_service: web::Data<UserService>,
+    idempotency_service: Option<web::Data<IdempotencyService>>,
+    http_request: HttpRequest,
     request: web::Json<CreateUserRequest>,
 ) -> Result<HttpResponse> {
-    match user_service.create_user(request.into_inner()).await {
-        Ok(user) => Ok(HttpResponse::Created().json(user)),
-        Err(err) => {
-            log::error!("Failed to create user: {}", err);
-            Ok(HttpResponse::BadRequest().json(ErrorResponse {
-                error: "Failed to create user".to_string(),
+    let request = request.into_inner();
+
+    let (idempotency_service, key) = match (idempotency_service, http_request.headers().get(IDEMPOTENCY_KEY_HEADER)) {
+        (Some(idempotency_service), Some(value)) => (idempotency_service, value),
+        // Mock mode keeps no keys, so there every request counts as new
+        _ => {
+            return Ok(match user_service.create_user(request).await {
+                Ok(user) => HttpResponse::Created().json(user),
+                Err(err) => service_error_response("Failed to create user", err),
+            })
+        }
+    };
+    let key = match key.to_str() {
+        Ok(key) => key.to_string(),
+        Err(_) => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+                error: "Idempotency-Key must contain only visible ASCII characters".to_string(),
             }))
         }
+    };
+
+    let fingerprint = idempotency_service::fingerprint(&request);
+    let outcome = match idempotency_service.begin(CREATE_USER_SCOPE, &key, &fingerprint).await {
+        Ok(outcome) => outcome,
+        Err(err) => return Ok(service_error_response("Failed to check idempotency key", err)),
+    };
+
+    let claimed_at = match outcome {
+        IdempotencyOutcome::Started { claimed_at } => claimed_at,
+        IdempotencyOutcome::Replay { status_code, body } => {
+            let status = StatusCode::from_u16(status_code).unwrap_or(StatusCode::OK);
+            return Ok(HttpResponse::build(status)
+                .insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"))
+                .json(body));
+        }
+        IdempotencyOutcome::Mismatch => {
+            return Ok(HttpResponse::UnprocessableEntity().json(ErrorResponse {
+                error: "Idempotency-Key was already used with a different request body".to_string(),
+            }))
+        }
+        IdempotencyOutcome::InProgress => {
+            return Ok(HttpResponse::Conflict().json(ErrorResponse {
+                error: "A request with this Idempotency-Key is still being processed".to_string(),
+            }))
+        }
+    };
+
+    let user = match user_service.create_user(request).await {
+        Ok(user) => user,
+        Err(err) => {
+            // A failed request created nothing and may simply be retried
+            if let Err(err) = idempotency_service.release(CREATE_USER_SCOPE, &key, claimed_at).await {
+                log::error!("Failed to release idempotency key {}: {}", key, err);
+            }
+            return Ok(service_error_response("Failed to create user", err));
+        }
+    };
+
+    let body = serde_json::json!(user);
+    if let Err(err) = idempotency_service
+        .complete(CREATE_USER_SCOPE, &key, claimed_at, StatusCode::CREATED.as_u16(), &body)
+        .await
+    {
+        log::error!("Failed to store idempotency key {}: {}", key, err);
     }
+
+    Ok(HttpResponse::Created().json(body))
 }
 
 pub async fn get_user(
     user_service: web::Data<UserService>,
+    http_request: HttpRequest,
     path: web::Path<Uuid>,
 ) -> Result<HttpResponse> {
     let user_id = path.into_inner();
     
     match user_service.get_user_by_id(user_id).await {
-        Ok(Some(user)) => Ok(HttpResponse::Ok().json(user)),
+        Ok(Some(user)) => {
+            let etag = conditional::etag_for(user.version);
+            if conditional::is_not_modified(&http_request, &etag, user.updated_at) {
+                return Ok(HttpResponse::NotModified()
+                    .insert_header(ETag(etag))
+                    .insert_header(LastModified(conditional::http_date(user.updated_at)))
+                    .finish());
+            }
+            Ok(versioned_user_response(StatusCode::OK, user))
+        }
         Ok(None) => Ok(HttpResponse::NotFound().json(ErrorResponse {
             error: "User not found".to_string(),
         })),
@@ -44,6 +135,153 @@ pub async fn get_user(
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct LookupQuery {
+    pub email: String,
+}
+
+/// Finds a user by email, ignoring case: `GET /api/v1/users/lookup?email=`.
+pub async fn lookup_user(
+    user_service: web::Data<UserService>,
+    query: web::Query<LookupQuery>,
+) -> Result<HttpResponse> {
+    match user_service.get_user_by_email(&query.email).await {
+        Ok(user) => Ok(versioned_user_response(StatusCode::OK, user)),
+        Err(err) => Ok(service_error_response("Failed to look up user", err)),
+    }
+}
+
+/// Looks up many users in one round trip: `POST /api/v1/users:batchGet`.
+pub async fn batch_get_users(
+    user_service: web::Data<UserService>,
+    request: web::Json<BatchGetUsersRequest>,
+) -> Result<HttpResponse> {
+    match user_service.batch_get_users(request.into_inner().ids).await {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => Ok(service_error_response("Failed to batch get users", err)),
+    }
+}
+
+pub async fn update_user(
+    user_service: web::Data<UserService>,
+    http_request: HttpRequest,
+    path: web::Path<Uuid>,
+    request: web::Json<UpdateUserRequest>,
+) -> Result<HttpResponse> {
+    let expected_versions = match conditional::required_versions(&http_request) {
+        Ok(versions) => versions,
+        Err(response) => return Ok(response),
+    };
+
+    match user_service
+        .update_user(path.into_inner(), request.into_inner(), expected_versions.as_deref())
+        .await
+    {
+        Ok(user) => Ok(versioned_user_response(StatusCode::OK, user)),
+        Err(err) => Ok(service_error_response("Failed to update user", err)),
+    }
+}
+
+pub async fn patch_user(
+    user_service: web::Data<UserService>,
+    http_request: HttpRequest,
+    path: web::Path<Uuid>,
+    body: web::Bytes,
+) -> Result<HttpResponse> {
+    let patch = match UserPatch::parse(&media_type(&http_request), &body) {
+        Some(Ok(patch)) => patch,
+        Some(Err(err)) => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+                error: format!("Malformed patch document: {}", err),
+            }))
+        }
+        None => {
+            return Ok(HttpResponse::UnsupportedMediaType()
+                .insert_header((
+                    ACCEPT_PATCH_HEADER,
+                    format!("{}, {}", MERGE_PATCH_CONTENT_TYPE, JSON_PATCH_CONTENT_TYPE),
+                ))
+                .json(ErrorResponse {
+                    error: format!(
+                        "Content-Type must be {} or {}",
+                        MERGE_PATCH_CONTENT_TYPE, JSON_PATCH_CONTENT_TYPE
+                    ),
+                }))
+        }
+    };
+
+    let expected_versions = match conditional::required_versions(&http_request) {
+        Ok(versions) => versions,
+        Err(response) => return Ok(response),
+    };
+
+    match user_service
+        .patch_user(path.into_inner(), patch, expected_versions.as_deref())
+        .await
+    {
+        Ok(user) => Ok(versioned_user_response(StatusCode::OK, user)),
+        Err(err) => Ok(service_error_response("Failed to patch user", err)),
+    }
+}
+
+/// The request's `Content-Type` without parameters, lowercased; empty when absent.
+fn media_type(http_request: &HttpRequest) -> String {
+    http_request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub mode: ImportMode,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Bulk-creates users from a CSV or NDJSON body, which is decoded while it streams in.
+/// An all-or-nothing import that rolled back answers `422`; otherwise the report comes
+/// back with `200`, listing any rows that were skipped.
+pub async fn import_users(
+    import_service: web::Data<UserImportService>,
+    http_request: HttpRequest,
+    query: web::Query<ImportQuery>,
+    payload: web::Payload,
+) -> Result<HttpResponse> {
+    let Some(format) = ImportFormat::from_content_type(&media_type(&http_request)) else {
+        return Ok(HttpResponse::UnsupportedMediaType().json(ErrorResponse::new(format!(
+            "Content-Type must be {} or {}",
+            CSV_CONTENT_TYPE, NDJSON_CONTENT_TYPE
+        ))));
+    };
+    let options = ImportOptions {
+        mode: query.mode,
+        dry_run: query.dry_run,
+    };
+
+    match import_service.import(format, options, payload).await {
+        Ok(report) => {
+            let rolled_back = report.mode == ImportMode::AllOrNothing && !report.dry_run && !report.is_clean();
+            let status = if rolled_back { StatusCode::UNPROCESSABLE_ENTITY } else { StatusCode::OK };
+            Ok(HttpResponse::build(status).json(report))
+        }
+        Err(err) => Ok(service_error_response("Failed to import users", err)),
+    }
+}
+
+/// A user body with the `ETag` and `Last-Modified` validators clients need for later
+/// conditional requests.
+fn versioned_user_response(status: StatusCode, user: UserResponse) -> HttpResponse {
+    HttpResponse::build(status)
+        .insert_header(ETag(conditional::etag_for(user.version)))
+        .insert_header(LastModified(conditional::http_date(user.updated_at)))
+        .json(user)
+}
+
 pub async fn get_users(
     user_service: web::Data<UserService>,
 ) -> Result<HttpResponse> {
@@ -58,6 +296,160 @@ pub async fn get_users(
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+pub async fn search_users(
+    search_service: web::Data<UserSearchService>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse> {
+    match search_service.search(&query.q, query.limit).await {
+        Ok(hits) => Ok(HttpResponse::Ok().json(hits)),
+        Err(err) => Ok(service_error_response("Failed to search users", err)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    /// Comma-separated field names; all fields when omitted
+    pub fields: Option<String>,
+    pub email_contains: Option<String>,
+    pub name_contains: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+/// Streams every matching user straight from a database cursor into a chunked response in
+/// the format negotiated from `Accept`.
+pub async fn export_users(
+    user_service: web::Data<UserService>,
+    http_request: HttpRequest,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse> {
+    let format = match export::negotiate_format(&http_request) {
+        Ok(format) => format,
+        Err(response) => return Ok(response),
+    };
+
+    let query = query.into_inner();
+    let fields = match query.fields.as_deref().map(UserField::parse_list) {
+        None => UserField::ALL.to_vec(),
+        Some(Ok(fields)) if !fields.is_empty() => fields,
+        Some(Ok(_)) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("fields cannot be empty"))),
+        Some(Err(unknown)) => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!("Unknown field '{}'", unknown))))
+        }
+    };
+    let filter = UserFilter {
+        email_contains: query.email_contains,
+        name_contains: query.name_contains,
+        created_after: query.created_after,
+        created_before: query.created_before,
+    };
+
+    let mut users = match user_service.export_users(&filter) {
+        Ok(users) => Box::pin(users),
+        Err(err) => return Ok(service_error_response("Failed to export users", err)),
+    };
+    // Wait for the first row so a failing query still gets a proper error status
+    let first = match users.next().await {
+        Some(Err(err)) => {
+            log::error!("Failed to export users: {}", err);
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Internal server error")));
+        }
+        first => first,
+    };
+
+    let encoder = ExportEncoder::new(format, fields);
+    let body = async_stream::stream! {
+        yield Ok(web::Bytes::from(encoder.header()));
+
+        let mut exported = 0u64;
+        let mut batches = stream::iter(first).chain(users).ready_chunks(EXPORT_BATCH_ROWS);
+        while let Some(batch) = batches.next().await {
+            let mut chunk = String::new();
+            for user in batch {
+                match user {
+                    Ok(user) => {
+                        chunk.push_str(&encoder.row(&user, exported));
+                        exported += 1;
+                    }
+                    Err(err) => {
+                        // The status line is already sent; failing the body aborts the response
+                        // so the client cannot mistake a truncated export for a complete one
+                        log::error!("User export failed after {} rows: {}", exported, err);
+                        yield Ok(web::Bytes::from(chunk));
+                        yield Err(actix_web::error::ErrorInternalServerError("Export failed"));
+                        return;
+                    }
+                }
+            }
+            yield Ok(web::Bytes::from(chunk));
+        }
+
+        yield Ok(web::Bytes::from(encoder.footer()));
+        log::info!("Exported {} users", exported);
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .streaming::<_, actix_web::Error>(body))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StreamQuery {
+    /// Fallback for clients that cannot set the `Last-Event-ID` header on their first connection.
+    pub last_event_id: Option<i64>,
+}
+
+pub async fn stream_users(
+    event_stream: web::Data<UserEventStream>,
+    request: HttpRequest,
+    query: web::Query<StreamQuery>,
+) -> Result<HttpResponse> {
+    let last_event_id = match request.headers().get(LAST_EVENT_ID_HEADER) {
+        Some(value) => match value.to_str().ok().and_then(|value| value.trim().parse::<i64>().ok()) {
+            Some(id) => Some(id),
+            None => {
+                return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+                    error: "Last-Event-ID must be an event id".to_string(),
+                }))
+            }
+        },
+        None => query.last_event_id,
+    };
+
+    let event_stream = event_stream.into_inner();
+    log::info!(
+        "User stream subscriber connected (resume from {:?}, {} already connected)",
+        last_event_id,
+        event_stream.subscriber_count()
+    );
+
+    let retry = stream::once(std::future::ready(format!("retry: {}\n\n", event_stream.retry_ms())));
+    let messages = event_stream.subscribe(last_event_id).map(|message| match message {
+        UserStreamMessage::Event(event) => format_sse_event(&event),
+        UserStreamMessage::KeepAlive => ": keep-alive\n\n".to_string(),
+    });
+    let body = retry
+        .chain(messages)
+        .map(|frame| Ok::<_, actix_web::Error>(web::Bytes::from(frame)));
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body))
+}
+
+/// Renders one event as an SSE frame; the JSON data is always a single line.
+fn format_sse_event(event: &UserEvent) -> String {
+    format!("id: {}\nevent: {}\ndata: {}\n\n", event.id, event.event_type, event.envelope())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -114,6 +506,46 @@ mod tests {
         assert!(request.is_err()); // Should fail due to missing required field
     }
 
+    #[test]
+    fn test_media_type_ignores_parameters_and_case() {
+        let request = actix_web::test::TestRequest::default()
+            .insert_header(("Content-Type", "Text/CSV; charset=utf-8"))
+            .to_http_request();
+        assert_eq!(media_type(&request), "text/csv");
+
+        let missing = actix_web::test::TestRequest::default().to_http_request();
+        assert_eq!(media_type(&missing), "");
+    }
+
+    #[test]
+    fn test_import_query_defaults() {
+        let query = web::Query::<ImportQuery>::from_query("").unwrap();
+        assert_eq!(query.mode, ImportMode::AllOrNothing);
+        assert!(!query.dry_run);
+
+        let query = web::Query::<ImportQuery>::from_query("mode=per_row&dry_run=true").unwrap();
+        assert_eq!(query.mode, ImportMode::PerRow);
+        assert!(query.dry_run);
+    }
+
+    #[test]
+    fn test_format_sse_event() {
+        let event = UserEvent {
+            id: 7,
+            txid: 1000,
+            event_type: "user.created".to_string(),
+            user_id: Uuid::new_v4(),
+            payload: serde_json::json!({"name": "Line\nBreak"}),
+            created_at: chrono::Utc::now(),
+        };
+
+        let frame = format_sse_event(&event);
+        assert!(frame.starts_with("id: 7\nevent: user.created\ndata: {"));
+        assert!(frame.ends_with("\n\n"));
+        // Newlines inside the payload must stay escaped, or they would split the data field
+        assert_eq!(frame.matches('\n').count(), 4);
+    }
+
     // Note: Full integration tests for handlers would require setting up
     // a test application with mock services, which is more complex.
     // The tests above focus on the core logic and data handling.
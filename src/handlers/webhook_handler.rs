@@ -0,0 +1,96 @@
+use actix_web::{web, HttpResponse, Result};
+use uuid::Uuid;
+use crate::models::webhook::CreateWebhookRequest;
+use crate::services::webhook_service::WebhookService;
+use super::service_error_response;
+
+pub async fn create_webhook(
+    webhook_service: web::Data<WebhookService>,
+    request: web::Json<CreateWebhookRequest>,
+) -> Result<HttpResponse> {
+    match webhook_service.create_subscription(request.into_inner()).await {
+        Ok(webhook) => Ok(HttpResponse::Created().json(webhook)),
+        Err(err) => Ok(service_error_response("Failed to create webhook", err)),
+    }
+}
+
+pub async fn get_webhooks(
+    webhook_service: web::Data<WebhookService>,
+) -> Result<HttpResponse> {
+    match webhook_service.get_all_subscriptions().await {
+        Ok(webhooks) => Ok(HttpResponse::Ok().json(webhooks)),
+        Err(err) => Ok(service_error_response("Failed to get webhooks", err)),
+    }
+}
+
+pub async fn get_webhook(
+    webhook_service: web::Data<WebhookService>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse> {
+    match webhook_service.get_subscription(path.into_inner()).await {
+        Ok(webhook) => Ok(HttpResponse::Ok().json(webhook)),
+        Err(err) => Ok(service_error_response("Failed to get webhook", err)),
+    }
+}
+
+pub async fn delete_webhook(
+    webhook_service: web::Data<WebhookService>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse> {
+    match webhook_service.delete_subscription(path.into_inner()).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(err) => Ok(service_error_response("Failed to delete webhook", err)),
+    }
+}
+
+pub async fn get_webhook_deliveries(
+    webhook_service: web::Data<WebhookService>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse> {
+    match webhook_service.get_delivery_history(path.into_inner()).await {
+        Ok(history) => Ok(HttpResponse::Ok().json(history)),
+        Err(err) => Ok(service_error_response("Failed to get webhook deliveries", err)),
+    }
+}
+
+pub async fn get_dead_letters(
+    webhook_service: web::Data<WebhookService>,
+) -> Result<HttpResponse> {
+    match webhook_service.get_dead_letters().await {
+        Ok(dead_letters) => Ok(HttpResponse::Ok().json(dead_letters)),
+        Err(err) => Ok(service_error_response("Failed to get dead letters", err)),
+    }
+}
+
+pub async fn replay_dead_letter(
+    webhook_service: web::Data<WebhookService>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse> {
+    match webhook_service.replay_dead_letter(path.into_inner()).await {
+        Ok(dead_letter) => Ok(HttpResponse::Accepted().json(dead_letter)),
+        Err(err) => Ok(service_error_response("Failed to replay dead letter", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::webhook::CreateWebhookRequest;
+
+    #[test]
+    fn test_create_webhook_request_deserialization() {
+        let json_data = r#"{"url": "https://example.com/hook", "event_types": ["user.created"], "secret": "0123456789abcdef"}"#;
+        let request: CreateWebhookRequest = serde_json::from_str(json_data).unwrap();
+
+        assert_eq!(request.url, "https://example.com/hook");
+        assert_eq!(request.event_types, vec!["user.created".to_string()]);
+        assert_eq!(request.secret.as_deref(), Some("0123456789abcdef"));
+    }
+
+    #[test]
+    fn test_create_webhook_request_requires_event_types() {
+        let json_data = r#"{"url": "https://example.com/hook"}"#;
+        let request: Result<CreateWebhookRequest, _> = serde_json::from_str(json_data);
+
+        assert!(request.is_err());
+    }
+}
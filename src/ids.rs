@@ -0,0 +1,71 @@
+//! Identifier generation injected into data access, so tests can predict the ids of new rows.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use uuid::Uuid;
+
+pub trait IdGenerator: Send + Sync {
+    fn new_id(&self) -> Uuid;
+}
+
+/// Time-ordered UUIDv7 ids. New rows land at the right-hand edge of the primary key's B-tree
+/// instead of at random pages, and ids sort by creation time. Ids generated within the same
+/// millisecond by one process still increase.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidV7Generator;
+
+impl IdGenerator for UuidV7Generator {
+    fn new_id(&self) -> Uuid {
+        Uuid::now_v7()
+    }
+}
+
+/// Hands out `00000000-0000-0000-0000-000000000001`, `...0002` and so on.
+#[derive(Debug)]
+pub struct SequentialIdGenerator {
+    next: AtomicU64,
+}
+
+impl SequentialIdGenerator {
+    pub fn new() -> Self {
+        Self::starting_at(1)
+    }
+
+    pub fn starting_at(first: u64) -> Self {
+        Self { next: AtomicU64::new(first) }
+    }
+}
+
+impl Default for SequentialIdGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdGenerator for SequentialIdGenerator {
+    fn new_id(&self) -> Uuid {
+        Uuid::from_u128(self.next.fetch_add(1, Ordering::Relaxed).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v7_ids_are_time_ordered() {
+        let generator = UuidV7Generator;
+        let ids: Vec<Uuid> = (0..1000).map(|_| generator.new_id()).collect();
+
+        assert!(ids.iter().all(|id| id.get_version_num() == 7));
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_sequential_ids_are_predictable() {
+        let generator = SequentialIdGenerator::new();
+
+        assert_eq!(generator.new_id().to_string(), "00000000-0000-0000-0000-000000000001");
+        assert_eq!(generator.new_id().to_string(), "00000000-0000-0000-0000-000000000002");
+    }
+}
@@ -1,14 +1,28 @@
 // Library module to expose functionality for integration tests
 
+pub mod app;
+pub mod chaos;
+pub mod cli;
+pub mod clock;
 pub mod config;
 pub mod db;
+pub mod errors;
+pub mod ids;
+pub mod load_shed;
+pub mod loadgen;
+pub mod mock;
 pub mod models;
+pub mod rate_limit;
 pub mod dao;
 pub mod services;
+pub mod shutdown;
+pub mod testing;
+pub mod tls;
 pub mod handlers;
 
 // Re-export commonly used types for easier testing
 pub use config::Settings;
 pub use models::user::{User, CreateUserRequest, UserResponse};
 pub use dao::user_dao::UserDao;
-pub use services::user_service::UserService;
\ No newline at end of file
+pub use services::user_service::UserService;
+pub use services::webhook_service::WebhookService;
\ No newline at end of file
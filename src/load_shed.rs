@@ -0,0 +1,361 @@
+//! Load shedding. Rather than queueing behind an exhausted connection pool until requests time
+//! out deep in the DAOs, the server refuses work it cannot take with a quick 503 and
+//! `Retry-After`: every request while pool wait is over its threshold, and any beyond its
+//! route's concurrency limit. Limits adapt per route, growing by about one per limit's worth of
+//! fast responses and shrinking by a tenth on each slow or failed one.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use crate::config::LoadSheddingConfig;
+use crate::db::DbPool;
+use crate::handlers::ErrorResponse;
+
+/// How much a slow or failed response shrinks its route's limit.
+const BACKOFF_RATIO: f64 = 0.9;
+
+/// Measures how long a connection takes to come out of the pool, the wait requests queue in
+/// before their first query.
+pub struct PoolMonitor {
+    pool: DbPool,
+    max_wait: Duration,
+    interval: Duration,
+    last_wait_micros: AtomicU64,
+    under_pressure: AtomicBool,
+    slow_samples: AtomicU64,
+}
+
+/// Pool state for `/metrics`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: u32,
+    pub max_connections: u32,
+    pub last_wait: Duration,
+    pub under_pressure: bool,
+    /// Samples that waited at least the threshold
+    pub slow_samples: u64,
+}
+
+impl PoolMonitor {
+    pub fn new(pool: DbPool, config: &LoadSheddingConfig) -> Self {
+        Self {
+            pool,
+            max_wait: Duration::from_millis(config.max_pool_wait_ms),
+            interval: Duration::from_millis(config.pool_sample_interval_ms),
+            last_wait_micros: AtomicU64::new(0),
+            under_pressure: AtomicBool::new(false),
+            slow_samples: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the last sample waited at least `max_pool_wait_ms` for a connection.
+    pub fn under_pressure(&self) -> bool {
+        self.under_pressure.load(Ordering::SeqCst)
+    }
+
+    /// Takes a connection and returns it straight away, queueing behind requests like any
+    /// other caller, and records the wait. No wait with a connection idle, so a quiet pool is
+    /// left alone. Gives up at the threshold, since that is all it needs to know.
+    pub async fn sample(&self) -> Duration {
+        let wait = if self.pool.num_idle() > 0 {
+            Duration::ZERO
+        } else {
+            let started = Instant::now();
+            match tokio::time::timeout(self.max_wait, self.pool.acquire()).await {
+                Ok(Ok(connection)) => drop(connection),
+                Ok(Err(err)) => log::warn!("Failed to sample database pool wait: {}", err),
+                Err(_) => {}
+            }
+            started.elapsed()
+        };
+
+        let slow = wait >= self.max_wait;
+        if slow {
+            self.slow_samples.fetch_add(1, Ordering::SeqCst);
+        }
+        if self.under_pressure.swap(slow, Ordering::SeqCst) != slow {
+            if slow {
+                log::warn!("Database pool under pressure, waited {:?} for a connection; shedding load", wait);
+            } else {
+                log::info!("Database pool pressure relieved");
+            }
+        }
+        self.last_wait_micros.store(wait.as_micros() as u64, Ordering::SeqCst);
+        wait
+    }
+
+    /// Samples every `pool_sample_interval_ms`, on every instance.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            self.sample().await;
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max_connections: self.pool.options().get_max_connections(),
+            last_wait: Duration::from_micros(self.last_wait_micros.load(Ordering::SeqCst)),
+            under_pressure: self.under_pressure(),
+            slow_samples: self.slow_samples.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Why a request was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shed {
+    PoolPressure,
+    ConcurrencyLimit,
+}
+
+impl Shed {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Shed::PoolPressure => "pool_pressure",
+            Shed::ConcurrencyLimit => "concurrency_limit",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RouteState {
+    limit: f64,
+    in_flight: u32,
+    shed_pool_pressure: u64,
+    shed_concurrency_limit: u64,
+}
+
+/// A route's concurrency for `/metrics`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteStats {
+    /// Method and route pattern, such as `GET /api/v1/users/{id}`
+    pub route: String,
+    pub limit: u32,
+    pub in_flight: u32,
+    pub shed_pool_pressure: u64,
+    pub shed_concurrency_limit: u64,
+}
+
+/// Admits or refuses requests by pool pressure and per-route concurrency limits.
+pub struct LoadShedder {
+    config: LoadSheddingConfig,
+    pool: Option<Arc<PoolMonitor>>,
+    routes: Mutex<HashMap<String, RouteState>>,
+}
+
+impl LoadShedder {
+    /// Without a pool, as in mock mode, only concurrency limits apply.
+    pub fn new(config: LoadSheddingConfig, pool: Option<Arc<PoolMonitor>>) -> Self {
+        Self { config, pool, routes: Mutex::new(HashMap::new()) }
+    }
+
+    /// Admits a request to `route` unless the pool is under pressure or the route is at its
+    /// limit. The permit holds its place until dropped.
+    pub fn admit(self: &Arc<Self>, route: &str) -> Result<Permit, Shed> {
+        let mut routes = self.routes.lock().unwrap();
+        let state = routes
+            .entry(route.to_string())
+            .or_insert_with(|| RouteState { limit: self.config.initial_limit as f64, ..RouteState::default() });
+
+        if self.pool.as_ref().is_some_and(|pool| pool.under_pressure()) {
+            state.shed_pool_pressure += 1;
+            return Err(Shed::PoolPressure);
+        }
+        if state.in_flight as f64 >= state.limit.floor() {
+            state.shed_concurrency_limit += 1;
+            return Err(Shed::ConcurrencyLimit);
+        }
+        state.in_flight += 1;
+        Ok(Permit { shedder: self.clone(), route: route.to_string(), started: Instant::now() })
+    }
+
+    /// Grows the route's limit after a fast response, or shrinks it after a slow or failed one.
+    fn adjust(&self, route: &str, elapsed: Duration, failed: bool) {
+        let overloaded = failed
+            || elapsed > Duration::from_millis(self.config.latency_target_ms)
+            || self.pool.as_ref().is_some_and(|pool| pool.under_pressure());
+        let (min, max) = (self.config.min_limit as f64, self.config.max_limit as f64);
+
+        let mut routes = self.routes.lock().unwrap();
+        if let Some(state) = routes.get_mut(route) {
+            state.limit = if overloaded {
+                (state.limit * BACKOFF_RATIO).max(min)
+            } else {
+                (state.limit + 1.0 / state.limit).min(max)
+            };
+        }
+    }
+
+    fn release(&self, route: &str) {
+        if let Some(state) = self.routes.lock().unwrap().get_mut(route) {
+            state.in_flight -= 1;
+        }
+    }
+
+    pub fn retry_after_secs(&self) -> u64 {
+        self.config.retry_after_secs
+    }
+
+    /// Every route seen so far, by name.
+    pub fn route_stats(&self) -> Vec<RouteStats> {
+        let routes = self.routes.lock().unwrap();
+        let mut stats: Vec<RouteStats> = routes
+            .iter()
+            .map(|(route, state)| RouteStats {
+                route: route.clone(),
+                limit: state.limit.floor() as u32,
+                in_flight: state.in_flight,
+                shed_pool_pressure: state.shed_pool_pressure,
+                shed_concurrency_limit: state.shed_concurrency_limit,
+            })
+            .collect();
+        stats.sort_by(|a, b| a.route.cmp(&b.route));
+        stats
+    }
+}
+
+/// A request's place within its route's limit.
+pub struct Permit {
+    shedder: Arc<LoadShedder>,
+    route: String,
+    started: Instant,
+}
+
+impl Permit {
+    /// Feeds the response time back into the route's limit; `failed` for server errors, which
+    /// include pool timeouts.
+    pub fn complete(self, failed: bool) {
+        self.shedder.adjust(&self.route, self.started.elapsed(), failed);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.shedder.release(&self.route);
+    }
+}
+
+/// Middleware shedding load through the registered [`LoadShedder`]. Health checks, admin
+/// endpoints, metrics and requests matching no route are always let through.
+pub(crate) async fn shed_load(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let path = request.path();
+    let exempt = path.starts_with("/health/") || path.starts_with("/admin/") || path == "/metrics";
+    let shedder = request.app_data::<web::Data<LoadShedder>>().cloned().filter(|_| !exempt);
+    let route = request.match_pattern().map(|pattern| format!("{} {}", request.method(), pattern));
+    let (Some(shedder), Some(route)) = (shedder, route) else {
+        return Ok(next.call(request).await?.map_into_left_body());
+    };
+    let shedder = shedder.into_inner();
+
+    match shedder.admit(&route) {
+        Ok(permit) => {
+            let response = next.call(request).await?;
+            permit.complete(response.status().is_server_error());
+            Ok(response.map_into_left_body())
+        }
+        Err(shed) => {
+            log::debug!("Shed {} ({})", route, shed.as_str());
+            let response = HttpResponse::ServiceUnavailable()
+                .insert_header((header::RETRY_AFTER, shedder.retry_after_secs().to_string()))
+                .json(ErrorResponse::new("Server overloaded, retry later"));
+            Ok(request.into_response(response).map_into_right_body())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shedder(initial_limit: u32) -> Arc<LoadShedder> {
+        let config = LoadSheddingConfig {
+            enabled: true,
+            initial_limit,
+            min_limit: 1,
+            max_limit: 4,
+            latency_target_ms: 60_000,
+            ..LoadSheddingConfig::default()
+        };
+        Arc::new(LoadShedder::new(config, None))
+    }
+
+    #[test]
+    fn test_routes_are_limited_separately() {
+        let shedder = shedder(2);
+        let first = shedder.admit("GET /api/v1/users").unwrap();
+        let _second = shedder.admit("GET /api/v1/users").unwrap();
+        assert_eq!(shedder.admit("GET /api/v1/users").err(), Some(Shed::ConcurrencyLimit));
+        assert!(shedder.admit("POST /api/v1/users").is_ok());
+
+        drop(first);
+        assert!(shedder.admit("GET /api/v1/users").is_ok());
+        let stats = shedder.route_stats();
+        assert_eq!(stats[0].route, "GET /api/v1/users");
+        assert_eq!((stats[0].in_flight, stats[0].shed_concurrency_limit), (1, 1));
+        assert_eq!(stats[1].in_flight, 0);
+    }
+
+    #[test]
+    fn test_limits_grow_when_fast_and_shrink_on_failures() {
+        let shedder = shedder(2);
+        let limit = || shedder.route_stats()[0].limit;
+
+        // About one more per limit's worth of fast responses, up to the maximum
+        shedder.admit("GET /api/v1/users").unwrap().complete(false);
+        shedder.admit("GET /api/v1/users").unwrap().complete(false);
+        assert_eq!(limit(), 2);
+        shedder.admit("GET /api/v1/users").unwrap().complete(false);
+        assert_eq!(limit(), 3);
+        for _ in 0..20 {
+            shedder.admit("GET /api/v1/users").unwrap().complete(false);
+        }
+        assert_eq!(limit(), 4);
+
+        // A tenth less per failure, down to the minimum
+        shedder.admit("GET /api/v1/users").unwrap().complete(true);
+        assert_eq!(limit(), 3);
+        for _ in 0..30 {
+            shedder.admit("GET /api/v1/users").unwrap().complete(true);
+        }
+        assert_eq!(limit(), 1);
+    }
+
+    crate::db_test!(async fn test_pool_monitor_reports_pressure_while_connections_are_held(pool: PgPool) {
+        let config = LoadSheddingConfig { max_pool_wait_ms: 50, ..LoadSheddingConfig::default() };
+        let monitor = Arc::new(PoolMonitor::new(pool.clone(), &config));
+        let shedder = Arc::new(LoadShedder::new(config, Some(monitor.clone())));
+
+        monitor.sample().await;
+        assert!(!monitor.under_pressure());
+
+        let max_connections = pool.options().get_max_connections();
+        let mut held = Vec::new();
+        for _ in 0..max_connections {
+            held.push(pool.acquire().await.unwrap());
+        }
+        assert!(monitor.sample().await >= Duration::from_millis(50));
+        assert!(monitor.under_pressure());
+        assert_eq!(shedder.admit("GET /api/v1/users").err(), Some(Shed::PoolPressure));
+        let stats = monitor.stats();
+        assert_eq!((stats.idle, stats.max_connections, stats.slow_samples), (0, max_connections, 1));
+
+        drop(held);
+        monitor.sample().await;
+        assert!(!monitor.under_pressure());
+        assert!(shedder.admit("GET /api/v1/users").is_ok());
+    });
+}
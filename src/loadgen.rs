@@ -0,0 +1,345 @@
+//! Load generation against a running server, for throughput and latency baselines. The
+//! `loadgen` binary is the command line around [`run`]; unlike `test-api.sh`, which checks
+//! that each endpoint answers, it keeps a fixed number of requests in flight and measures.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+use serde_json::json;
+use uuid::Uuid;
+
+/// Users created before measuring, for the scenarios that read them back.
+const SETUP_USERS: usize = 100;
+
+/// What each request does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scenario {
+    /// `POST /api/v1/users` with a new email each time
+    Create,
+    /// `GET /api/v1/users/{id}` over users created during setup
+    Get,
+    /// `GET /api/v1/users`, whose cost grows with the number of users
+    List,
+    /// 80% get, 10% list and 10% create, interleaved
+    Mixed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Create,
+    Get,
+    List,
+}
+
+impl Scenario {
+    /// The operation for the run's `n`th request.
+    fn operation(self, n: u64) -> Operation {
+        match self {
+            Scenario::Create => Operation::Create,
+            Scenario::Get => Operation::Get,
+            Scenario::List => Operation::List,
+            Scenario::Mixed => match n % 10 {
+                0 => Operation::Create,
+                1 => Operation::List,
+                _ => Operation::Get,
+            },
+        }
+    }
+
+    fn needs_users(self) -> bool {
+        matches!(self, Scenario::Get | Scenario::Mixed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    /// Server root, such as `http://localhost:8080`
+    pub base_url: String,
+    pub scenario: Scenario,
+    /// Requests kept in flight at once, each on its own connection
+    pub concurrency: usize,
+    /// How long to send requests for
+    pub duration: Duration,
+    /// Stops earlier once this many requests have been sent
+    pub max_requests: Option<u64>,
+}
+
+/// Latency percentiles in milliseconds, over every request sent, failed ones included.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub p999: f64,
+    pub max: f64,
+}
+
+impl LatencySummary {
+    fn from_micros(mut samples: Vec<u64>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort_unstable();
+        let millis = |micros: u64| micros as f64 / 1000.0;
+        let total: u64 = samples.iter().sum();
+        // Nearest rank: the smallest sample with at least `per_mille` of samples at or below
+        // it, in integers so that 99.9% of 1000 samples is exactly 999
+        let percentile = |per_mille: usize| {
+            let rank = (per_mille * samples.len()).div_ceil(1000);
+            millis(samples[rank.clamp(1, samples.len()) - 1])
+        };
+
+        Self {
+            min: millis(samples[0]),
+            mean: total as f64 / samples.len() as f64 / 1000.0,
+            p50: percentile(500),
+            p90: percentile(900),
+            p99: percentile(990),
+            p999: percentile(999),
+            max: millis(samples[samples.len() - 1]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadReport {
+    pub scenario: Scenario,
+    pub concurrency: usize,
+    pub requests: u64,
+    /// Failed requests by status code, or `transport` when no response arrived
+    pub failures: BTreeMap<String, u64>,
+    pub elapsed_secs: f64,
+    /// Requests completed per second, failed ones included
+    pub throughput: f64,
+    pub latency_ms: LatencySummary,
+}
+
+impl LoadReport {
+    pub fn failed(&self) -> u64 {
+        self.failures.values().sum()
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scenario = serde_json::to_value(self.scenario).map_err(|_| fmt::Error)?;
+        writeln!(
+            f,
+            "Scenario {} with {} connections for {:.1}s",
+            scenario.as_str().unwrap_or_default(),
+            self.concurrency,
+            self.elapsed_secs
+        )?;
+        write!(f, "Requests:   {}", self.requests)?;
+        if self.failures.is_empty() {
+            writeln!(f)?;
+        } else {
+            let failures: Vec<String> = self.failures.iter().map(|(kind, count)| format!("{} {}", count, kind)).collect();
+            writeln!(f, " ({} failed: {})", self.failed(), failures.join(", "))?;
+        }
+        writeln!(f, "Throughput: {:.1} req/s", self.throughput)?;
+        let latency = &self.latency_ms;
+        write!(
+            f,
+            "Latency ms: min {:.2}  mean {:.2}  p50 {:.2}  p90 {:.2}  p99 {:.2}  p99.9 {:.2}  max {:.2}",
+            latency.min, latency.mean, latency.p50, latency.p90, latency.p99, latency.p999, latency.max
+        )
+    }
+}
+
+/// What one worker measured.
+#[derive(Default)]
+struct WorkerResult {
+    latencies: Vec<u64>,
+    failures: BTreeMap<String, u64>,
+}
+
+struct Target {
+    client: reqwest::Client,
+    users_url: String,
+    /// Distinguishes this run's emails from earlier runs against the same server
+    run_id: String,
+    user_ids: Vec<Uuid>,
+}
+
+impl Target {
+    /// Request number `n`, sent by `worker`. Workers are numbered from 1; 0 creates the setup
+    /// users.
+    fn request(&self, operation: Operation, worker: usize, n: u64) -> reqwest::RequestBuilder {
+        match operation {
+            Operation::Create => self.client.post(&self.users_url).json(&json!({
+                "email": format!("loadgen-{}-{}-{}@example.com", self.run_id, worker, n),
+                "name": "Load Test",
+            })),
+            Operation::Get => {
+                let id = self.user_ids[(worker + n as usize) % self.user_ids.len()];
+                self.client.get(format!("{}/{}", self.users_url, id))
+            }
+            Operation::List => self.client.get(&self.users_url),
+        }
+    }
+}
+
+/// Sends requests as `options` say and reports what was measured. Fails only when the server
+/// cannot be set up for the scenario; failed requests during the run are counted instead.
+pub async fn run(options: &LoadOptions) -> anyhow::Result<LoadReport> {
+    if options.concurrency == 0 {
+        bail!("concurrency must be at least 1");
+    }
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(options.concurrency)
+        .build()
+        .context("Failed to build HTTP client")?;
+    let mut target = Target {
+        client,
+        users_url: format!("{}/api/v1/users", options.base_url.trim_end_matches('/')),
+        run_id: Uuid::new_v4().simple().to_string()[..8].to_string(),
+        user_ids: Vec::new(),
+    };
+    if options.scenario.needs_users() {
+        target.user_ids = create_setup_users(&target).await?;
+    }
+
+    let target = Arc::new(target);
+    let sent = Arc::new(AtomicU64::new(0));
+    let started = Instant::now();
+    let deadline = started + options.duration;
+    let workers: Vec<_> = (0..options.concurrency)
+        .map(|index| {
+            let worker = index + 1;
+            let (target, sent, options) = (target.clone(), sent.clone(), options.clone());
+            tokio::spawn(async move {
+                let mut result = WorkerResult::default();
+                while Instant::now() < deadline {
+                    // Numbered across workers, so the mixed scenario keeps its proportions
+                    // however requests spread over them
+                    let n = sent.fetch_add(1, Ordering::Relaxed);
+                    if options.max_requests.is_some_and(|max| n >= max) {
+                        break;
+                    }
+
+                    let request = target.request(options.scenario.operation(n), worker, n);
+                    let sent_at = Instant::now();
+                    let failure = match request.send().await {
+                        // Read the body too, so the latency covers the whole response
+                        Ok(response) => match (response.status(), response.bytes().await) {
+                            (status, Ok(_)) if status.is_success() => None,
+                            (status, Ok(_)) => Some(status.as_u16().to_string()),
+                            (_, Err(_)) => Some("transport".to_string()),
+                        },
+                        Err(_) => Some("transport".to_string()),
+                    };
+                    result.latencies.push(sent_at.elapsed().as_micros() as u64);
+                    if let Some(kind) = failure {
+                        *result.failures.entry(kind).or_default() += 1;
+                    }
+                }
+                result
+            })
+        })
+        .collect();
+
+    let mut latencies = Vec::new();
+    let mut failures = BTreeMap::new();
+    for worker in workers {
+        let result = worker.await.context("Load worker panicked")?;
+        latencies.extend(result.latencies);
+        for (kind, count) in result.failures {
+            *failures.entry(kind).or_default() += count;
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    Ok(LoadReport {
+        scenario: options.scenario,
+        concurrency: options.concurrency,
+        requests: latencies.len() as u64,
+        failures,
+        elapsed_secs: elapsed,
+        throughput: if elapsed > 0.0 { latencies.len() as f64 / elapsed } else { 0.0 },
+        latency_ms: LatencySummary::from_micros(latencies),
+    })
+}
+
+async fn create_setup_users(target: &Target) -> anyhow::Result<Vec<Uuid>> {
+    #[derive(serde::Deserialize)]
+    struct Created {
+        id: Uuid,
+    }
+
+    let mut ids = Vec::with_capacity(SETUP_USERS);
+    for n in 0..SETUP_USERS {
+        let response = target
+            .request(Operation::Create, 0, n as u64)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", target.users_url))?;
+        if !response.status().is_success() {
+            bail!("Creating setup users failed with {}", response.status());
+        }
+        ids.push(response.json::<Created>().await.context("Unexpected response creating a user")?.id);
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_summary_uses_nearest_rank() {
+        let summary = LatencySummary::from_micros((1..=1000).rev().map(|n| n * 1000).collect());
+        assert_eq!(
+            summary,
+            LatencySummary {
+                min: 1.0,
+                mean: 500.5,
+                p50: 500.0,
+                p90: 900.0,
+                p99: 990.0,
+                p999: 999.0,
+                max: 1000.0,
+            }
+        );
+
+        let single = LatencySummary::from_micros(vec![1500]);
+        assert_eq!((single.p50, single.p999, single.max), (1.5, 1.5, 1.5));
+        assert_eq!(LatencySummary::from_micros(Vec::new()), LatencySummary::default());
+    }
+
+    #[test]
+    fn test_mixed_scenario_operations() {
+        let operations: Vec<Operation> = (0..10).map(|n| Scenario::Mixed.operation(n)).collect();
+        assert_eq!(operations.iter().filter(|&&op| op == Operation::Create).count(), 1);
+        assert_eq!(operations.iter().filter(|&&op| op == Operation::List).count(), 1);
+        assert_eq!(operations.iter().filter(|&&op| op == Operation::Get).count(), 8);
+        assert!(Scenario::Mixed.needs_users() && !Scenario::Create.needs_users());
+    }
+
+    #[test]
+    fn test_report_display() {
+        let report = LoadReport {
+            scenario: Scenario::Mixed,
+            concurrency: 8,
+            requests: 1000,
+            failures: BTreeMap::from([("500".to_string(), 3), ("transport".to_string(), 1)]),
+            elapsed_secs: 2.0,
+            throughput: 500.0,
+            latency_ms: LatencySummary::from_micros(vec![1000, 2000]),
+        };
+
+        let text = report.to_string();
+        assert!(text.starts_with("Scenario mixed with 8 connections for 2.0s\n"));
+        assert!(text.contains("Requests:   1000 (4 failed: 3 500, 1 transport)\n"));
+        assert!(text.contains("Throughput: 500.0 req/s\n"));
+        assert!(text.contains("p50 1.00  p90 2.00"));
+    }
+}
@@ -1,64 +1,254 @@
-mod config;
-mod db;
-mod models;
-mod dao;
-mod services;
-mod handlers;
-
-use actix_web::{web, App, HttpServer, middleware::Logger};
+use clap::Parser;
+use std::net::TcpListener;
 use std::sync::Arc;
 
-use crate::config::Settings;
-use crate::dao::user_dao::UserDao;
-use crate::services::user_service::UserService;
-use crate::handlers::user_handler;
+use tangy_mango::app::{self, AppState};
+use tangy_mango::cli::{self, Cli, Command, ServeArgs};
+use tangy_mango::config::Settings;
+use tangy_mango::dao::rate_limit_dao::RateLimitDao;
+use tangy_mango::dao::user_dao::UserDao;
+use tangy_mango::dao::webhook_dao::WebhookDao;
+use tangy_mango::db::{self, DbPool, LeaderElection, MigrationState};
+use tangy_mango::mock;
+use tangy_mango::rate_limit::{self, RateLimitStoreKind};
+use tangy_mango::services::webhook_dispatcher::WebhookDispatcher;
+use tangy_mango::shutdown::{self, BackgroundJobs};
+use tangy_mango::tls::{self, CertReloader};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+
     // Initialize logger
     env_logger::init();
 
     // Load configuration
-    let settings = Settings::new().expect("Failed to load configuration");
+    let settings = match Settings::new() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("Failed to load configuration: {}", err);
+            std::process::exit(1);
+        }
+    };
     log::info!("Configuration loaded successfully");
 
-    // Create database connection pool
-    let pool = db::create_pool(&settings)
+    match cli.command.unwrap_or(Command::Serve(ServeArgs::default())) {
+        Command::Serve(args) if args.mock => {
+            let store = match mock::load_store(args.mock_data.as_deref()) {
+                Ok(store) => store,
+                Err(err) => {
+                    eprintln!("Failed to load mock data: {:#}", err);
+                    std::process::exit(1);
+                }
+            };
+            check_limits(&settings);
+            let state = AppState::mock(&settings, store, args.mock_faults());
+            serve_mock(settings, state).await
+        }
+        Command::Serve(args) => {
+            check_chaos(&settings);
+            check_limits(&settings);
+            let pool = connect(&settings).await;
+            if args.no_migrate {
+                warn_if_pending_migrations(&pool).await;
+            } else {
+                db::run_migrations(&pool)
+                    .await
+                    .expect("Failed to run database migrations");
+                log::info!("Database migrations completed");
+            }
+            serve(settings, pool).await
+        }
+        Command::Import(args) => match cli::run_import(&settings, connect(&settings).await, &args).await {
+            Ok(report) => {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+                if !report.is_clean() {
+                    std::process::exit(1);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                eprintln!("Import failed: {:#}", err);
+                std::process::exit(2);
+            }
+        },
+        Command::Migrate { action } => {
+            exit_on_error("Migration failed", cli::run_migrate(&connect(&settings).await, &action).await)
+        }
+        Command::User { action } => exit_on_error("Error", cli::run_user(connect(&settings).await, &action).await),
+        // Reports an unreachable database as a problem instead of failing to start
+        Command::Config { action } => exit_on_error("Error", cli::run_config(&settings, &action).await),
+        Command::Seed(args) => match cli::run_seed(connect(&settings).await, &args).await {
+            Ok(report) => {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+                Ok(())
+            }
+            Err(err) => exit_on_error("Seeding failed", Err(err)),
+        },
+    }
+}
+
+async fn connect(settings: &Settings) -> DbPool {
+    let pool = db::create_pool(settings)
         .await
         .expect("Failed to create database pool");
     log::info!("Database connection pool created");
+    pool
+}
 
-    // Run migrations
-    db::run_migrations(&pool)
-        .await
-        .expect("Failed to run database migrations");
-    log::info!("Database migrations completed");
+fn exit_on_error(context: &str, result: anyhow::Result<()>) -> std::io::Result<()> {
+    if let Err(err) = result {
+        eprintln!("{}: {:#}", context, err);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Stops the server from starting if any setting under `section` has a problem.
+fn exit_on_problems(settings: &Settings, section: &str, name: &str) {
+    let prefix = format!("{}.", section);
+    let problems: Vec<String> = settings
+        .problems()
+        .into_iter()
+        .filter(|problem| problem.starts_with(&prefix))
+        .collect();
+    if !problems.is_empty() {
+        eprintln!("Invalid {} configuration: {}", name, problems.join("; "));
+        std::process::exit(1);
+    }
+}
+
+/// Fault injection with an open admin endpoint or impossible rules must not go unnoticed, so
+/// either stops the server from starting.
+fn check_chaos(settings: &Settings) {
+    if !settings.chaos.enabled {
+        return;
+    }
+    exit_on_problems(settings, "chaos", "chaos");
+    log::warn!("Fault injection is enabled; see /admin/chaos");
+}
+
+/// Quotas that could never be met, or rules that silently match nothing, stop the server from
+/// starting while rate limiting is enabled. Pool sampling runs whether or not load is shed, so
+/// its settings are always checked.
+fn check_limits(settings: &Settings) {
+    if settings.rate_limit.enabled {
+        exit_on_problems(settings, "rate_limit", "rate limit");
+    }
+    exit_on_problems(settings, "load_shedding", "load shedding");
+}
+
+/// Loads the certificate and key for HTTPS, if configured, and keeps them current in the
+/// background. An unusable TLS configuration stops the server from starting.
+fn setup_tls(settings: &Settings, jobs: &mut BackgroundJobs) -> Option<rustls::ServerConfig> {
+    let config = settings.server.tls.as_ref()?;
+    exit_on_problems(settings, "server.tls", "TLS");
+
+    let server_config = CertReloader::load(config).and_then(|certs| {
+        let server_config = tls::server_config(config, certs.clone())?;
+        jobs.spawn("tls-reload", certs.run());
+        Ok(server_config)
+    });
+    match server_config {
+        Ok(server_config) => Some(server_config),
+        Err(err) => {
+            eprintln!("Failed to set up TLS: {:#}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn scheme(tls: &Option<rustls::ServerConfig>) -> &'static str {
+    if tls.is_some() {
+        "https"
+    } else {
+        "http"
+    }
+}
 
-    // Initialize DAOs and Services
-    let user_dao = UserDao::new(pool.clone());
-    let user_service = Arc::new(UserService::new(user_dao));
+/// With `--no-migrate` another process owns migrations; starting ahead of them is allowed, as
+/// during a rolling deploy, but worth knowing about.
+async fn warn_if_pending_migrations(pool: &DbPool) {
+    match db::migration_status(pool).await {
+        Ok(statuses) => {
+            let pending = statuses
+                .iter()
+                .filter(|status| status.state == MigrationState::Pending)
+                .count();
+            if pending > 0 {
+                log::warn!("Starting with {} pending migrations (--no-migrate)", pending);
+            }
+        }
+        Err(err) => log::warn!("Failed to check migration status: {}", err),
+    }
+}
 
+async fn serve(settings: Settings, pool: DbPool) -> std::io::Result<()> {
+    match UserDao::new(pool.clone()).count_email_collisions().await {
+        Ok(0) => {}
+        Ok(count) => log::warn!(
+            "Found {} users whose email collides with an older account; see the email_collisions table",
+            count
+        ),
+        Err(err) => log::warn!("Failed to check for email collisions: {}", err),
+    }
+    let state = AppState::new(&settings, &pool);
+    let mut jobs = BackgroundJobs::new();
+
+    // Relay the outbox and send webhooks in the background, on one instance at a time
+    let dispatcher = Arc::new(WebhookDispatcher::new(WebhookDao::new(pool.clone()), settings.webhooks.clone()));
+    let election = LeaderElection::new(pool.clone(), "webhook-dispatcher", &settings.leader_election);
+    jobs.spawn("webhook-dispatcher", election.run(move || dispatcher.clone().run()));
+
+    let services = state.postgres.clone().expect("Postgres services are set up when serving from Postgres");
+
+    // Start the user change feed listener in the background
+    jobs.spawn("user-event-listener", services.user_event_stream.clone().run());
+
+    // Expire old idempotency keys in the background, on one instance at a time
+    let cleanup_service = services.idempotency_service.clone();
+    let election = LeaderElection::new(pool.clone(), "idempotency-cleanup", &settings.leader_election);
+    jobs.spawn("idempotency-cleanup", election.run(move || cleanup_service.clone().run_cleanup()));
+
+    // Measure database pool wait on every instance, for load shedding and /metrics
+    if let Some(pool_monitor) = &state.pool_monitor {
+        jobs.spawn("pool-monitor", pool_monitor.clone().run());
+    }
+
+    // Delete refilled rate limit buckets in the background, on one instance at a time
+    if settings.rate_limit.enabled && settings.rate_limit.store == RateLimitStoreKind::Postgres {
+        let dao = RateLimitDao::new(pool.clone());
+        let interval = std::time::Duration::from_secs(settings.rate_limit.cleanup_interval_secs);
+        let election = LeaderElection::new(pool.clone(), "rate-limit-cleanup", &settings.leader_election);
+        jobs.spawn("rate-limit-cleanup", election.run(move || rate_limit::run_cleanup(dao.clone(), interval)));
+    }
+
+    let tls = setup_tls(&settings, &mut jobs);
     let server_host = settings.server.host.clone();
     let server_port = settings.server.port;
 
-    log::info!("Starting server at {}:{}", server_host, server_port);
+    log::info!("Starting server at {}://{}:{}", scheme(&tls), server_host, server_port);
 
     // Start HTTP server
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::from(user_service.clone()))
-            .wrap(Logger::default())
-            .service(
-                web::scope("/api/v1")
-                    .service(
-                        web::scope("/users")
-                            .route("", web::post().to(user_handler::create_user))
-                            .route("", web::get().to(user_handler::get_users))
-                            .route("/{id}", web::get().to(user_handler::get_user))
-                    )
-            )
-    })
-    .bind(format!("{}:{}", server_host, server_port))?
-    .run()
-    .await
-}
\ No newline at end of file
+    let listener = TcpListener::bind(format!("{}:{}", server_host, server_port))?;
+    let server = app::run_server(state.clone(), listener, None, tls)?;
+    shutdown::serve(server, &state, jobs, Some(pool), shutdown::signal()).await?;
+    Ok(())
+}
+
+/// Serves `--mock` mode, with no database and no background jobs beyond certificate reloading.
+async fn serve_mock(settings: Settings, state: AppState) -> std::io::Result<()> {
+    let mut jobs = BackgroundJobs::new();
+    let tls = setup_tls(&settings, &mut jobs);
+    let address = format!("{}:{}", settings.server.host, settings.server.port);
+    log::warn!(
+        "Starting mock server at {}://{}; data lives in memory and is lost on exit",
+        scheme(&tls),
+        address
+    );
+
+    let listener = TcpListener::bind(address)?;
+    let server = app::run_server(state.clone(), listener, None, tls)?;
+    shutdown::serve(server, &state, jobs, None, shutdown::signal()).await?;
+    Ok(())
+}
@@ -0,0 +1,123 @@
+//! `serve --mock`: the API over an in-memory user store, so frontend work needs neither
+//! Postgres nor Docker. Routes whose data only Postgres can hold answer 503; latency and
+//! failures to exercise loading and error states come from the [chaos](crate::chaos) layer.
+
+use std::path::Path;
+
+use actix_web::HttpResponse;
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::handlers::ErrorResponse;
+use crate::ids::{IdGenerator, UuidV7Generator};
+use crate::models::email::normalize_email;
+use crate::models::user::User;
+use crate::services::memory_user_store::MemoryUserStore;
+use crate::services::user_seeder::{generate_users, SeedOptions};
+
+/// Users generated for the store when no data file is given.
+const DEFAULT_MOCK_USERS: usize = 50;
+
+/// A user as read from a mock data file. Only `email` and `name` are required, so the output
+/// of `GET /api/v1/users` works as well as a hand-written list.
+#[derive(Debug, Deserialize)]
+struct MockUser {
+    id: Option<Uuid>,
+    email: String,
+    name: String,
+    version: Option<i64>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    suspended_at: Option<DateTime<Utc>>,
+}
+
+/// Builds the mock store from a JSON array of users, or from generated users without a file.
+pub fn load_store(data: Option<&Path>) -> anyhow::Result<MemoryUserStore> {
+    let users = match data {
+        Some(path) => {
+            let json = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            parse_users(&json, Utc::now()).with_context(|| format!("Invalid mock data in {}", path.display()))?
+        }
+        None => generate_users(&SeedOptions::new(DEFAULT_MOCK_USERS, 1, Utc::now())),
+    };
+
+    let store = MemoryUserStore::new();
+    store.insert_users(users).context("Mock data has duplicate users")?;
+    Ok(store)
+}
+
+/// Parses mock data, filling in missing ids and timestamps as creation would.
+fn parse_users(json: &str, now: DateTime<Utc>) -> anyhow::Result<Vec<User>> {
+    let users: Vec<MockUser> = serde_json::from_str(json)?;
+    let ids = UuidV7Generator;
+
+    users
+        .into_iter()
+        .enumerate()
+        .map(|(index, user)| {
+            let email = normalize_email(&user.email).map_err(|err| anyhow::anyhow!("user {}: {}", index, err))?;
+            if user.name.trim().is_empty() {
+                bail!("user {}: name cannot be empty", index);
+            }
+            let created_at = user.created_at.unwrap_or(now);
+            Ok(User {
+                id: user.id.unwrap_or_else(|| ids.new_id()),
+                email,
+                name: user.name,
+                version: user.version.unwrap_or(1),
+                created_at,
+                updated_at: user.updated_at.unwrap_or(created_at),
+                suspended_at: user.suspended_at,
+            })
+        })
+        .collect()
+}
+
+/// Stands in for routes whose data only Postgres can hold.
+pub(crate) async fn unavailable() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(ErrorResponse::new("Not available in mock mode"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_users_fills_in_defaults() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+        let users = parse_users(
+            r#"[
+                {"email": " Ada@EXAMPLE.com", "name": "Ada Lovelace"},
+                {"id": "01890a5d-ac96-774b-bcce-b302099a8057", "email": "alan@example.com", "name": "Alan Turing",
+                 "version": 4, "created_at": "2024-01-15T09:30:00Z", "suspended_at": "2024-02-01T00:00:00Z"}
+            ]"#,
+            now,
+        )
+        .unwrap();
+
+        assert_eq!(users[0].email, "Ada@example.com");
+        assert_eq!((users[0].version, users[0].created_at, users[0].updated_at), (1, now, now));
+        assert_eq!(users[0].id.get_version_num(), 7);
+
+        assert_eq!(users[1].id.to_string(), "01890a5d-ac96-774b-bcce-b302099a8057");
+        assert_eq!(users[1].version, 4);
+        assert_eq!(users[1].updated_at, Utc.with_ymd_and_hms(2024, 1, 15, 9, 30, 0).unwrap());
+        assert!(users[1].suspended_at.is_some());
+    }
+
+    #[test]
+    fn test_parse_users_rejects_invalid_users() {
+        let now = Utc::now();
+        assert!(parse_users(r#"[{"email": "not-an-email", "name": "A"}]"#, now).is_err());
+        assert!(parse_users(r#"[{"email": "a@example.com", "name": " "}]"#, now).is_err());
+        assert!(parse_users(r#"{"users": []}"#, now).is_err());
+    }
+
+    #[test]
+    fn test_generated_store_without_data() {
+        let store = load_store(None).unwrap();
+        assert_eq!(store.len(), DEFAULT_MOCK_USERS);
+    }
+}
@@ -0,0 +1,81 @@
+//! Canonical form of email addresses.
+//!
+//! Addresses are stored normalized: surrounding whitespace removed and the domain lowercased
+//! and converted to its ASCII (punycode) form. The local part keeps its case as entered, but
+//! uniqueness and lookups compare the whole address ignoring ASCII case through the
+//! `users.email_normalized` column, so `Bob@Example.com` and `bob@example.com` are one account.
+
+/// Longest address that fits a forward or reverse path (RFC 5321).
+const MAX_EMAIL_LENGTH: usize = 254;
+
+/// Normalizes an address for storage, or explains why it is not a usable address.
+pub fn normalize_email(raw: &str) -> Result<String, String> {
+    let email = raw.trim();
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return Err("Email must contain '@'".to_string());
+    };
+    if local.is_empty() || domain.is_empty() {
+        return Err("Email must have a local part and a domain".to_string());
+    }
+    if email.chars().any(char::is_whitespace) {
+        return Err("Email cannot contain whitespace".to_string());
+    }
+
+    let domain = idna::domain_to_ascii(domain).map_err(|_| format!("Email domain '{}' is not valid", domain))?;
+    let normalized = format!("{}@{}", local, domain);
+    if normalized.len() > MAX_EMAIL_LENGTH {
+        return Err(format!("Email cannot be longer than {} characters", MAX_EMAIL_LENGTH));
+    }
+
+    Ok(normalized)
+}
+
+/// The key two addresses share when they belong to the same account. Only ASCII letters are
+/// folded, exactly as the `email_key()` SQL function computes `users.email_normalized`.
+pub fn email_key(email: &str) -> String {
+    email.trim_matches(' ').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_trims_and_lowercases_domain() {
+        assert_eq!(normalize_email("  Bob.Smith@Example.COM ").unwrap(), "Bob.Smith@example.com");
+        assert_eq!(normalize_email("alice@example.com").unwrap(), "alice@example.com");
+    }
+
+    #[test]
+    fn test_normalize_converts_idn_domains_to_punycode() {
+        assert_eq!(normalize_email("hans@Bücher.example").unwrap(), "hans@xn--bcher-kva.example");
+        assert_eq!(normalize_email("user@xn--bcher-kva.example").unwrap(), "user@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn test_normalize_uses_last_at_sign() {
+        assert_eq!(normalize_email("\"a@b\"@Example.com").unwrap(), "\"a@b\"@example.com");
+    }
+
+    #[test]
+    fn test_normalize_rejects_invalid_addresses() {
+        assert!(normalize_email("").is_err());
+        assert!(normalize_email("no-at-sign").is_err());
+        assert!(normalize_email("@example.com").is_err());
+        assert!(normalize_email("user@").is_err());
+        assert!(normalize_email("us er@example.com").is_err());
+        assert!(normalize_email(&format!("{}@example.com", "a".repeat(250))).is_err());
+    }
+
+    #[test]
+    fn test_email_key_is_case_insensitive() {
+        assert_eq!(email_key("Bob@example.com"), email_key("bob@example.com"));
+        assert_ne!(email_key("bob@example.com"), email_key("rob@example.com"));
+    }
+
+    #[test]
+    fn test_email_key_folds_only_ascii_letters() {
+        assert_eq!(email_key(" ÉMILE@example.com "), "Émile@example.com");
+        assert_ne!(email_key("émile@example.com"), email_key("Émile@example.com"));
+    }
+}
@@ -0,0 +1,86 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailChangeStatus {
+    /// Waiting for the new address to be confirmed
+    Pending,
+    Confirmed,
+    /// Superseded by a later request or cancelled from the old address
+    Cancelled,
+    /// Confirmed, then undone from the old address
+    Reverted,
+}
+
+impl EmailChangeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailChangeStatus::Pending => "pending",
+            EmailChangeStatus::Confirmed => "confirmed",
+            EmailChangeStatus::Cancelled => "cancelled",
+            EmailChangeStatus::Reverted => "reverted",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(EmailChangeStatus::Pending),
+            "confirmed" => Some(EmailChangeStatus::Confirmed),
+            "cancelled" => Some(EmailChangeStatus::Cancelled),
+            "reverted" => Some(EmailChangeStatus::Reverted),
+            _ => None,
+        }
+    }
+}
+
+/// A request to move a user to a new email address. The change only reaches `users.email`
+/// once the confirmation token sent to the new address is presented.
+#[derive(Debug, Clone)]
+pub struct EmailChange {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub old_email: String,
+    pub new_email: String,
+    pub status: EmailChangeStatus,
+    pub confirm_expires_at: DateTime<Utc>,
+    pub revert_expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Body of `POST /users/{id}/email-change`.
+#[derive(Debug, Deserialize)]
+pub struct EmailChangeRequest {
+    pub email: String,
+}
+
+/// Body of the confirm and revert endpoints, carrying the token from the emailed link.
+#[derive(Debug, Deserialize)]
+pub struct EmailChangeTokenRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmailChangeResponse {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub new_email: String,
+    pub status: EmailChangeStatus,
+    pub confirm_expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<EmailChange> for EmailChangeResponse {
+    fn from(change: EmailChange) -> Self {
+        EmailChangeResponse {
+            id: change.id,
+            user_id: change.user_id,
+            new_email: change.new_email,
+            status: change.status,
+            confirm_expires_at: change.confirm_expires_at,
+            created_at: change.created_at,
+        }
+    }
+}
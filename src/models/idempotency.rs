@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone)]
+pub struct IdempotencyRecord {
+    pub scope: String,
+    pub key: String,
+    pub fingerprint: String,
+    pub status_code: Option<i16>,
+    pub response_body: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// What a request carrying an `Idempotency-Key` should do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdempotencyOutcome {
+    /// First use of the key: execute the request and then complete or release the key. The
+    /// claim is identified by `claimed_at`, so a request whose claim was taken over by a retry
+    /// can no longer touch the key.
+    Started { claimed_at: DateTime<Utc> },
+    /// The key already completed with the same request; replay the stored response.
+    Replay { status_code: u16, body: serde_json::Value },
+    /// The key was used before with a different request body.
+    Mismatch,
+    /// Another request with the same key has not finished yet.
+    InProgress,
+}
+
+impl IdempotencyRecord {
+    /// Decides how a new request with `fingerprint` relates to this existing record.
+    pub fn outcome_for(&self, fingerprint: &str) -> IdempotencyOutcome {
+        if self.fingerprint != fingerprint {
+            return IdempotencyOutcome::Mismatch;
+        }
+
+        match (self.status_code, &self.response_body) {
+            (Some(status_code), Some(body)) => IdempotencyOutcome::Replay {
+                status_code: status_code as u16,
+                body: body.clone(),
+            },
+            _ => IdempotencyOutcome::InProgress,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_record(status_code: Option<i16>) -> IdempotencyRecord {
+        IdempotencyRecord {
+            scope: "POST /api/v1/users".to_string(),
+            key: "key-1".to_string(),
+            fingerprint: "a".repeat(64),
+            status_code,
+            response_body: status_code.map(|_| serde_json::json!({"id": 1})),
+            created_at: Utc::now(),
+            completed_at: None,
+        }
+    }
+
+    #[test]
+    fn test_completed_record_replays() {
+        let record = create_test_record(Some(201));
+        assert_eq!(
+            record.outcome_for(&"a".repeat(64)),
+            IdempotencyOutcome::Replay { status_code: 201, body: serde_json::json!({"id": 1}) }
+        );
+    }
+
+    #[test]
+    fn test_pending_record_is_in_progress() {
+        let record = create_test_record(None);
+        assert_eq!(record.outcome_for(&"a".repeat(64)), IdempotencyOutcome::InProgress);
+    }
+
+    #[test]
+    fn test_different_fingerprint_is_mismatch() {
+        let record = create_test_record(Some(201));
+        assert_eq!(record.outcome_for(&"b".repeat(64)), IdempotencyOutcome::Mismatch);
+    }
+}
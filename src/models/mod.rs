@@ -1 +1,9 @@
-pub mod user;
\ No newline at end of file
+pub mod email;
+pub mod email_change;
+pub mod idempotency;
+pub mod user;
+pub mod user_event;
+pub mod user_export;
+pub mod user_import;
+pub mod user_search;
+pub mod webhook;
\ No newline at end of file
@@ -7,8 +7,11 @@ pub struct User {
     pub id: Uuid,
     pub email: String,
     pub name: String,
+    pub version: i64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// When the user was suspended; `None` while the account is active
+    pub suspended_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -17,13 +20,59 @@ pub struct CreateUserRequest {
     pub name: String,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UpdateUserRequest {
+    pub email: String,
+    pub name: String,
+}
+
+pub const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+pub const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+
+/// A partial update to a user, applied to its JSON representation.
+#[derive(Debug, Clone)]
+pub enum UserPatch {
+    /// RFC 7396 JSON Merge Patch
+    Merge(serde_json::Value),
+    /// RFC 6902 JSON Patch
+    Json(json_patch::Patch),
+}
+
+impl UserPatch {
+    /// Parses a request body according to its media type. Returns `None` for unsupported types.
+    pub fn parse(content_type: &str, body: &[u8]) -> Option<Result<Self, serde_json::Error>> {
+        match content_type {
+            MERGE_PATCH_CONTENT_TYPE => Some(serde_json::from_slice(body).map(UserPatch::Merge)),
+            JSON_PATCH_CONTENT_TYPE => Some(serde_json::from_slice(body).map(UserPatch::Json)),
+            _ => None,
+        }
+    }
+}
+
+/// Body of `POST /users:batchGet`.
+#[derive(Debug, Deserialize)]
+pub struct BatchGetUsersRequest {
+    pub ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchGetUsersResponse {
+    /// Found users, in the order their ids were requested
+    pub users: Vec<UserResponse>,
+    /// Requested ids with no matching user
+    pub missing: Vec<Uuid>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct UserResponse {
     pub id: Uuid,
     pub email: String,
     pub name: String,
+    pub version: i64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// When the user was suspended; `None` while the account is active
+    pub suspended_at: Option<DateTime<Utc>>,
 }
 
 impl From<User> for UserResponse {
@@ -32,8 +81,10 @@ impl From<User> for UserResponse {
             id: user.id,
             email: user.email,
             name: user.name,
+            version: user.version,
             created_at: user.created_at,
             updated_at: user.updated_at,
+            suspended_at: user.suspended_at,
         }
     }
 }
@@ -64,8 +115,10 @@ mod tests {
             id,
             email: "test@example.com".to_string(),
             name: "Test User".to_string(),
+            version: 1,
             created_at: now,
             updated_at: now,
+            suspended_at: None,
         };
 
         assert_eq!(user.id, id);
@@ -84,8 +137,10 @@ mod tests {
             id,
             email: "test@example.com".to_string(),
             name: "Test User".to_string(),
+            version: 1,
             created_at: now,
             updated_at: now,
+            suspended_at: None,
         };
 
         let response: UserResponse = user.clone().into();
@@ -93,10 +148,28 @@ mod tests {
         assert_eq!(response.id, user.id);
         assert_eq!(response.email, user.email);
         assert_eq!(response.name, user.name);
+        assert_eq!(response.version, user.version);
         assert_eq!(response.created_at, user.created_at);
         assert_eq!(response.updated_at, user.updated_at);
     }
 
+    #[test]
+    fn test_user_patch_parsing() {
+        let merge = UserPatch::parse(MERGE_PATCH_CONTENT_TYPE, br#"{"name": "New Name"}"#);
+        assert!(matches!(merge, Some(Ok(UserPatch::Merge(_)))));
+
+        let json = UserPatch::parse(
+            JSON_PATCH_CONTENT_TYPE,
+            br#"[{"op": "replace", "path": "/name", "value": "New Name"}]"#,
+        );
+        assert!(matches!(json, Some(Ok(UserPatch::Json(_)))));
+
+        let invalid = UserPatch::parse(JSON_PATCH_CONTENT_TYPE, br#"{"op": "replace"}"#);
+        assert!(matches!(invalid, Some(Err(_))));
+
+        assert!(UserPatch::parse("application/json", b"{}").is_none());
+    }
+
     #[test]
     fn test_user_response_creation() {
         let id = Uuid::new_v4();
@@ -106,8 +179,10 @@ mod tests {
             id,
             email: "test@example.com".to_string(),
             name: "Test User".to_string(),
+            version: 1,
             created_at: now,
             updated_at: now,
+            suspended_at: None,
         };
 
         assert_eq!(response.id, id);
@@ -0,0 +1,107 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Lifecycle event types recorded in the `user_events` outbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UserEventType {
+    #[serde(rename = "user.created")]
+    Created,
+    #[serde(rename = "user.updated")]
+    Updated,
+    #[serde(rename = "user.deleted")]
+    Deleted,
+}
+
+impl UserEventType {
+    pub const ALL: [UserEventType; 3] = [
+        UserEventType::Created,
+        UserEventType::Updated,
+        UserEventType::Deleted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserEventType::Created => "user.created",
+            UserEventType::Updated => "user.updated",
+            UserEventType::Deleted => "user.deleted",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|event_type| event_type.as_str() == value)
+    }
+}
+
+/// Where an event sits in the change feed, which is ordered by the transaction that recorded
+/// each event and then by id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FeedPosition {
+    pub txid: i64,
+    pub id: i64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct UserEvent {
+    pub id: i64,
+    /// Transaction that recorded the event
+    #[serde(skip)]
+    pub txid: i64,
+    pub event_type: String,
+    pub user_id: Uuid,
+    pub payload: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+impl UserEvent {
+    pub fn position(&self) -> FeedPosition {
+        FeedPosition { txid: self.txid, id: self.id }
+    }
+
+    /// The JSON envelope pushed to event consumers: `{id, type, created_at, data}`.
+    pub fn envelope(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "type": self.event_type,
+            "created_at": self.created_at,
+            "data": self.payload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_type_round_trip() {
+        for event_type in UserEventType::ALL {
+            assert_eq!(UserEventType::parse(event_type.as_str()), Some(event_type));
+        }
+        assert_eq!(UserEventType::parse("user.exploded"), None);
+    }
+
+    #[test]
+    fn test_event_type_serialization() {
+        let json = serde_json::to_string(&UserEventType::Created).unwrap();
+        assert_eq!(json, "\"user.created\"");
+    }
+
+    #[test]
+    fn test_event_envelope() {
+        let user_id = Uuid::new_v4();
+        let event = UserEvent {
+            id: 42,
+            txid: 1000,
+            event_type: UserEventType::Updated.as_str().to_string(),
+            user_id,
+            payload: serde_json::json!({"id": user_id, "name": "Test User"}),
+            created_at: Utc::now(),
+        };
+
+        let envelope = event.envelope();
+        assert_eq!(envelope["id"], 42);
+        assert_eq!(envelope["type"], "user.updated");
+        assert_eq!(envelope["data"]["name"], "Test User");
+    }
+}
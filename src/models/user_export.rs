@@ -0,0 +1,147 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use crate::models::user::UserResponse;
+
+/// Output format of a user export, negotiated from the `Accept` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+    /// A single JSON array
+    Json,
+}
+
+impl ExportFormat {
+    /// Picks the format for one media range of an `Accept` header. Wildcards select JSON.
+    pub fn from_media_range(media_range: &str) -> Option<Self> {
+        match media_range {
+            "text/csv" => Some(ExportFormat::Csv),
+            "application/x-ndjson" | "application/ndjson" | "application/jsonlines" => Some(ExportFormat::Ndjson),
+            "application/json" | "application/*" | "*/*" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Json => "application/json",
+        }
+    }
+}
+
+/// A field of [`UserResponse`] that can be selected for export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserField {
+    Id,
+    Email,
+    Name,
+    Version,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl UserField {
+    pub const ALL: [UserField; 6] = [
+        UserField::Id,
+        UserField::Email,
+        UserField::Name,
+        UserField::Version,
+        UserField::CreatedAt,
+        UserField::UpdatedAt,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserField::Id => "id",
+            UserField::Email => "email",
+            UserField::Name => "name",
+            UserField::Version => "version",
+            UserField::CreatedAt => "created_at",
+            UserField::UpdatedAt => "updated_at",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.as_str() == value)
+    }
+
+    /// Parses a comma-separated field list, e.g. `id,email`. Returns the unknown name on failure.
+    pub fn parse_list(value: &str) -> Result<Vec<Self>, String> {
+        let mut fields = Vec::new();
+        for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let field = Self::parse(name).ok_or_else(|| name.to_string())?;
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        Ok(fields)
+    }
+
+    /// The field's value with the same representation as the user's JSON body.
+    pub fn value(&self, user: &UserResponse) -> Value {
+        match self {
+            UserField::Id => Value::String(user.id.to_string()),
+            UserField::Email => Value::String(user.email.clone()),
+            UserField::Name => Value::String(user.name.clone()),
+            UserField::Version => Value::from(user.version),
+            UserField::CreatedAt => serde_json::json!(user.created_at),
+            UserField::UpdatedAt => serde_json::json!(user.updated_at),
+        }
+    }
+}
+
+/// Criteria for selecting users; every criterion that is set must match.
+#[derive(Debug, Clone, Default)]
+pub struct UserFilter {
+    /// Case-insensitive substring of the email
+    pub email_contains: Option<String>,
+    /// Case-insensitive substring of the name
+    pub name_contains: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_export_format_from_media_range() {
+        assert_eq!(ExportFormat::from_media_range("text/csv"), Some(ExportFormat::Csv));
+        assert_eq!(ExportFormat::from_media_range("application/x-ndjson"), Some(ExportFormat::Ndjson));
+        assert_eq!(ExportFormat::from_media_range("*/*"), Some(ExportFormat::Json));
+        assert_eq!(ExportFormat::from_media_range("text/html"), None);
+    }
+
+    #[test]
+    fn test_parse_field_list() {
+        assert_eq!(
+            UserField::parse_list("email, id,email").unwrap(),
+            vec![UserField::Email, UserField::Id]
+        );
+        assert_eq!(UserField::parse_list("id,password").unwrap_err(), "password");
+        assert!(UserField::parse_list("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_field_values_match_json_body() {
+        let now = Utc::now();
+        let user = UserResponse {
+            id: Uuid::new_v4(),
+            email: "test@example.com".to_string(),
+            name: "Test User".to_string(),
+            version: 2,
+            created_at: now,
+            updated_at: now,
+            suspended_at: None,
+        };
+        let body = serde_json::json!(user);
+
+        for field in UserField::ALL {
+            assert_eq!(field.value(&user), body[field.as_str()]);
+        }
+    }
+}
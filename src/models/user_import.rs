@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+pub const CSV_CONTENT_TYPE: &str = "text/csv";
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Input format of a bulk import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// Comma-separated values with a header row naming the `email` and `name` columns
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl ImportFormat {
+    /// Picks the format for a request's media type.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            CSV_CONTENT_TYPE => Some(ImportFormat::Csv),
+            NDJSON_CONTENT_TYPE | "application/ndjson" | "application/jsonlines" => Some(ImportFormat::Ndjson),
+            _ => None,
+        }
+    }
+
+    /// Picks the format for a file name by its extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "csv" => Some(ImportFormat::Csv),
+            "ndjson" | "jsonl" => Some(ImportFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+/// How an import treats rows that cannot be inserted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// A single transaction: any failed row rolls back the whole import.
+    #[default]
+    AllOrNothing,
+    /// Valid rows are committed batch by batch and failed rows are skipped.
+    PerRow,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportOptions {
+    pub mode: ImportMode,
+    /// Validate and report without writing anything.
+    pub dry_run: bool,
+}
+
+/// Why a single row was not imported. `line` is the row's 1-based line in the input.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImportRowError {
+    pub line: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub error: String,
+}
+
+/// Outcome of a bulk import.
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub mode: ImportMode,
+    pub dry_run: bool,
+    pub total_rows: u64,
+    /// Rows that passed validation and did not conflict with existing users.
+    pub valid_rows: u64,
+    /// Rows actually committed; always 0 for dry runs and rolled-back imports.
+    pub imported_rows: u64,
+    pub failed_rows: u64,
+    pub errors: Vec<ImportRowError>,
+    /// Whether `errors` was cut short; `failed_rows` still counts every failure.
+    pub errors_truncated: bool,
+}
+
+impl ImportReport {
+    pub fn new(options: ImportOptions) -> Self {
+        Self {
+            mode: options.mode,
+            dry_run: options.dry_run,
+            total_rows: 0,
+            valid_rows: 0,
+            imported_rows: 0,
+            failed_rows: 0,
+            errors: Vec::new(),
+            errors_truncated: false,
+        }
+    }
+
+    /// Whether every row was accepted.
+    pub fn is_clean(&self) -> bool {
+        self.failed_rows == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_format_detection() {
+        assert_eq!(ImportFormat::from_content_type("text/csv"), Some(ImportFormat::Csv));
+        assert_eq!(ImportFormat::from_content_type("application/x-ndjson"), Some(ImportFormat::Ndjson));
+        assert_eq!(ImportFormat::from_content_type("application/json"), None);
+
+        assert_eq!(ImportFormat::from_path("users.CSV"), Some(ImportFormat::Csv));
+        assert_eq!(ImportFormat::from_path("exports/users.jsonl"), Some(ImportFormat::Ndjson));
+        assert_eq!(ImportFormat::from_path("users"), None);
+    }
+
+    #[test]
+    fn test_import_mode_serialization() {
+        assert_eq!(serde_json::to_string(&ImportMode::PerRow).unwrap(), "\"per_row\"");
+        let mode: ImportMode = serde_json::from_str("\"all_or_nothing\"").unwrap();
+        assert_eq!(mode, ImportMode::AllOrNothing);
+    }
+}
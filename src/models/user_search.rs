@@ -0,0 +1,142 @@
+use serde::Serialize;
+use crate::models::user::{User, UserResponse};
+
+/// A row matched by a search, as read from the database.
+#[derive(Debug)]
+pub struct UserSearchMatch {
+    pub user: User,
+    pub score: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserSearchHit {
+    pub user: UserResponse,
+    /// Text rank plus trigram similarity; higher is better
+    pub score: f32,
+    pub highlights: SearchHighlights,
+}
+
+/// HTML fragments of the fields that matched, with matched words wrapped in `<mark>`.
+#[derive(Debug, Default, Serialize)]
+pub struct SearchHighlights {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+impl UserSearchHit {
+    pub fn new(found: UserSearchMatch, terms: &[String]) -> Self {
+        UserSearchHit {
+            highlights: SearchHighlights {
+                name: highlight(&found.user.name, terms),
+                email: highlight(&found.user.email, terms),
+            },
+            user: UserResponse::from(found.user),
+            score: found.score,
+        }
+    }
+}
+
+/// Lowercased words of a search query. Everything but letters and digits separates words,
+/// so tsquery operators in the input are never interpreted as syntax.
+pub fn search_terms(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Builds a `to_tsquery` expression requiring every term as a prefix, so partial input such
+/// as `jo do` matches "John Doe". Returns `None` when there are no terms.
+pub fn prefix_tsquery(terms: &[String]) -> Option<String> {
+    if terms.is_empty() {
+        return None;
+    }
+    let prefixes: Vec<String> = terms.iter().map(|term| format!("{}:*", term)).collect();
+    Some(prefixes.join(" & "))
+}
+
+/// HTML-escapes `text`, wrapping each word that starts with one of `terms` in `<mark>`.
+/// Returns `None` if no word matched.
+pub fn highlight(text: &str, terms: &[String]) -> Option<String> {
+    let mut html = String::with_capacity(text.len() + 16);
+    let mut matched = false;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let word_len = rest.find(|c: char| !c.is_alphanumeric()).unwrap_or(rest.len());
+        if word_len == 0 {
+            let separator = rest.chars().next().unwrap();
+            push_escaped(&mut html, separator);
+            rest = &rest[separator.len_utf8()..];
+            continue;
+        }
+
+        let (word, remainder) = rest.split_at(word_len);
+        let lowercase = word.to_lowercase();
+        let is_match = terms.iter().any(|term| lowercase.starts_with(term.as_str()));
+        if is_match {
+            matched = true;
+            html.push_str("<mark>");
+        }
+        word.chars().for_each(|c| push_escaped(&mut html, c));
+        if is_match {
+            html.push_str("</mark>");
+        }
+        rest = remainder;
+    }
+
+    matched.then_some(html)
+}
+
+fn push_escaped(html: &mut String, c: char) {
+    match c {
+        '&' => html.push_str("&amp;"),
+        '<' => html.push_str("&lt;"),
+        '>' => html.push_str("&gt;"),
+        '"' => html.push_str("&quot;"),
+        '\'' => html.push_str("&#39;"),
+        c => html.push(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(query: &str) -> Vec<String> {
+        search_terms(query)
+    }
+
+    #[test]
+    fn test_prefix_tsquery() {
+        assert_eq!(prefix_tsquery(&terms("Jo")), Some("jo:*".to_string()));
+        assert_eq!(prefix_tsquery(&terms("  john   DOE ")), Some("john:* & doe:*".to_string()));
+        assert_eq!(prefix_tsquery(&terms("bob@ex.com | !x")), Some("bob:* & ex:* & com:* & x:*".to_string()));
+        assert_eq!(prefix_tsquery(&terms("José")), Some("josé:*".to_string()));
+        assert_eq!(prefix_tsquery(&terms("@@ --")), None);
+    }
+
+    #[test]
+    fn test_highlight_marks_matching_words() {
+        assert_eq!(
+            highlight("John Doe", &terms("jo")).as_deref(),
+            Some("<mark>John</mark> Doe")
+        );
+        assert_eq!(
+            highlight("bulk12@example.com", &terms("example")).as_deref(),
+            Some("bulk12@<mark>example</mark>.com")
+        );
+        assert_eq!(highlight("John Doe", &terms("smith")), None);
+    }
+
+    #[test]
+    fn test_highlight_escapes_html() {
+        assert_eq!(
+            highlight("<b>Tom</b> & \"Jerry\"", &terms("tom")).as_deref(),
+            Some("&lt;b&gt;<mark>Tom</mark>&lt;/b&gt; &amp; &quot;Jerry&quot;")
+        );
+    }
+}
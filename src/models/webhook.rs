@@ -0,0 +1,185 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Subscribes to every event type, current and future.
+pub const WILDCARD_EVENT_TYPE: &str = "*";
+
+#[derive(Debug, Clone)]
+pub struct WebhookSubscription {
+    pub id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub event_types: Vec<String>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub event_types: Vec<String>,
+    /// Signing secret; generated when omitted.
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookSubscriptionResponse {
+    pub id: Uuid,
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<WebhookSubscription> for WebhookSubscriptionResponse {
+    fn from(subscription: WebhookSubscription) -> Self {
+        WebhookSubscriptionResponse {
+            id: subscription.id,
+            url: subscription.url,
+            event_types: subscription.event_types,
+            active: subscription.active,
+            created_at: subscription.created_at,
+            updated_at: subscription.updated_at,
+        }
+    }
+}
+
+/// Returned once on creation; the secret is never exposed again.
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhookResponse {
+    #[serde(flatten)]
+    pub subscription: WebhookSubscriptionResponse,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Succeeded,
+    Dead,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Succeeded => "succeeded",
+            DeliveryStatus::Dead => "dead",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(DeliveryStatus::Pending),
+            "succeeded" => Some(DeliveryStatus::Succeeded),
+            "dead" => Some(DeliveryStatus::Dead),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub subscription_id: Uuid,
+    pub event_id: i64,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DeliveryAttempt {
+    pub id: i64,
+    pub delivery_id: Uuid,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i64,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryHistoryEntry {
+    #[serde(flatten)]
+    pub delivery: WebhookDelivery,
+    pub attempt_log: Vec<DeliveryAttempt>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub delivery_id: Uuid,
+    pub subscription_id: Uuid,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub replayed_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_subscription() -> WebhookSubscription {
+        let now = Utc::now();
+        WebhookSubscription {
+            id: Uuid::new_v4(),
+            url: "https://partner.example.com/hooks".to_string(),
+            secret: "whsec_test".to_string(),
+            event_types: vec!["user.created".to_string()],
+            active: true,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn test_subscription_response_hides_secret() {
+        let subscription = create_test_subscription();
+        let response = WebhookSubscriptionResponse::from(subscription.clone());
+
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains("partner.example.com"));
+        assert!(!json.contains("whsec_test"));
+    }
+
+    #[test]
+    fn test_created_response_includes_secret_once() {
+        let subscription = create_test_subscription();
+        let response = CreatedWebhookResponse {
+            secret: subscription.secret.clone(),
+            subscription: subscription.into(),
+        };
+
+        let json: serde_json::Value = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["secret"], "whsec_test");
+        assert_eq!(json["url"], "https://partner.example.com/hooks");
+    }
+
+    #[test]
+    fn test_delivery_status_round_trip() {
+        for status in [DeliveryStatus::Pending, DeliveryStatus::Succeeded, DeliveryStatus::Dead] {
+            assert_eq!(DeliveryStatus::parse(status.as_str()), Some(status));
+        }
+        assert_eq!(DeliveryStatus::parse("unknown"), None);
+    }
+
+    #[test]
+    fn test_create_webhook_request_without_secret() {
+        let json_data = r#"{"url": "https://example.com/hook", "event_types": ["*"]}"#;
+        let request: CreateWebhookRequest = serde_json::from_str(json_data).unwrap();
+
+        assert_eq!(request.event_types, vec![WILDCARD_EVENT_TYPE.to_string()]);
+        assert!(request.secret.is_none());
+    }
+}
@@ -0,0 +1,458 @@
+//! Per-client rate limiting with token buckets. Each `[[rate_limit.routes]]` rule gives every
+//! client of the matching routes a bucket of `requests` tokens, refilled evenly over
+//! `window_secs`; a request takes one token or is refused with 429. Buckets live in memory for
+//! a single instance or in Postgres when instances must share them. Responses of limited routes
+//! carry the `RateLimit-*` headers of the IETF RateLimit header fields draft.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::chaos::path_matches;
+use crate::clock::{Clock, SystemClock};
+use crate::config::RateLimitConfig;
+use crate::dao::rate_limit_dao::RateLimitDao;
+use crate::handlers::ErrorResponse;
+use crate::tls::ClientIdentity;
+
+/// Buckets the memory store holds before it starts dropping full ones, which are the same as
+/// no bucket at all.
+const MEMORY_PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitStoreKind {
+    #[default]
+    Memory,
+    Postgres,
+}
+
+/// Who a bucket belongs to. Requests without the API key or client certificate a rule asks
+/// for are counted by IP address instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    #[default]
+    Ip,
+    /// The header named by `api_key_header`
+    ApiKey,
+    /// The subject of the verified client certificate; see [`ClientIdentity`]
+    User,
+}
+
+/// A quota for the routes matching `method` and `path`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitRule {
+    /// Any method when unset
+    #[serde(default)]
+    pub method: Option<String>,
+    /// A route pattern as for fault injection: `{...}` matches any one segment and a final `*`
+    /// any number of them
+    pub path: String,
+    #[serde(default)]
+    pub key: RateLimitKey,
+    /// Bucket size, and so the largest burst
+    pub requests: u32,
+    /// How long an empty bucket takes to refill
+    pub window_secs: u64,
+}
+
+impl RateLimitRule {
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        self.method.as_deref().is_none_or(|expected| expected.eq_ignore_ascii_case(method.as_str()))
+            && path_matches(&self.path, path)
+    }
+
+    fn quota(&self) -> Quota {
+        Quota { requests: self.requests, window: Duration::from_secs(self.window_secs) }
+    }
+
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.path.starts_with('/') {
+            problems.push("path must start with /".to_string());
+        }
+        if let Some(method) = &self.method {
+            if Method::from_bytes(method.as_bytes()).is_err() {
+                problems.push(format!("method '{}' is not an HTTP method", method));
+            }
+        }
+        if self.requests == 0 {
+            problems.push("requests must be at least 1".to_string());
+        }
+        if self.window_secs == 0 {
+            problems.push("window_secs must be at least 1".to_string());
+        }
+        problems
+    }
+}
+
+/// Up to `requests` at once, refilled evenly over `window`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub requests: u32,
+    pub window: Duration,
+}
+
+impl Quota {
+    pub fn capacity(&self) -> f64 {
+        self.requests as f64
+    }
+
+    pub fn tokens_per_sec(&self) -> f64 {
+        self.requests as f64 / self.window.as_secs_f64()
+    }
+}
+
+/// The outcome of taking a token from a bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Taken {
+    pub allowed: bool,
+    /// Tokens left afterwards, fractions included
+    pub tokens: f64,
+}
+
+/// Where buckets are kept. Errors are `sqlx` errors, as for the other stores.
+pub trait RateLimitStore: Send + Sync {
+    /// Refills `key`'s bucket for the time since it was last used, starting full, then takes
+    /// a token if there is a whole one.
+    fn take<'a>(&'a self, key: &'a str, quota: Quota) -> BoxFuture<'a, Result<Taken, sqlx::Error>>;
+}
+
+impl RateLimitStore for RateLimitDao {
+    fn take<'a>(&'a self, key: &'a str, quota: Quota) -> BoxFuture<'a, Result<Taken, sqlx::Error>> {
+        Box::pin(RateLimitDao::take(self, key, quota))
+    }
+}
+
+struct MemoryBucket {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+    full_at: DateTime<Utc>,
+}
+
+/// Buckets of this instance alone; each instance of several would allow the full quota.
+pub struct MemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, MemoryBucket>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MemoryRateLimitStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self { buckets: Mutex::new(HashMap::new()), clock }
+    }
+
+    fn take_now(&self, key: &str, quota: Quota) -> Taken {
+        let now = self.clock.now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MEMORY_PRUNE_THRESHOLD && !buckets.contains_key(key) {
+            buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(MemoryBucket {
+            tokens: quota.capacity(),
+            updated_at: now,
+            full_at: now,
+        });
+        let elapsed = (now - bucket.updated_at).num_microseconds().unwrap_or(i64::MAX).max(0) as f64 / 1e6;
+        let tokens = (bucket.tokens + elapsed * quota.tokens_per_sec()).min(quota.capacity());
+        let allowed = tokens >= 1.0;
+
+        bucket.tokens = if allowed { tokens - 1.0 } else { tokens };
+        bucket.updated_at = now;
+        let until_full = (quota.capacity() - bucket.tokens) / quota.tokens_per_sec();
+        bucket.full_at = now + chrono::Duration::microseconds((until_full * 1e6) as i64);
+        Taken { allowed, tokens: bucket.tokens }
+    }
+}
+
+impl RateLimitStore for MemoryRateLimitStore {
+    fn take<'a>(&'a self, key: &'a str, quota: Quota) -> BoxFuture<'a, Result<Taken, sqlx::Error>> {
+        Box::pin(async move { Ok(self.take_now(key, quota)) })
+    }
+}
+
+/// Deletes full buckets from Postgres every `interval`, as they are the same as no bucket.
+pub async fn run_cleanup(dao: RateLimitDao, interval: Duration) {
+    loop {
+        match dao.delete_full().await {
+            Ok(0) => {}
+            Ok(deleted) => log::debug!("Deleted {} full rate limit buckets", deleted),
+            Err(err) => log::error!("Failed to delete full rate limit buckets: {}", err),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// The configured rules over a bucket store.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    store: Arc<dyn RateLimitStore>,
+    /// Digests of the configured API keys, as bucket keys hold them
+    api_keys: HashSet<String>,
+}
+
+/// What a limited request is told, as `RateLimit-*` and `Retry-After` headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again
+    pub reset_secs: u64,
+    /// Seconds until the next request is allowed; 0 while allowed
+    pub retry_after_secs: u64,
+    pub window_secs: u64,
+}
+
+impl Decision {
+    fn new(taken: Taken, quota: Quota) -> Self {
+        let seconds_until = |tokens: f64| (tokens.max(0.0) / quota.tokens_per_sec()).ceil() as u64;
+        Self {
+            allowed: taken.allowed,
+            limit: quota.requests,
+            remaining: taken.tokens.floor() as u32,
+            reset_secs: seconds_until(quota.capacity() - taken.tokens),
+            retry_after_secs: if taken.allowed { 0 } else { seconds_until(1.0 - taken.tokens) },
+            window_secs: quota.window.as_secs(),
+        }
+    }
+
+    fn headers(&self) -> Vec<(HeaderName, String)> {
+        let mut headers = vec![
+            (HeaderName::from_static("ratelimit-limit"), self.limit.to_string()),
+            (HeaderName::from_static("ratelimit-remaining"), self.remaining.to_string()),
+            (HeaderName::from_static("ratelimit-reset"), self.reset_secs.to_string()),
+            (HeaderName::from_static("ratelimit-policy"), format!("{};w={}", self.limit, self.window_secs)),
+        ];
+        if !self.allowed {
+            headers.push((header::RETRY_AFTER, self.retry_after_secs.to_string()));
+        }
+        headers
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, store: Arc<dyn RateLimitStore>) -> Self {
+        let api_keys = config.api_keys.iter().map(|api_key| key_digest(api_key.as_bytes())).collect();
+        Self { config, store, api_keys }
+    }
+
+    /// Takes a token for `request` from its client's bucket under the first matching rule.
+    /// `None` for unlimited routes, and when the store fails: an unavailable limiter lets
+    /// requests through rather than taking the API down with it.
+    pub async fn check(&self, request: &ServiceRequest) -> Option<Decision> {
+        let rule = self.config.routes.iter().find(|rule| rule.matches(request.method(), request.path()))?;
+        let key = format!(
+            "{} {} {}",
+            rule.method.as_deref().unwrap_or("*"),
+            rule.path,
+            self.client_key(rule.key, request)
+        );
+
+        match self.store.take(&key, rule.quota()).await {
+            Ok(taken) => Some(Decision::new(taken, rule.quota())),
+            Err(err) => {
+                log::warn!("Rate limiting unavailable, allowing request: {}", err);
+                None
+            }
+        }
+    }
+
+    fn client_key(&self, kind: RateLimitKey, request: &ServiceRequest) -> String {
+        match kind {
+            RateLimitKey::ApiKey => {
+                // Stored hashed, since the Postgres store would otherwise hold the keys. Unknown
+                // keys count by IP, or a client could take a fresh bucket with any new value
+                if let Some(api_key) = request.headers().get(self.config.api_key_header.as_str()) {
+                    let digest = key_digest(api_key.as_bytes());
+                    if self.api_keys.contains(&digest) {
+                        return format!("key:{}", digest);
+                    }
+                }
+            }
+            RateLimitKey::User => {
+                if let Some(identity) = ClientIdentity::of(request.request()) {
+                    return format!("user:{}", identity.subject);
+                }
+            }
+            RateLimitKey::Ip => {}
+        }
+
+        let ip = if self.config.trust_forwarded_for {
+            request.connection_info().realip_remote_addr().map(str::to_string)
+        } else {
+            request.peer_addr().map(|address| address.ip().to_string())
+        };
+        format!("ip:{}", ip.unwrap_or_default())
+    }
+}
+
+fn key_digest(api_key: &[u8]) -> String {
+    hex::encode(Sha256::digest(api_key))
+}
+
+/// Middleware enforcing the registered [`RateLimiter`]. Health checks are never limited.
+pub(crate) async fn limit_requests(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let limiter = request
+        .app_data::<web::Data<RateLimiter>>()
+        .filter(|_| !request.path().starts_with("/health/"))
+        .cloned();
+    let decision = match limiter {
+        Some(limiter) => limiter.check(&request).await,
+        None => None,
+    };
+    let Some(decision) = decision else {
+        return Ok(next.call(request).await?.map_into_left_body());
+    };
+
+    let mut response = if decision.allowed {
+        next.call(request).await?.map_into_left_body()
+    } else {
+        let response = HttpResponse::TooManyRequests().json(ErrorResponse::new("Rate limit exceeded"));
+        request.into_response(response).map_into_right_body()
+    };
+    for (name, value) in decision.headers() {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use chrono::TimeZone;
+    use crate::clock::FixedClock;
+
+    fn quota(requests: u32, window_secs: u64) -> Quota {
+        Quota { requests, window: Duration::from_secs(window_secs) }
+    }
+
+    fn rule(method: Option<&str>, path: &str, key: RateLimitKey) -> RateLimitRule {
+        RateLimitRule { method: method.map(str::to_string), path: path.to_string(), key, requests: 2, window_secs: 60 }
+    }
+
+    #[test]
+    fn test_memory_buckets_refill_over_the_window() {
+        let clock = Arc::new(FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap()));
+        let store = MemoryRateLimitStore::with_clock(clock.clone());
+        let quota = quota(2, 60);
+
+        assert_eq!(store.take_now("a", quota), Taken { allowed: true, tokens: 1.0 });
+        assert_eq!(store.take_now("a", quota), Taken { allowed: true, tokens: 0.0 });
+        assert!(!store.take_now("a", quota).allowed);
+        // Other clients have buckets of their own
+        assert!(store.take_now("b", quota).allowed);
+
+        // One token every 30 seconds
+        clock.advance(chrono::Duration::seconds(15));
+        let taken = store.take_now("a", quota);
+        assert_eq!(taken, Taken { allowed: false, tokens: 0.5 });
+        clock.advance(chrono::Duration::seconds(15));
+        assert!(store.take_now("a", quota).allowed);
+
+        // Never beyond capacity, however long it was idle
+        clock.advance(chrono::Duration::days(1));
+        assert_eq!(store.take_now("a", quota).tokens, 1.0);
+    }
+
+    #[test]
+    fn test_decision_headers() {
+        let quota = quota(10, 60);
+        let allowed = Decision::new(Taken { allowed: true, tokens: 7.5 }, quota);
+        assert_eq!((allowed.limit, allowed.remaining, allowed.reset_secs, allowed.retry_after_secs), (10, 7, 15, 0));
+        assert!(!allowed.headers().iter().any(|(name, _)| name == header::RETRY_AFTER));
+
+        let refused = Decision::new(Taken { allowed: false, tokens: 0.25 }, quota);
+        assert_eq!((refused.remaining, refused.retry_after_secs), (0, 5));
+        let headers = refused.headers();
+        assert!(headers.contains(&(HeaderName::from_static("ratelimit-policy"), "10;w=60".to_string())));
+        assert!(headers.contains(&(header::RETRY_AFTER, "5".to_string())));
+    }
+
+    #[actix_web::test]
+    async fn test_rules_and_client_keys() {
+        let config = RateLimitConfig {
+            enabled: true,
+            routes: vec![
+                rule(Some("POST"), "/api/v1/users", RateLimitKey::ApiKey),
+                rule(None, "/api/v1/users/{id}/*", RateLimitKey::Ip),
+            ],
+            api_keys: vec!["secret".to_string(), "one".to_string(), "two".to_string()],
+            ..RateLimitConfig::default()
+        };
+        let limiter = RateLimiter::new(config, Arc::new(MemoryRateLimitStore::new()));
+        let peer = "192.0.2.7:4000".parse().unwrap();
+        let create = |api_key: Option<&str>| {
+            let request = TestRequest::post().uri("/api/v1/users").peer_addr(peer);
+            match api_key {
+                Some(api_key) => request.insert_header(("X-Api-Key", api_key)),
+                None => request,
+            }
+            .to_srv_request()
+        };
+
+        assert_eq!(limiter.client_key(RateLimitKey::ApiKey, &create(None)), "ip:192.0.2.7");
+        assert!(limiter.client_key(RateLimitKey::ApiKey, &create(Some("secret"))).starts_with("key:"));
+        assert_eq!(limiter.client_key(RateLimitKey::ApiKey, &create(Some("guessed"))), "ip:192.0.2.7");
+        assert_eq!(limiter.client_key(RateLimitKey::User, &create(None)), "ip:192.0.2.7");
+
+        // Two per API key; a different key has its own quota
+        assert!(limiter.check(&create(Some("one"))).await.unwrap().allowed);
+        assert!(limiter.check(&create(Some("one"))).await.unwrap().allowed);
+        assert!(!limiter.check(&create(Some("one"))).await.unwrap().allowed);
+        assert!(limiter.check(&create(Some("two"))).await.unwrap().allowed);
+
+        // Unknown keys share the IP's bucket, however many a client makes up
+        assert!(limiter.check(&create(None)).await.unwrap().allowed);
+        assert!(limiter.check(&create(Some("made-up-1"))).await.unwrap().allowed);
+        assert!(!limiter.check(&create(Some("made-up-2"))).await.unwrap().allowed);
+        assert!(!limiter.check(&create(None)).await.unwrap().allowed);
+
+        let list = TestRequest::get().uri("/api/v1/users").peer_addr(peer).to_srv_request();
+        assert!(limiter.check(&list).await.is_none());
+        let nested = TestRequest::get().uri("/api/v1/users/42/email-changes").peer_addr(peer).to_srv_request();
+        assert_eq!(limiter.check(&nested).await.unwrap().remaining, 1);
+    }
+
+    #[test]
+    fn test_rule_problems() {
+        let mut rule = rule(Some("FETCH IT"), "api/v1/users", RateLimitKey::Ip);
+        rule.requests = 0;
+        assert_eq!(
+            rule.problems(),
+            [
+                "path must start with /",
+                "method 'FETCH IT' is not an HTTP method",
+                "requests must be at least 1",
+            ]
+        );
+    }
+}
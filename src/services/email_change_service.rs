@@ -0,0 +1,286 @@
+use std::sync::Arc;
+
+use chrono::Duration;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use crate::clock::{Clock, SystemClock};
+use crate::config::EmailChangeConfig;
+use crate::dao::email_change_dao::{EmailChangeDao, NewEmailChange};
+use crate::dao::user_dao::UserDao;
+use crate::errors::ServiceError;
+use crate::models::email::{email_key, normalize_email};
+use crate::models::email_change::{EmailChange, EmailChangeRequest, EmailChangeResponse, EmailChangeStatus};
+use crate::models::user::UserResponse;
+use crate::services::mailer::{EmailMessage, Mailer};
+use crate::services::user_service::map_write_error;
+
+/// Moves users to a new email address only once the new address proves it receives mail.
+///
+/// Requesting a change sends a confirmation link to the new address and a notice with a
+/// revert link to the current one, so a hijacked session cannot silently take over an
+/// account. Uniqueness is checked again at confirmation, as the address may have been
+/// claimed by someone else in the meantime.
+pub struct EmailChangeService {
+    user_dao: UserDao,
+    email_change_dao: EmailChangeDao,
+    mailer: Arc<dyn Mailer>,
+    config: EmailChangeConfig,
+    clock: Arc<dyn Clock>,
+}
+
+impl EmailChangeService {
+    pub fn new(
+        user_dao: UserDao,
+        email_change_dao: EmailChangeDao,
+        mailer: Arc<dyn Mailer>,
+        config: EmailChangeConfig,
+    ) -> Self {
+        Self {
+            user_dao,
+            email_change_dao,
+            mailer,
+            config,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Replaces the clock that link expiry is computed and checked against.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub async fn request_change(
+        &self,
+        user_id: Uuid,
+        request: EmailChangeRequest,
+    ) -> Result<EmailChangeResponse, ServiceError> {
+        let new_email = normalize_email(&request.email).map_err(ServiceError::Validation)?;
+        let user = self
+            .user_dao
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or(ServiceError::NotFound("User"))?;
+
+        if email_key(&new_email) == email_key(&user.email) {
+            return Err(ServiceError::Validation("New email must differ from the current email".to_string()));
+        }
+        if self.user_dao.get_user_by_email(&new_email).await?.is_some() {
+            return Err(ServiceError::Conflict("Email is already in use".to_string()));
+        }
+
+        let confirm_token = generate_token();
+        let revert_token = generate_token();
+        let now = self.clock.now();
+        let change = self
+            .email_change_dao
+            .create_change(&NewEmailChange {
+                user_id,
+                old_email: &user.email,
+                new_email: &new_email,
+                confirm_token_hash: &hash_token(&confirm_token),
+                revert_token_hash: &hash_token(&revert_token),
+                confirm_expires_at: now + Duration::minutes(self.config.confirm_ttl_minutes),
+                revert_expires_at: now + Duration::hours(self.config.revert_ttl_hours),
+            })
+            .await?;
+
+        // The change stays pending if a message cannot be sent; requesting it again issues
+        // fresh links and cancels these
+        for message in [
+            confirmation_message(&self.config, &change, &confirm_token),
+            revert_notice(&self.config, &change, &revert_token),
+        ] {
+            if let Err(err) = self.mailer.send(message).await {
+                log::error!("Failed to send email change message for user {}: {}", user_id, err);
+            }
+        }
+
+        Ok(EmailChangeResponse::from(change))
+    }
+
+    pub async fn get_pending_change(&self, user_id: Uuid) -> Result<EmailChangeResponse, ServiceError> {
+        self.email_change_dao
+            .get_pending_change(user_id)
+            .await?
+            .map(EmailChangeResponse::from)
+            .ok_or(ServiceError::NotFound("Email change"))
+    }
+
+    /// Applies the change a confirmation token was issued for.
+    pub async fn confirm(&self, token: &str) -> Result<UserResponse, ServiceError> {
+        let mut tx = self.email_change_dao.begin().await?;
+        let change = EmailChangeDao::lock_by_confirm_token(&mut tx, &hash_token(token))
+            .await?
+            .ok_or(ServiceError::NotFound("Email change"))?;
+
+        if change.status != EmailChangeStatus::Pending {
+            return Err(already(change.status));
+        }
+        let now = self.clock.now();
+        if now > change.confirm_expires_at {
+            return Err(ServiceError::Unprocessable("Confirmation link has expired".to_string()));
+        }
+
+        let user = UserDao::lock_user(&mut tx, change.user_id)
+            .await?
+            .ok_or(ServiceError::NotFound("User"))?;
+        if email_key(&user.email) != email_key(&change.old_email) {
+            return Err(ServiceError::Conflict(
+                "User's email has changed since this change was requested".to_string(),
+            ));
+        }
+        if UserDao::email_taken_by_other(&mut tx, &change.new_email, user.id).await? {
+            return Err(ServiceError::Conflict("Email is already in use".to_string()));
+        }
+
+        let user = UserDao::set_email(&mut tx, user.id, &change.new_email, now)
+            .await
+            .map_err(map_write_error)?
+            .ok_or(ServiceError::NotFound("User"))?;
+        EmailChangeDao::complete(&mut tx, change.id, EmailChangeStatus::Confirmed, now).await?;
+        tx.commit().await?;
+
+        log::info!("User {} confirmed an email change", user.id);
+        Ok(UserResponse::from(user))
+    }
+
+    /// Undoes a change from the old address: a pending change is cancelled and a confirmed one
+    /// restores the previous email, provided nobody has claimed it since.
+    pub async fn revert(&self, token: &str) -> Result<UserResponse, ServiceError> {
+        let mut tx = self.email_change_dao.begin().await?;
+        let change = EmailChangeDao::lock_by_revert_token(&mut tx, &hash_token(token))
+            .await?
+            .ok_or(ServiceError::NotFound("Email change"))?;
+
+        let now = self.clock.now();
+        if now > change.revert_expires_at {
+            return Err(ServiceError::Unprocessable("Revert link has expired".to_string()));
+        }
+        let user = UserDao::lock_user(&mut tx, change.user_id)
+            .await?
+            .ok_or(ServiceError::NotFound("User"))?;
+
+        let user = match change.status {
+            EmailChangeStatus::Pending => {
+                EmailChangeDao::complete(&mut tx, change.id, EmailChangeStatus::Cancelled, now).await?;
+                user
+            }
+            EmailChangeStatus::Confirmed => {
+                if email_key(&user.email) != email_key(&change.new_email) {
+                    return Err(ServiceError::Conflict(
+                        "User's email has changed again since this change was confirmed".to_string(),
+                    ));
+                }
+                if UserDao::email_taken_by_other(&mut tx, &change.old_email, user.id).await? {
+                    return Err(ServiceError::Conflict("The previous email is now in use by another user".to_string()));
+                }
+                let user = UserDao::set_email(&mut tx, user.id, &change.old_email, now)
+                    .await
+                    .map_err(map_write_error)?
+                    .ok_or(ServiceError::NotFound("User"))?;
+                EmailChangeDao::complete(&mut tx, change.id, EmailChangeStatus::Reverted, now).await?;
+                user
+            }
+            status => return Err(already(status)),
+        };
+        tx.commit().await?;
+
+        log::warn!("Email change {} for user {} was reverted from the old address", change.id, user.id);
+        Ok(UserResponse::from(user))
+    }
+}
+
+fn already(status: EmailChangeStatus) -> ServiceError {
+    ServiceError::Conflict(format!("Email change is already {}", status.as_str()))
+}
+
+fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Tokens are stored hashed so that a leaked table cannot be used to confirm changes.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn link(config: &EmailChangeConfig, action: &str, token: &str) -> String {
+    format!("{}/email-change/{}?token={}", config.link_base_url.trim_end_matches('/'), action, token)
+}
+
+fn confirmation_message(config: &EmailChangeConfig, change: &EmailChange, token: &str) -> EmailMessage {
+    EmailMessage {
+        to: change.new_email.clone(),
+        subject: "Confirm your new email address".to_string(),
+        body: format!(
+            "Open this link to start using {} for your account:\n\n{}\n\nThe link expires at {}.\n",
+            change.new_email,
+            link(config, "confirm", token),
+            change.confirm_expires_at.to_rfc3339()
+        ),
+    }
+}
+
+fn revert_notice(config: &EmailChangeConfig, change: &EmailChange, token: &str) -> EmailMessage {
+    EmailMessage {
+        to: change.old_email.clone(),
+        subject: "Your account email is being changed".to_string(),
+        body: format!(
+            "A change of your account email to {} was requested. If this was not you, open this \
+             link to cancel the change or, once confirmed, undo it:\n\n{}\n\nThe link expires at {}.\n",
+            change.new_email,
+            link(config, "revert", token),
+            change.revert_expires_at.to_rfc3339()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_change() -> EmailChange {
+        let now = chrono::Utc::now();
+        EmailChange {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            old_email: "old@example.com".to_string(),
+            new_email: "new@example.com".to_string(),
+            status: EmailChangeStatus::Pending,
+            confirm_expires_at: now,
+            revert_expires_at: now,
+            created_at: now,
+            completed_at: None,
+        }
+    }
+
+    #[test]
+    fn test_tokens_are_random_and_stored_hashed() {
+        let token = generate_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_token());
+
+        let hash = hash_token(&token);
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, token);
+        assert_eq!(hash, hash_token(&token));
+    }
+
+    #[test]
+    fn test_messages_go_to_the_right_addresses() {
+        let config = EmailChangeConfig {
+            link_base_url: "https://app.example.com/".to_string(),
+            ..EmailChangeConfig::default()
+        };
+        let change = create_test_change();
+
+        let confirmation = confirmation_message(&config, &change, "abc");
+        assert_eq!(confirmation.to, "new@example.com");
+        assert!(confirmation.body.contains("https://app.example.com/email-change/confirm?token=abc"));
+
+        let notice = revert_notice(&config, &change, "def");
+        assert_eq!(notice.to, "old@example.com");
+        assert!(notice.body.contains("new@example.com"));
+        assert!(notice.body.contains("https://app.example.com/email-change/revert?token=def"));
+    }
+}
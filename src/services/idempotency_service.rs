@@ -0,0 +1,160 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::config::IdempotencyConfig;
+use crate::dao::idempotency_dao::IdempotencyDao;
+use crate::errors::ServiceError;
+use crate::models::idempotency::IdempotencyOutcome;
+
+const MAX_KEY_LENGTH: usize = 255;
+
+pub struct IdempotencyService {
+    idempotency_dao: IdempotencyDao,
+    config: IdempotencyConfig,
+}
+
+impl IdempotencyService {
+    pub fn new(idempotency_dao: IdempotencyDao, config: IdempotencyConfig) -> Self {
+        Self { idempotency_dao, config }
+    }
+
+    /// Claims `key` for this request or reports how an earlier use of it should be handled.
+    pub async fn begin(
+        &self,
+        scope: &str,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<IdempotencyOutcome, ServiceError> {
+        validate_key(key)?;
+
+        // The existing record can be released between our claim and read, so try twice
+        for _ in 0..2 {
+            let claimed = self
+                .idempotency_dao
+                .try_claim(scope, key, fingerprint, self.ttl(), self.in_progress_timeout())
+                .await?;
+            if let Some(claimed_at) = claimed {
+                return Ok(IdempotencyOutcome::Started { claimed_at });
+            }
+            if let Some(record) = self.idempotency_dao.get(scope, key).await? {
+                return Ok(record.outcome_for(fingerprint));
+            }
+        }
+
+        Ok(IdempotencyOutcome::InProgress)
+    }
+
+    /// Stores the response of a request started at `claimed_at` so retries replay it. Nothing
+    /// is stored if a retry has taken the key over in the meantime.
+    pub async fn complete(
+        &self,
+        scope: &str,
+        key: &str,
+        claimed_at: DateTime<Utc>,
+        status_code: u16,
+        response_body: &serde_json::Value,
+    ) -> Result<(), ServiceError> {
+        let completed = self
+            .idempotency_dao
+            .complete(scope, key, claimed_at, status_code as i16, response_body)
+            .await?;
+        if !completed {
+            log::warn!("Idempotency key {} was taken over before its request completed", key);
+        }
+        Ok(())
+    }
+
+    /// Frees the key of a request started at `claimed_at` that failed, so the client can retry
+    /// it. A key taken over by a retry in the meantime is left to that retry.
+    pub async fn release(&self, scope: &str, key: &str, claimed_at: DateTime<Utc>) -> Result<(), ServiceError> {
+        self.idempotency_dao.release(scope, key, claimed_at).await?;
+        Ok(())
+    }
+
+    /// Periodically deletes keys older than the configured TTL.
+    pub async fn run_cleanup(self: Arc<Self>) {
+        let interval = Duration::from_secs(self.config.cleanup_interval_secs);
+
+        loop {
+            match self.idempotency_dao.delete_expired(self.ttl()).await {
+                Ok(0) => {}
+                Ok(deleted) => log::info!("Deleted {} expired idempotency keys", deleted),
+                Err(err) => log::error!("Failed to delete expired idempotency keys: {}", err),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    fn ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.config.ttl_hours as i64)
+    }
+
+    fn in_progress_timeout(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.config.in_progress_timeout_secs as i64)
+    }
+}
+
+/// SHA-256 of the request's canonical JSON, so formatting differences between retries
+/// do not count as a different body.
+pub fn fingerprint<T: Serialize>(request: &T) -> String {
+    let canonical = serde_json::to_vec(request).unwrap_or_default();
+    hex::encode(Sha256::digest(&canonical))
+}
+
+fn validate_key(key: &str) -> Result<(), ServiceError> {
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(ServiceError::Validation(format!(
+            "Idempotency-Key must be between 1 and {} characters",
+            MAX_KEY_LENGTH
+        )));
+    }
+    if !key.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(ServiceError::Validation(
+            "Idempotency-Key must contain only visible ASCII characters".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::CreateUserRequest;
+
+    #[test]
+    fn test_fingerprint_ignores_formatting() {
+        let compact: CreateUserRequest =
+            serde_json::from_str(r#"{"email":"a@example.com","name":"A"}"#).unwrap();
+        let spaced: CreateUserRequest =
+            serde_json::from_str(r#"{ "name": "A",  "email": "a@example.com" }"#).unwrap();
+
+        assert_eq!(fingerprint(&compact), fingerprint(&spaced));
+        assert_eq!(fingerprint(&compact).len(), 64);
+    }
+
+    #[test]
+    fn test_fingerprint_detects_different_bodies() {
+        let first = CreateUserRequest {
+            email: "a@example.com".to_string(),
+            name: "A".to_string(),
+        };
+        let second = CreateUserRequest {
+            email: "a@example.com".to_string(),
+            name: "B".to_string(),
+        };
+
+        assert_ne!(fingerprint(&first), fingerprint(&second));
+    }
+
+    #[test]
+    fn test_key_validation() {
+        assert!(validate_key("5b1c7a3e-retry-key").is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key(&"k".repeat(MAX_KEY_LENGTH + 1)).is_err());
+        assert!(validate_key("has space").is_err());
+    }
+}
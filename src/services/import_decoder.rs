@@ -0,0 +1,353 @@
+//! Incremental CSV/NDJSON decoding for bulk user imports.
+//!
+//! Input arrives in arbitrary chunks (HTTP body frames or file reads), so rows are decoded as
+//! soon as they are complete and a row split across chunks is carried over to the next one.
+
+use csv_core::{ReadRecordResult, Reader};
+use crate::errors::ServiceError;
+use crate::models::user::CreateUserRequest;
+use crate::models::user_import::{ImportFormat, ImportRowError};
+
+/// Upper bound on a single row, so a missing line terminator cannot buffer the whole upload.
+const MAX_ROW_BYTES: usize = 64 * 1024;
+
+/// A decoded row together with its 1-based line in the input.
+#[derive(Debug)]
+pub struct ImportRow {
+    pub line: u64,
+    pub request: CreateUserRequest,
+}
+
+pub type DecodedRow = Result<ImportRow, ImportRowError>;
+
+pub struct ImportDecoder {
+    state: State,
+}
+
+enum State {
+    // Boxed because csv_core's parser state dwarfs the NDJSON decoder
+    Csv(Box<CsvDecoder>),
+    Ndjson(NdjsonDecoder),
+}
+
+impl ImportDecoder {
+    pub fn new(format: ImportFormat) -> Self {
+        let state = match format {
+            ImportFormat::Csv => State::Csv(Box::new(CsvDecoder::new())),
+            ImportFormat::Ndjson => State::Ndjson(NdjsonDecoder::new()),
+        };
+        Self { state }
+    }
+
+    /// Decodes every row completed by `chunk`. Errors are fatal to the whole import (an
+    /// unusable CSV header or an oversized row); problems with a single row are returned
+    /// as that row's `Err`.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<DecodedRow>, ServiceError> {
+        match &mut self.state {
+            State::Csv(decoder) => decoder.decode(chunk, false),
+            State::Ndjson(decoder) => decoder.decode(chunk, false),
+        }
+    }
+
+    /// Decodes whatever is left once the input has ended.
+    pub fn finish(&mut self) -> Result<Vec<DecodedRow>, ServiceError> {
+        match &mut self.state {
+            State::Csv(decoder) => decoder.decode(&[], true),
+            State::Ndjson(decoder) => decoder.decode(&[], true),
+        }
+    }
+}
+
+fn row_too_long(line: u64) -> ServiceError {
+    ServiceError::Validation(format!("Row at line {} exceeds {} bytes", line, MAX_ROW_BYTES))
+}
+
+/// Positions of the columns we import, taken from the header row.
+struct Columns {
+    email: usize,
+    name: usize,
+}
+
+struct CsvDecoder {
+    reader: Reader,
+    record: Vec<u8>,
+    record_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+    in_record: bool,
+    record_line: u64,
+    columns: Option<Columns>,
+}
+
+impl CsvDecoder {
+    fn new() -> Self {
+        Self {
+            reader: Reader::new(),
+            record: vec![0; 1024],
+            record_len: 0,
+            ends: vec![0; 16],
+            ends_len: 0,
+            in_record: false,
+            record_line: 1,
+            columns: None,
+        }
+    }
+
+    fn decode(&mut self, mut input: &[u8], at_end: bool) -> Result<Vec<DecodedRow>, ServiceError> {
+        let mut rows = Vec::new();
+
+        loop {
+            if !self.in_record {
+                // Skip blank lines ourselves so the record's starting line is known
+                let blank = input.iter().take_while(|&&b| b == b'\n' || b == b'\r').count();
+                let newlines = input[..blank].iter().filter(|&&b| b == b'\n').count() as u64;
+                self.reader.set_line(self.reader.line() + newlines);
+                input = &input[blank..];
+
+                if input.is_empty() && !at_end {
+                    return Ok(rows);
+                }
+                self.in_record = !input.is_empty();
+                self.record_line = self.reader.line();
+            }
+
+            let (result, consumed, written, ends_written) = self.reader.read_record(
+                input,
+                &mut self.record[self.record_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[consumed..];
+            self.record_len += written;
+            self.ends_len += ends_written;
+
+            match result {
+                ReadRecordResult::InputEmpty => {
+                    if !at_end {
+                        return Ok(rows);
+                    }
+                }
+                ReadRecordResult::OutputFull => {
+                    if self.record.len() >= MAX_ROW_BYTES {
+                        return Err(row_too_long(self.record_line));
+                    }
+                    let grown = self.record.len() * 2;
+                    self.record.resize(grown, 0);
+                }
+                ReadRecordResult::OutputEndsFull => {
+                    let grown = self.ends.len() * 2;
+                    self.ends.resize(grown, 0);
+                }
+                ReadRecordResult::Record => {
+                    if let Some(row) = self.take_record()? {
+                        rows.push(row);
+                    }
+                }
+                ReadRecordResult::End => return Ok(rows),
+            }
+        }
+    }
+
+    /// Consumes the completed record: the first one is the header, later ones are rows.
+    fn take_record(&mut self) -> Result<Option<DecodedRow>, ServiceError> {
+        let line = self.record_line;
+        let mut fields = Vec::with_capacity(self.ends_len);
+        let mut start = 0;
+        for &end in &self.ends[..self.ends_len] {
+            fields.push(std::str::from_utf8(&self.record[start..end]));
+            start = end;
+        }
+        self.record_len = 0;
+        self.ends_len = 0;
+        self.in_record = false;
+
+        let Some(columns) = &self.columns else {
+            self.columns = Some(parse_header(&fields)?);
+            return Ok(None);
+        };
+
+        let field = |index: usize, name: &str| match fields.get(index) {
+            Some(Ok(value)) => Ok(value.to_string()),
+            Some(Err(_)) => Err(format!("{} is not valid UTF-8", name)),
+            None => Err(format!("Missing {} column", name)),
+        };
+        let email = field(columns.email, "email");
+        let row = match (email, field(columns.name, "name")) {
+            (Ok(email), Ok(name)) => Ok(ImportRow {
+                line,
+                request: CreateUserRequest { email, name },
+            }),
+            (email, name) => Err(ImportRowError {
+                line,
+                email: email.as_ref().ok().cloned(),
+                error: email.err().or(name.err()).unwrap_or_default(),
+            }),
+        };
+
+        Ok(Some(row))
+    }
+}
+
+fn parse_header(fields: &[Result<&str, std::str::Utf8Error>]) -> Result<Columns, ServiceError> {
+    let position = |name: &str| {
+        fields.iter().position(|field| {
+            matches!(field, Ok(value) if value.trim().trim_start_matches('\u{feff}').eq_ignore_ascii_case(name))
+        })
+    };
+
+    match (position("email"), position("name")) {
+        (Some(email), Some(name)) => Ok(Columns { email, name }),
+        _ => Err(ServiceError::Validation(
+            "CSV header must include email and name columns".to_string(),
+        )),
+    }
+}
+
+struct NdjsonDecoder {
+    buffer: Vec<u8>,
+    line: u64,
+}
+
+impl NdjsonDecoder {
+    fn new() -> Self {
+        Self { buffer: Vec::new(), line: 1 }
+    }
+
+    fn decode(&mut self, input: &[u8], at_end: bool) -> Result<Vec<DecodedRow>, ServiceError> {
+        self.buffer.extend_from_slice(input);
+        let mut rows = Vec::new();
+        let mut start = 0;
+
+        while let Some(offset) = self.buffer[start..].iter().position(|&b| b == b'\n') {
+            let end = start + offset;
+            if let Some(row) = self.decode_line(start, end) {
+                rows.push(row);
+            }
+            self.line += 1;
+            start = end + 1;
+        }
+
+        if at_end && start < self.buffer.len() {
+            let end = self.buffer.len();
+            if let Some(row) = self.decode_line(start, end) {
+                rows.push(row);
+            }
+            start = end;
+        }
+
+        self.buffer.drain(..start);
+        if self.buffer.len() > MAX_ROW_BYTES {
+            return Err(row_too_long(self.line));
+        }
+
+        Ok(rows)
+    }
+
+    fn decode_line(&self, start: usize, end: usize) -> Option<DecodedRow> {
+        let bytes = &self.buffer[start..end];
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            return None;
+        }
+
+        Some(
+            serde_json::from_slice::<CreateUserRequest>(bytes)
+                .map(|request| ImportRow { line: self.line, request })
+                .map_err(|err| ImportRowError {
+                    line: self.line,
+                    email: serde_json::from_slice::<serde_json::Value>(bytes)
+                        .ok()
+                        .and_then(|value| value.get("email")?.as_str().map(str::to_string)),
+                    error: format!("Invalid JSON row: {}", err),
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `input` one byte at a time, the worst case for rows split across chunks.
+    fn decode_bytewise(format: ImportFormat, input: &[u8]) -> Vec<DecodedRow> {
+        let mut decoder = ImportDecoder::new(format);
+        let mut rows = Vec::new();
+        for byte in input {
+            rows.extend(decoder.feed(std::slice::from_ref(byte)).unwrap());
+        }
+        rows.extend(decoder.finish().unwrap());
+        rows
+    }
+
+    fn decode_whole(format: ImportFormat, input: &[u8]) -> Result<Vec<DecodedRow>, ServiceError> {
+        let mut decoder = ImportDecoder::new(format);
+        let mut rows = decoder.feed(input)?;
+        rows.extend(decoder.finish()?);
+        Ok(rows)
+    }
+
+    #[test]
+    fn test_csv_rows_with_header_in_any_order() {
+        let input = b"name,id,email\r\nAda Lovelace,1,ada@example.com\r\n\"Hopper, Grace\",2,grace@example.com";
+
+        for rows in [decode_bytewise(ImportFormat::Csv, input), decode_whole(ImportFormat::Csv, input).unwrap()] {
+            assert_eq!(rows.len(), 2);
+            let first = rows[0].as_ref().unwrap();
+            assert_eq!(first.line, 2);
+            assert_eq!(first.request.email, "ada@example.com");
+            assert_eq!(first.request.name, "Ada Lovelace");
+            let second = rows[1].as_ref().unwrap();
+            assert_eq!(second.line, 3);
+            assert_eq!(second.request.name, "Hopper, Grace");
+        }
+    }
+
+    #[test]
+    fn test_csv_line_numbers_skip_blank_lines_and_multiline_fields() {
+        let input = b"email,name\n\n\"multi@example.com\",\"Two\nLines\"\n\nnext@example.com,Next\n";
+        let rows = decode_bytewise(ImportFormat::Csv, input);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].as_ref().unwrap().line, 3);
+        assert_eq!(rows[0].as_ref().unwrap().request.name, "Two\nLines");
+        assert_eq!(rows[1].as_ref().unwrap().line, 6);
+    }
+
+    #[test]
+    fn test_csv_short_row_is_a_row_error() {
+        let rows = decode_whole(ImportFormat::Csv, b"email,name\nonly@example.com\n").unwrap();
+
+        let error = rows[0].as_ref().unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.email.as_deref(), Some("only@example.com"));
+        assert!(error.error.contains("name"));
+    }
+
+    #[test]
+    fn test_csv_header_must_name_required_columns() {
+        let result = decode_whole(ImportFormat::Csv, b"mail,name\nx@example.com,X\n");
+        assert!(matches!(result, Err(ServiceError::Validation(_))));
+    }
+
+    #[test]
+    fn test_ndjson_rows_and_errors() {
+        let input = b"{\"email\":\"a@example.com\",\"name\":\"A\"}\n\n{\"email\":\"b@example.com\"}\r\n{\"email\":\"c@example.com\",\"name\":\"C\"}";
+
+        for rows in [decode_bytewise(ImportFormat::Ndjson, input), decode_whole(ImportFormat::Ndjson, input).unwrap()] {
+            assert_eq!(rows.len(), 3);
+            assert_eq!(rows[0].as_ref().unwrap().line, 1);
+
+            let error = rows[1].as_ref().unwrap_err();
+            assert_eq!(error.line, 3);
+            assert_eq!(error.email.as_deref(), Some("b@example.com"));
+
+            assert_eq!(rows[2].as_ref().unwrap().line, 4);
+            assert_eq!(rows[2].as_ref().unwrap().request.name, "C");
+        }
+    }
+
+    #[test]
+    fn test_oversized_row_is_rejected() {
+        let mut decoder = ImportDecoder::new(ImportFormat::Ndjson);
+        let result = decoder.feed(&vec![b'x'; MAX_ROW_BYTES + 1]);
+        assert!(matches!(result, Err(ServiceError::Validation(_))));
+    }
+}
@@ -0,0 +1,32 @@
+use futures_util::future::{self, BoxFuture};
+use thiserror::Error;
+
+/// A plain-text email.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct MailError(pub String);
+
+/// Delivers transactional email such as email change confirmations.
+pub trait Mailer: Send + Sync {
+    fn send(&self, message: EmailMessage) -> BoxFuture<'_, Result<(), MailError>>;
+}
+
+/// Writes messages to the log instead of sending them; the default until an SMTP or API
+/// transport is configured, and handy in development where the links can be copied from
+/// the log.
+#[derive(Debug, Default)]
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, message: EmailMessage) -> BoxFuture<'_, Result<(), MailError>> {
+        log::info!("Email to {}: {}\n{}", message.to, message.subject, message.body);
+        Box::pin(future::ready(Ok(())))
+    }
+}
@@ -0,0 +1,532 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use futures_util::future::{self, BoxFuture};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use sqlx::error::{DatabaseError, ErrorKind};
+use thiserror::Error;
+use uuid::Uuid;
+use crate::clock::{Clock, SystemClock};
+use crate::dao::user_dao::UserPatchOutcome;
+use crate::errors::ServiceError;
+use crate::ids::{IdGenerator, UuidV7Generator};
+use crate::models::email::email_key;
+use crate::models::user::{CreateUserRequest, UpdateUserRequest, User};
+use crate::models::user_export::UserFilter;
+use crate::models::user_search::{search_terms, UserSearchMatch};
+use super::user_store::{PatchFn, UserStore};
+
+/// Users kept in memory, for `serve --mock`, benchmarks and tests that need no database. It
+/// follows the same rules as [`UserDao`](crate::dao::user_dao::UserDao) except that no
+/// outbox events are recorded, and search approximates Postgres' full-text and trigram
+/// matching.
+pub struct MemoryUserStore {
+    state: Mutex<MemoryState>,
+    clock: Arc<dyn Clock>,
+    ids: Arc<dyn IdGenerator>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    users: HashMap<Uuid, User>,
+    /// User id by [`email_key`], enforcing the unique index on the normalized email
+    emails: HashMap<String, Uuid>,
+}
+
+/// Stands in for the unique violation Postgres reports when an email is already taken.
+#[derive(Debug, Error)]
+#[error("duplicate key value violates unique constraint \"users_email_normalized_key\"")]
+pub struct EmailTaken;
+
+impl DatabaseError for EmailTaken {
+    fn message(&self) -> &str {
+        "duplicate key value violates unique constraint \"users_email_normalized_key\""
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Borrowed("23505"))
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::UniqueViolation
+    }
+}
+
+fn email_taken() -> sqlx::Error {
+    sqlx::Error::Database(Box::new(EmailTaken))
+}
+
+impl Default for MemoryUserStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryUserStore {
+    /// An empty store using the system clock and UUIDv7 ids.
+    pub fn new() -> Self {
+        Self::with_clock_and_ids(Arc::new(SystemClock), Arc::new(UuidV7Generator))
+    }
+
+    pub fn with_clock_and_ids(clock: Arc<dyn Clock>, ids: Arc<dyn IdGenerator>) -> Self {
+        Self {
+            state: Mutex::new(MemoryState::default()),
+            clock,
+            ids,
+        }
+    }
+
+    /// Adds users exactly as given, such as those of a fixture. Fails without adding anything
+    /// if an email is taken, within `users` or by a stored user, or an id is stored already.
+    pub fn insert_users(&self, users: Vec<User>) -> Result<(), sqlx::Error> {
+        let mut state = self.lock();
+        let mut emails = HashSet::new();
+        let mut ids = HashSet::new();
+        for user in &users {
+            if state.emails.contains_key(&email_key(&user.email)) || !emails.insert(email_key(&user.email)) {
+                return Err(email_taken());
+            }
+            if state.users.contains_key(&user.id) || !ids.insert(user.id) {
+                return Err(sqlx::Error::Protocol(format!("User {} already exists", user.id)));
+            }
+        }
+
+        for user in users {
+            state.insert(user);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryState> {
+        // A panic while holding the lock cannot leave the maps half updated, so carry on
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn create(&self, request: CreateUserRequest) -> Result<User, sqlx::Error> {
+        let mut state = self.lock();
+        if state.emails.contains_key(&email_key(&request.email)) {
+            return Err(email_taken());
+        }
+
+        let now = self.clock.now();
+        let user = User {
+            id: self.ids.new_id(),
+            email: request.email,
+            name: request.name,
+            version: 1,
+            created_at: now,
+            updated_at: now,
+            suspended_at: None,
+        };
+        state.insert(user.clone());
+        Ok(user)
+    }
+
+    fn update(&self, id: Uuid, request: &UpdateUserRequest, expected_versions: Option<&[i64]>) -> Option<User> {
+        let mut state = self.lock();
+        let user = state.users.get(&id)?;
+        if !version_matches(user, expected_versions) || email_key(&user.email) != email_key(&request.email) {
+            return None;
+        }
+
+        Some(state.write(id, request, self.clock.now()))
+    }
+
+    fn patch(
+        &self,
+        id: Uuid,
+        expected_versions: Option<&[i64]>,
+        apply: PatchFn<'_>,
+    ) -> Result<UserPatchOutcome<ServiceError>, sqlx::Error> {
+        let mut state = self.lock();
+        let Some(current) = state.users.get(&id) else {
+            return Ok(UserPatchOutcome::NotFound);
+        };
+        if !version_matches(current, expected_versions) {
+            return Ok(UserPatchOutcome::VersionMismatch);
+        }
+
+        let request = match apply(current) {
+            Ok(request) => request,
+            Err(err) => return Ok(UserPatchOutcome::Rejected(err)),
+        };
+        if state.emails.get(&email_key(&request.email)).is_some_and(|owner| *owner != id) {
+            return Err(email_taken());
+        }
+
+        Ok(UserPatchOutcome::Updated(state.write(id, &request, self.clock.now())))
+    }
+
+    fn suspend(&self, id: Uuid, suspended: bool) -> Option<User> {
+        let mut state = self.lock();
+        let now = self.clock.now();
+        let user = state.users.get_mut(&id)?;
+        if user.suspended_at.is_some() == suspended {
+            return None;
+        }
+
+        user.suspended_at = suspended.then_some(now);
+        user.version += 1;
+        user.updated_at = now;
+        Some(user.clone())
+    }
+
+    fn delete(&self, id: Uuid) -> Option<User> {
+        let mut state = self.lock();
+        let user = state.users.remove(&id)?;
+        state.emails.remove(&email_key(&user.email));
+        Some(user)
+    }
+
+    fn filtered(&self, filter: &UserFilter) -> Vec<User> {
+        let email = filter.email_contains.as_deref().map(str::to_lowercase);
+        let name = filter.name_contains.as_deref().map(str::to_lowercase);
+
+        let mut users: Vec<User> = self
+            .lock()
+            .users
+            .values()
+            .filter(|user| email.as_ref().is_none_or(|email| user.email.to_lowercase().contains(email)))
+            .filter(|user| name.as_ref().is_none_or(|name| user.name.to_lowercase().contains(name)))
+            .filter(|user| filter.created_after.is_none_or(|after| user.created_at >= after))
+            .filter(|user| filter.created_before.is_none_or(|before| user.created_at < before))
+            .cloned()
+            .collect();
+        users.sort_by_key(|user| (user.created_at, user.id));
+        users
+    }
+
+    fn search(&self, query: &str, terms: &[String], similarity_threshold: f64, limit: i64) -> Vec<UserSearchMatch> {
+        let query_trigrams = trigrams(query);
+
+        let mut matches: Vec<UserSearchMatch> = self
+            .lock()
+            .users
+            .values()
+            .filter_map(|user| {
+                let words = search_terms(&format!("{} {}", user.name, user.email));
+                let full_text = !terms.is_empty()
+                    && terms.iter().all(|term| words.iter().any(|word| word.starts_with(term.as_str())));
+                let similarity = word_similarity(&query_trigrams, &user.name)
+                    .max(word_similarity(&query_trigrams, &user.email));
+                if !full_text && similarity < similarity_threshold {
+                    return None;
+                }
+
+                // Postgres adds a full-text rank; every full-text match ranks alike here
+                let rank = if full_text { 0.1 } else { 0.0 };
+                Some(UserSearchMatch {
+                    user: user.clone(),
+                    score: (rank + similarity) as f32,
+                })
+            })
+            .collect();
+
+        matches.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| b.user.created_at.cmp(&a.user.created_at))
+        });
+        matches.truncate(usize::try_from(limit).unwrap_or(0));
+        matches
+    }
+}
+
+impl MemoryState {
+    fn insert(&mut self, user: User) {
+        self.emails.insert(email_key(&user.email), user.id);
+        self.users.insert(user.id, user);
+    }
+
+    /// Writes new fields to a stored user, bumping its version.
+    fn write(&mut self, id: Uuid, request: &UpdateUserRequest, now: chrono::DateTime<chrono::Utc>) -> User {
+        let user = self.users.get_mut(&id).expect("user checked by the caller");
+        let old_key = email_key(&user.email);
+        user.email = request.email.clone();
+        user.name = request.name.clone();
+        user.version += 1;
+        user.updated_at = now;
+        let user = user.clone();
+
+        self.emails.remove(&old_key);
+        self.emails.insert(email_key(&user.email), id);
+        user
+    }
+}
+
+fn version_matches(user: &User, expected_versions: Option<&[i64]>) -> bool {
+    expected_versions.is_none_or(|versions| versions.contains(&user.version))
+}
+
+/// Trigrams of each word, padded the way `pg_trgm` pads them.
+fn trigrams(text: &str) -> HashSet<[char; 3]> {
+    let mut trigrams = HashSet::new();
+    for word in search_terms(text) {
+        let padded: Vec<char> = "  ".chars().chain(word.chars()).chain(" ".chars()).collect();
+        trigrams.extend(padded.windows(3).map(|window| [window[0], window[1], window[2]]));
+    }
+    trigrams
+}
+
+/// Like `pg_trgm`'s `word_similarity`: the best share of the query's trigrams found in one
+/// word of `text`, or in all of it.
+fn word_similarity(query_trigrams: &HashSet<[char; 3]>, text: &str) -> f64 {
+    if query_trigrams.is_empty() {
+        return 0.0;
+    }
+
+    search_terms(text)
+        .iter()
+        .map(|word| trigrams(word))
+        .chain(std::iter::once(trigrams(text)))
+        .map(|candidate| {
+            let shared = query_trigrams.intersection(&candidate).count();
+            shared as f64 / query_trigrams.len() as f64
+        })
+        .fold(0.0, f64::max)
+}
+
+impl UserStore for MemoryUserStore {
+    fn create_user(&self, request: CreateUserRequest) -> BoxFuture<'_, Result<User, sqlx::Error>> {
+        Box::pin(future::ready(self.create(request)))
+    }
+
+    fn update_user<'a>(
+        &'a self,
+        id: Uuid,
+        request: &'a UpdateUserRequest,
+        expected_versions: Option<&'a [i64]>,
+    ) -> BoxFuture<'a, Result<Option<User>, sqlx::Error>> {
+        Box::pin(future::ready(Ok(self.update(id, request, expected_versions))))
+    }
+
+    fn patch_user<'a>(
+        &'a self,
+        id: Uuid,
+        expected_versions: Option<&'a [i64]>,
+        apply: PatchFn<'a>,
+    ) -> BoxFuture<'a, Result<UserPatchOutcome<ServiceError>, sqlx::Error>> {
+        Box::pin(future::ready(self.patch(id, expected_versions, apply)))
+    }
+
+    fn get_user_by_id(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(future::ready(Ok(self.lock().users.get(&id).cloned())))
+    }
+
+    fn get_users_by_ids<'a>(&'a self, ids: &'a [Uuid]) -> BoxFuture<'a, Result<Vec<User>, sqlx::Error>> {
+        let state = self.lock();
+        let users = ids.iter().filter_map(|id| state.users.get(id).cloned()).collect();
+        Box::pin(future::ready(Ok(users)))
+    }
+
+    fn get_user_by_email<'a>(&'a self, email: &'a str) -> BoxFuture<'a, Result<Option<User>, sqlx::Error>> {
+        let state = self.lock();
+        let user = state.emails.get(&email_key(email)).and_then(|id| state.users.get(id)).cloned();
+        Box::pin(future::ready(Ok(user)))
+    }
+
+    fn set_suspended(&self, id: Uuid, suspended: bool) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(future::ready(Ok(self.suspend(id, suspended))))
+    }
+
+    fn delete_user(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(future::ready(Ok(self.delete(id))))
+    }
+
+    fn get_all_users(&self) -> BoxFuture<'_, Result<Vec<User>, sqlx::Error>> {
+        let mut users = self.filtered(&UserFilter::default());
+        users.reverse();
+        Box::pin(future::ready(Ok(users)))
+    }
+
+    fn stream_users(&self, filter: &UserFilter) -> BoxStream<'static, Result<User, sqlx::Error>> {
+        stream::iter(self.filtered(filter).into_iter().map(Ok)).boxed()
+    }
+
+    fn search_users<'a>(
+        &'a self,
+        query: &'a str,
+        terms: &'a [String],
+        similarity_threshold: f64,
+        limit: i64,
+    ) -> BoxFuture<'a, Result<Vec<UserSearchMatch>, sqlx::Error>> {
+        Box::pin(future::ready(Ok(self.search(query, terms, similarity_threshold, limit))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+    use futures_util::TryStreamExt;
+    use crate::clock::FixedClock;
+    use crate::ids::SequentialIdGenerator;
+
+    fn store() -> (MemoryUserStore, Arc<FixedClock>) {
+        let clock = Arc::new(FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap()));
+        let store = MemoryUserStore::with_clock_and_ids(clock.clone(), Arc::new(SequentialIdGenerator::new()));
+        (store, clock)
+    }
+
+    fn request(email: &str, name: &str) -> CreateUserRequest {
+        CreateUserRequest { email: email.to_string(), name: name.to_string() }
+    }
+
+    fn update(email: &str, name: &str) -> UpdateUserRequest {
+        UpdateUserRequest { email: email.to_string(), name: name.to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_emails_are_unique_ignoring_case() {
+        let (store, _) = store();
+        let ada = store.create_user(request("ada@example.com", "Ada")).await.unwrap();
+        assert_eq!(ada.id, Uuid::from_u128(1));
+
+        let err = store.create_user(request(" ADA@example.com", "Imposter")).await.unwrap_err();
+        assert!(matches!(err, sqlx::Error::Database(db_err) if db_err.is_unique_violation()));
+
+        store.delete_user(ada.id).await.unwrap();
+        assert!(store.create_user(request("Ada@example.com", "Ada again")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_update_checks_versions_and_email_changes() {
+        let (store, clock) = store();
+        let ada = store.create_user(request("ada@example.com", "Ada")).await.unwrap();
+        clock.advance(Duration::minutes(5));
+
+        let updated = store
+            .update_user(ada.id, &update("ADA@example.com", "Ada L."), Some(&[1]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.version, 2);
+        assert_eq!(updated.updated_at, ada.created_at + Duration::minutes(5));
+
+        assert!(store.update_user(ada.id, &update("ada@example.com", "Stale"), Some(&[1])).await.unwrap().is_none());
+        assert!(store.update_user(ada.id, &update("new@example.com", "Ada"), None).await.unwrap().is_none());
+        assert!(store.get_user_by_email("ada@EXAMPLE.com").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_patch_reports_each_outcome() {
+        let (store, _) = store();
+        let ada = store.create_user(request("ada@example.com", "Ada")).await.unwrap();
+        store.create_user(request("alan@example.com", "Alan")).await.unwrap();
+
+        let outcome = store
+            .patch_user(ada.id, None, Box::new(|user| Ok(update(&user.email, "Ada Lovelace"))))
+            .await
+            .unwrap();
+        assert!(matches!(outcome, UserPatchOutcome::Updated(user) if user.name == "Ada Lovelace" && user.version == 2));
+
+        let outcome = store.patch_user(ada.id, Some(&[1]), Box::new(|user| Ok(update(&user.email, "x")))).await;
+        assert!(matches!(outcome, Ok(UserPatchOutcome::VersionMismatch)));
+
+        let outcome = store
+            .patch_user(ada.id, None, Box::new(|_| Err(ServiceError::Validation("no".to_string()))))
+            .await;
+        assert!(matches!(outcome, Ok(UserPatchOutcome::Rejected(ServiceError::Validation(_)))));
+
+        let outcome = store.patch_user(ada.id, None, Box::new(|_| Ok(update("alan@example.com", "Ada")))).await;
+        assert!(matches!(outcome, Err(sqlx::Error::Database(_))));
+
+        let outcome = store.patch_user(Uuid::from_u128(99), None, Box::new(|_| Ok(update("x@example.com", "x")))).await;
+        assert!(matches!(outcome, Ok(UserPatchOutcome::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_suspension_is_idempotent() {
+        let (store, _) = store();
+        let ada = store.create_user(request("ada@example.com", "Ada")).await.unwrap();
+
+        let suspended = store.set_suspended(ada.id, true).await.unwrap().unwrap();
+        assert!(suspended.suspended_at.is_some());
+        assert!(store.set_suspended(ada.id, true).await.unwrap().is_none());
+        let lifted = store.set_suspended(ada.id, false).await.unwrap().unwrap();
+        assert_eq!((lifted.suspended_at, lifted.version), (None, 3));
+    }
+
+    #[tokio::test]
+    async fn test_listing_and_filtering_orders_by_creation() {
+        let (store, clock) = store();
+        for (email, name) in [("ada@example.com", "Ada"), ("alan@example.com", "Alan"), ("grace@example.com", "Grace")] {
+            store.create_user(request(email, name)).await.unwrap();
+            clock.advance(Duration::days(1));
+        }
+
+        let all: Vec<String> = store.get_all_users().await.unwrap().into_iter().map(|user| user.name).collect();
+        assert_eq!(all, ["Grace", "Alan", "Ada"]);
+
+        let filter = UserFilter {
+            name_contains: Some("A".to_string()),
+            created_before: Some(Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap()),
+            ..UserFilter::default()
+        };
+        let streamed: Vec<User> = store.stream_users(&filter).try_collect().await.unwrap();
+        let names: Vec<&str> = streamed.iter().map(|user| user.name.as_str()).collect();
+        assert_eq!(names, ["Ada", "Alan"]);
+    }
+
+    #[tokio::test]
+    async fn test_search_matches_prefixes_and_misspellings() {
+        let (store, _) = store();
+        store.create_user(request("jdoe@example.com", "Jonathan Doe")).await.unwrap();
+        store.create_user(request("ada@example.com", "Ada Lovelace")).await.unwrap();
+
+        let terms = search_terms("jo do");
+        let found = store.search_users("jo do", &terms, 0.4, 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].user.name, "Jonathan Doe");
+
+        let terms = search_terms("Jonathon");
+        let found = store.search_users("Jonathon", &terms, 0.4, 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].user.name, "Jonathan Doe");
+
+        let terms = search_terms("zebra");
+        assert!(store.search_users("zebra", &terms, 0.4, 10).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_insert_users_rejects_taken_emails_atomically() {
+        let (store, _) = store();
+        let now = Utc::now();
+        let user = |id: u128, email: &str| User {
+            id: Uuid::from_u128(id),
+            email: email.to_string(),
+            name: "Fixture".to_string(),
+            version: 1,
+            created_at: now,
+            updated_at: now,
+            suspended_at: None,
+        };
+
+        store.insert_users(vec![user(1, "a@example.com")]).unwrap();
+        assert!(store.insert_users(vec![user(2, "b@example.com"), user(3, "A@example.com")]).is_err());
+        assert!(store.insert_users(vec![user(2, "b@example.com"), user(3, "B@example.com")]).is_err());
+        assert!(store.insert_users(vec![user(1, "c@example.com")]).is_err());
+        assert_eq!(store.len(), 1);
+    }
+}
@@ -1 +1,13 @@
-pub mod user_service;
\ No newline at end of file
+pub mod email_change_service;
+pub mod idempotency_service;
+pub mod import_decoder;
+pub mod mailer;
+pub mod memory_user_store;
+pub mod user_event_stream;
+pub mod user_import_service;
+pub mod user_search_service;
+pub mod user_seeder;
+pub mod user_service;
+pub mod user_store;
+pub mod webhook_dispatcher;
+pub mod webhook_service;
\ No newline at end of file
@@ -0,0 +1,359 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Utc};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+use uuid::{Builder, Uuid};
+use crate::dao::user_dao::UserDao;
+use crate::models::email::email_key;
+use crate::models::user::User;
+
+/// Users inserted per transaction.
+const SEED_BATCH_SIZE: usize = 1000;
+
+/// Domains reserved for documentation (RFC 2606), so seeded addresses never reach a real inbox.
+const EMAIL_DOMAINS: [&str; 3] = ["example.com", "example.org", "example.net"];
+
+/// Language and region whose names seeded users get.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    EnUs,
+    DeDe,
+    FrFr,
+    EsEs,
+    ItIt,
+    PtBr,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 6] = [Locale::EnUs, Locale::DeDe, Locale::FrFr, Locale::EsEs, Locale::ItIt, Locale::PtBr];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::EnUs => "en_US",
+            Locale::DeDe => "de_DE",
+            Locale::FrFr => "fr_FR",
+            Locale::EsEs => "es_ES",
+            Locale::ItIt => "it_IT",
+            Locale::PtBr => "pt_BR",
+        }
+    }
+
+    /// Accepts `de_DE`, `de-DE` or just the language, `de`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let language = value.split(['_', '-']).next()?.to_ascii_lowercase();
+        match language.as_str() {
+            "en" => Some(Locale::EnUs),
+            "de" => Some(Locale::DeDe),
+            "fr" => Some(Locale::FrFr),
+            "es" => Some(Locale::EsEs),
+            "it" => Some(Locale::ItIt),
+            "pt" => Some(Locale::PtBr),
+            _ => None,
+        }
+    }
+
+    fn first_names(&self) -> &'static [&'static str] {
+        match self {
+            Locale::EnUs => &[
+                "James", "Mary", "Robert", "Patricia", "John", "Jennifer", "Michael", "Linda", "David",
+                "Elizabeth", "William", "Barbara", "Richard", "Susan", "Joseph", "Jessica", "Thomas",
+                "Sarah", "Christopher", "Karen",
+            ],
+            Locale::DeDe => &[
+                "Lukas", "Anna", "Jürgen", "Lena", "Maximilian", "Sophie", "Felix", "Marie", "Jonas",
+                "Hannah", "Leon", "Laura", "Tobias", "Julia", "Matthias", "Katharina", "Stefan",
+                "Jörg", "Björn", "Käthe",
+            ],
+            Locale::FrFr => &[
+                "Jean", "Marie", "Pierre", "Camille", "Louis", "Léa", "Gabriel", "Chloé", "Hugo", "Manon",
+                "Théo", "Inès", "Mathéo", "Zoé", "Raphaël", "Élodie", "Jérôme", "Hélène", "François",
+                "Agnès",
+            ],
+            Locale::EsEs => &[
+                "Antonio", "María", "José", "Carmen", "Manuel", "Lucía", "Francisco", "Sofía", "Javier",
+                "Martina", "Alejandro", "Paula", "Sergio", "Elena", "Álvaro", "Inés", "Jesús", "Ángela",
+                "Íñigo", "Begoña",
+            ],
+            Locale::ItIt => &[
+                "Giuseppe", "Maria", "Giovanni", "Giulia", "Francesco", "Chiara", "Alessandro", "Sara",
+                "Lorenzo", "Martina", "Matteo", "Francesca", "Andrea", "Federica", "Nicolò", "Aurora",
+                "Luca", "Elisa", "Niccolò", "Beatrice",
+            ],
+            Locale::PtBr => &[
+                "João", "Maria", "Gabriel", "Ana", "Lucas", "Júlia", "Pedro", "Beatriz", "Matheus",
+                "Letícia", "Rafael", "Camila", "Thiago", "Larissa", "Vinícius", "Luíza", "Caio",
+                "Mariana", "Otávio", "Conceição",
+            ],
+        }
+    }
+
+    fn last_names(&self) -> &'static [&'static str] {
+        match self {
+            Locale::EnUs => &[
+                "Smith", "Johnson", "Williams", "Brown", "Jones", "Miller", "Davis", "Wilson", "Anderson",
+                "Taylor", "Thomas", "Moore", "Jackson", "Martin", "Thompson", "White", "Harris", "Clark",
+                "Lewis", "O'Connor",
+            ],
+            Locale::DeDe => &[
+                "Müller", "Schmidt", "Schneider", "Fischer", "Weber", "Meyer", "Wagner", "Becker",
+                "Schulz", "Hoffmann", "Schäfer", "Koch", "Bauer", "Richter", "Klein", "Wolf", "Schröder",
+                "Neumann", "Schwarz", "Weiß",
+            ],
+            Locale::FrFr => &[
+                "Martin", "Bernard", "Dubois", "Thomas", "Robert", "Richard", "Petit", "Durand", "Leroy",
+                "Moreau", "Simon", "Laurent", "Lefèvre", "Michel", "Garcia", "Fournier", "Girard",
+                "Lefebvre", "Mercier", "Rousseau",
+            ],
+            Locale::EsEs => &[
+                "García", "Rodríguez", "González", "Fernández", "López", "Martínez", "Sánchez", "Pérez",
+                "Gómez", "Martín", "Jiménez", "Ruiz", "Hernández", "Díaz", "Moreno", "Muñoz", "Álvarez",
+                "Romero", "Alonso", "Gutiérrez",
+            ],
+            Locale::ItIt => &[
+                "Rossi", "Russo", "Ferrari", "Esposito", "Bianchi", "Romano", "Colombo", "Ricci",
+                "Marino", "Greco", "Bruno", "Gallo", "Conti", "De Luca", "Mancini", "Costa", "Giordano",
+                "Rizzo", "Lombardi", "Moretti",
+            ],
+            Locale::PtBr => &[
+                "Silva", "Santos", "Oliveira", "Souza", "Rodrigues", "Ferreira", "Alves", "Pereira",
+                "Lima", "Gomes", "Costa", "Ribeiro", "Martins", "Carvalho", "Almeida", "Lopes", "Sousa",
+                "Fernandes", "Araújo", "Conceição",
+            ],
+        }
+    }
+}
+
+/// What to generate. The same options, including `seed` and `until`, always produce the same
+/// users, ids included.
+#[derive(Debug, Clone)]
+pub struct SeedOptions {
+    pub count: usize,
+    pub seed: u64,
+    /// Each user's name comes from one of these, picked at random
+    pub locales: Vec<Locale>,
+    /// Users are created over the `days` days before `until`, more of them recently
+    pub days: u32,
+    pub until: DateTime<Utc>,
+}
+
+impl SeedOptions {
+    /// Every locale, spread over the year before `until`.
+    pub fn new(count: usize, seed: u64, until: DateTime<Utc>) -> Self {
+        Self {
+            count,
+            seed,
+            locales: Locale::ALL.to_vec(),
+            days: 365,
+            until,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeedReport {
+    pub generated: usize,
+    /// Generated users that were new; the rest matched the id or email of an existing user
+    pub inserted: usize,
+}
+
+/// Fills a development database with plausible users.
+pub struct UserSeeder {
+    user_dao: UserDao,
+}
+
+impl UserSeeder {
+    pub fn new(user_dao: UserDao) -> Self {
+        Self { user_dao }
+    }
+
+    /// Generates users and inserts them in batches, skipping any that conflict with existing
+    /// users, so running the same seed twice adds nothing the second time.
+    pub async fn seed(&self, options: &SeedOptions) -> Result<SeedReport, sqlx::Error> {
+        let users = generate_users(options);
+        let mut inserted = 0;
+
+        for batch in users.chunks(SEED_BATCH_SIZE) {
+            let mut tx = self.user_dao.begin().await?;
+            inserted += UserDao::insert_users_skipping_conflicts(&mut tx, batch).await?.len();
+            tx.commit().await?;
+        }
+
+        Ok(SeedReport {
+            generated: users.len(),
+            inserted,
+        })
+    }
+}
+
+/// Generates users without touching the database, oldest first. Emails are unique (by
+/// [`email_key`]) and already normalized; ids are UUIDv7 matching `created_at`.
+pub fn generate_users(options: &SeedOptions) -> Vec<User> {
+    let mut rng = ChaCha8Rng::seed_from_u64(options.seed);
+    let locales = if options.locales.is_empty() { &Locale::ALL[..] } else { &options.locales[..] };
+    let span_ms = Duration::days(options.days as i64).num_milliseconds();
+    let mut taken = HashSet::with_capacity(options.count);
+
+    let mut users: Vec<User> = (0..options.count)
+        .map(|_| {
+            let locale = *locales.choose(&mut rng).unwrap();
+            let first = *locale.first_names().choose(&mut rng).unwrap();
+            let last = *locale.last_names().choose(&mut rng).unwrap();
+            let email = unique_email(&mut rng, &mut taken, first, last);
+
+            // The square root skews signups towards `until`, like a growing user base
+            let age_ms = ((1.0 - rng.gen::<f64>().sqrt()) * span_ms as f64) as i64;
+            let created_at = options.until - Duration::milliseconds(age_ms);
+
+            User {
+                id: seeded_id(&mut rng, created_at),
+                email,
+                name: format!("{} {}", first, last),
+                version: 1,
+                created_at,
+                updated_at: created_at,
+                suspended_at: None,
+            }
+        })
+        .collect();
+
+    users.sort_by_key(|user| (user.created_at, user.id));
+    users
+}
+
+fn unique_email(rng: &mut ChaCha8Rng, taken: &mut HashSet<String>, first: &str, last: &str) -> String {
+    let (first, last) = (ascii_fold(first), ascii_fold(last));
+    let local = match rng.gen_range(0..4) {
+        0 => format!("{}.{}", first, last),
+        1 => format!("{}{}", first, last),
+        2 => format!("{}.{}", &first[..1], last),
+        _ => format!("{}_{}{}", first, last, rng.gen_range(1..100)),
+    };
+    let domain = EMAIL_DOMAINS.choose(rng).unwrap();
+
+    let mut email = format!("{}@{}", local, domain);
+    let mut suffix = 2;
+    while !taken.insert(email_key(&email)) {
+        email = format!("{}{}@{}", local, suffix, domain);
+        suffix += 1;
+    }
+    email
+}
+
+/// A UUIDv7 for `created_at` whose random bits come from the seeded generator.
+fn seeded_id(rng: &mut ChaCha8Rng, created_at: DateTime<Utc>) -> Uuid {
+    let random: [u8; 10] = rng.gen();
+    Builder::from_unix_timestamp_millis(created_at.timestamp_millis() as u64, &random).into_uuid()
+}
+
+/// Lowercases a name and reduces it to the ASCII letters an email local part would use.
+fn ascii_fold(name: &str) -> String {
+    let mut folded = String::with_capacity(name.len());
+    for c in name.to_lowercase().chars() {
+        match c {
+            'a'..='z' | '0'..='9' => folded.push(c),
+            'à' | 'á' | 'â' | 'ã' | 'å' => folded.push('a'),
+            'ä' | 'æ' => folded.push_str("ae"),
+            'ç' => folded.push('c'),
+            'è' | 'é' | 'ê' | 'ë' => folded.push('e'),
+            'ì' | 'í' | 'î' | 'ï' => folded.push('i'),
+            'ñ' => folded.push('n'),
+            'ò' | 'ó' | 'ô' | 'õ' => folded.push('o'),
+            'ö' | 'œ' => folded.push_str("oe"),
+            'ù' | 'ú' | 'û' => folded.push('u'),
+            'ü' => folded.push_str("ue"),
+            'ß' => folded.push_str("ss"),
+            // Spaces, apostrophes and anything else are dropped
+            _ => {}
+        }
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::models::email::normalize_email;
+
+    fn options(count: usize, seed: u64) -> SeedOptions {
+        SeedOptions::new(count, seed, Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap())
+    }
+
+    #[test]
+    fn test_same_seed_generates_same_users() {
+        let first = generate_users(&options(50, 7));
+        let second = generate_users(&options(50, 7));
+        let other = generate_users(&options(50, 8));
+
+        let emails = |users: &[User]| users.iter().map(|user| user.email.clone()).collect::<Vec<_>>();
+        let ids = |users: &[User]| users.iter().map(|user| user.id).collect::<Vec<_>>();
+        assert_eq!(emails(&first), emails(&second));
+        assert_eq!(ids(&first), ids(&second));
+        assert_ne!(emails(&first), emails(&other));
+    }
+
+    #[test]
+    fn test_emails_are_unique_and_normalized() {
+        // More users than name combinations in a single locale, to force suffixes
+        let mut options = options(1000, 1);
+        options.locales = vec![Locale::DeDe];
+        let users = generate_users(&options);
+
+        let keys: HashSet<String> = users.iter().map(|user| email_key(&user.email)).collect();
+        assert_eq!(keys.len(), users.len());
+        for user in &users {
+            assert_eq!(normalize_email(&user.email).unwrap(), user.email);
+            assert!(user.email.is_ascii());
+        }
+    }
+
+    #[test]
+    fn test_created_at_is_spread_over_the_window() {
+        let options = options(500, 3);
+        let users = generate_users(&options);
+        let start = options.until - Duration::days(365);
+
+        assert!(users.iter().all(|user| user.created_at > start && user.created_at <= options.until));
+        assert!(users.windows(2).all(|pair| pair[0].created_at <= pair[1].created_at));
+        let days: HashSet<_> = users.iter().map(|user| user.created_at.date_naive()).collect();
+        assert!(days.len() > 100);
+        for user in &users {
+            assert_eq!(user.id.get_version_num(), 7);
+            assert_eq!(user.updated_at, user.created_at);
+        }
+    }
+
+    #[test]
+    fn test_names_come_from_the_chosen_locales() {
+        let mut options = options(100, 5);
+        options.locales = vec![Locale::FrFr];
+
+        for user in generate_users(&options) {
+            let first = user.name.split(' ').next().unwrap();
+            assert!(Locale::FrFr.first_names().contains(&first), "{}", user.name);
+        }
+    }
+
+    #[test]
+    fn test_locale_parsing() {
+        assert_eq!(Locale::parse("de_DE"), Some(Locale::DeDe));
+        assert_eq!(Locale::parse("pt-BR"), Some(Locale::PtBr));
+        assert_eq!(Locale::parse("FR"), Some(Locale::FrFr));
+        assert_eq!(Locale::parse("xx"), None);
+        for locale in Locale::ALL {
+            assert_eq!(Locale::parse(locale.as_str()), Some(locale));
+        }
+    }
+
+    #[test]
+    fn test_ascii_fold() {
+        assert_eq!(ascii_fold("Jürgen"), "juergen");
+        assert_eq!(ascii_fold("Weiß"), "weiss");
+        assert_eq!(ascii_fold("Íñigo"), "inigo");
+        assert_eq!(ascii_fold("O'Connor"), "oconnor");
+        assert_eq!(ascii_fold("De Luca"), "deluca");
+    }
+}
@@ -1,42 +1,259 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use futures_util::{Stream, TryStreamExt};
 use uuid::Uuid;
-use crate::dao::user_dao::UserDao;
-use crate::models::user::{CreateUserRequest, UserResponse};
+use crate::dao::user_dao::UserPatchOutcome;
+use crate::errors::ServiceError;
+use crate::models::email::{email_key, normalize_email};
+use crate::models::user::{BatchGetUsersResponse, CreateUserRequest, UpdateUserRequest, User, UserPatch, UserResponse};
+use crate::models::user_export::UserFilter;
+use super::user_store::UserStore;
+
+/// Upper bound on the ids a single batch get may request.
+pub const MAX_BATCH_GET_IDS: usize = 100;
+
+const EMAIL_CHANGE_REQUIRED: &str =
+    "A new email address must be confirmed; request it with POST /api/v1/users/{id}/email-change";
+
+/// Fields of the user representation that patches may not change.
+const READ_ONLY_FIELDS: [&str; 5] = ["id", "version", "created_at", "updated_at", "suspended_at"];
 
 pub struct UserService {
-    user_dao: UserDao,
+    user_store: Arc<dyn UserStore>,
 }
 
 impl UserService {
-    pub fn new(user_dao: UserDao) -> Self {
-        Self { user_dao }
+    pub fn new(user_store: Arc<dyn UserStore>) -> Self {
+        Self { user_store }
     }
 
-    pub async fn create_user(&self, request: CreateUserRequest) -> Result<UserResponse, sqlx::Error> {
-        // Basic validation could be added here
-        if request.email.is_empty() || request.name.is_empty() {
-            return Err(sqlx::Error::Protocol("Email and name cannot be empty".to_string()));
-        }
+    pub async fn create_user(&self, mut request: CreateUserRequest) -> Result<UserResponse, ServiceError> {
+        request.email = validate_user_fields(&request.email, &request.name).map_err(ServiceError::Validation)?;
 
-        let user = self.user_dao.create_user(request).await?;
+        let user = self.user_store.create_user(request).await.map_err(map_write_error)?;
         Ok(UserResponse::from(user))
     }
 
+    /// Replaces a user's email and name. `expected_versions` carries the versions named by the
+    /// client's `If-Match` header (`None` for `*`); a stale version fails the precondition.
+    /// The email may only change in case; a new address must be confirmed first.
+    pub async fn update_user(
+        &self,
+        id: Uuid,
+        mut request: UpdateUserRequest,
+        expected_versions: Option<&[i64]>,
+    ) -> Result<UserResponse, ServiceError> {
+        request.email = validate_user_fields(&request.email, &request.name).map_err(ServiceError::Validation)?;
+
+        match self.user_store.update_user(id, &request, expected_versions).await {
+            Ok(Some(user)) => Ok(UserResponse::from(user)),
+            Ok(None) => match self.user_store.get_user_by_id(id).await? {
+                Some(user) if email_key(&user.email) != email_key(&request.email) => {
+                    Err(ServiceError::Unprocessable(EMAIL_CHANGE_REQUIRED.to_string()))
+                }
+                Some(_) => Err(ServiceError::PreconditionFailed(
+                    "User has been modified since it was fetched".to_string(),
+                )),
+                None => Err(ServiceError::NotFound("User")),
+            },
+            Err(err) => Err(map_write_error(err)),
+        }
+    }
+
+    /// Applies a merge patch or JSON patch to the user's representation and saves the result
+    /// atomically after checking it with the same rules as creation.
+    pub async fn patch_user(
+        &self,
+        id: Uuid,
+        patch: UserPatch,
+        expected_versions: Option<&[i64]>,
+    ) -> Result<UserResponse, ServiceError> {
+        let outcome = self
+            .user_store
+            .patch_user(id, expected_versions, Box::new(|user| apply_patch(user, &patch)))
+            .await
+            .map_err(map_write_error)?;
+
+        match outcome {
+            UserPatchOutcome::Updated(user) => Ok(UserResponse::from(user)),
+            UserPatchOutcome::NotFound => Err(ServiceError::NotFound("User")),
+            UserPatchOutcome::VersionMismatch => Err(ServiceError::PreconditionFailed(
+                "User has been modified since it was fetched".to_string(),
+            )),
+            UserPatchOutcome::Rejected(err) => Err(err),
+        }
+    }
+
     pub async fn get_user_by_id(&self, id: Uuid) -> Result<Option<UserResponse>, sqlx::Error> {
-        let user = self.user_dao.get_user_by_id(id).await?;
+        let user = self.user_store.get_user_by_id(id).await?;
         Ok(user.map(UserResponse::from))
     }
 
+    /// Resolves many ids with one query. Duplicate ids are collapsed; users come back in the
+    /// order first requested and ids without a user are listed as missing.
+    pub async fn batch_get_users(&self, ids: Vec<Uuid>) -> Result<BatchGetUsersResponse, ServiceError> {
+        let ids = unique_ids(ids);
+        if ids.is_empty() {
+            return Err(ServiceError::Validation("ids cannot be empty".to_string()));
+        }
+        if ids.len() > MAX_BATCH_GET_IDS {
+            return Err(ServiceError::Validation(format!(
+                "At most {} ids can be requested at once",
+                MAX_BATCH_GET_IDS
+            )));
+        }
+
+        let found = self.user_store.get_users_by_ids(&ids).await?;
+        Ok(order_batch(&ids, found))
+    }
+
+    /// Finds a user by email, ignoring case and the differences normalization removes.
+    pub async fn get_user_by_email(&self, email: &str) -> Result<UserResponse, ServiceError> {
+        let email = normalize_email(email).map_err(ServiceError::Validation)?;
+
+        self.user_store
+            .get_user_by_email(&email)
+            .await?
+            .map(UserResponse::from)
+            .ok_or(ServiceError::NotFound("User"))
+    }
+
+    /// Suspends a user, or lifts the suspension. Already being in that state is not an error.
+    pub async fn set_suspended(&self, id: Uuid, suspended: bool) -> Result<UserResponse, ServiceError> {
+        match self.user_store.set_suspended(id, suspended).await? {
+            Some(user) => Ok(UserResponse::from(user)),
+            None => self
+                .user_store
+                .get_user_by_id(id)
+                .await?
+                .map(UserResponse::from)
+                .ok_or(ServiceError::NotFound("User")),
+        }
+    }
+
+    pub async fn delete_user(&self, id: Uuid) -> Result<UserResponse, ServiceError> {
+        self.user_store
+            .delete_user(id)
+            .await?
+            .map(UserResponse::from)
+            .ok_or(ServiceError::NotFound("User"))
+    }
+
     pub async fn get_all_users(&self) -> Result<Vec<UserResponse>, sqlx::Error> {
-        let users = self.user_dao.get_all_users().await?;
+        let users = self.user_store.get_all_users().await?;
         Ok(users.into_iter().map(UserResponse::from).collect())
     }
+
+    /// Streams the users matching `filter` without buffering them, for exports of any size.
+    pub fn export_users(
+        &self,
+        filter: &UserFilter,
+    ) -> Result<impl Stream<Item = Result<UserResponse, sqlx::Error>> + 'static, ServiceError> {
+        if let (Some(after), Some(before)) = (filter.created_after, filter.created_before) {
+            if after >= before {
+                return Err(ServiceError::Validation(
+                    "created_after must be earlier than created_before".to_string(),
+                ));
+            }
+        }
+
+        Ok(self.user_store.stream_users(filter).map_ok(UserResponse::from))
+    }
+}
+
+fn unique_ids(ids: Vec<Uuid>) -> Vec<Uuid> {
+    let mut seen = HashSet::new();
+    ids.into_iter().filter(|id| seen.insert(*id)).collect()
+}
+
+/// Arranges users found for `ids` in request order and lists the ids that were not found.
+fn order_batch(ids: &[Uuid], found: Vec<User>) -> BatchGetUsersResponse {
+    let mut by_id: HashMap<Uuid, User> = found.into_iter().map(|user| (user.id, user)).collect();
+    let mut response = BatchGetUsersResponse {
+        users: Vec::with_capacity(by_id.len()),
+        missing: Vec::new(),
+    };
+
+    for id in ids {
+        match by_id.remove(id) {
+            Some(user) => response.users.push(UserResponse::from(user)),
+            None => response.missing.push(*id),
+        }
+    }
+
+    response
+}
+
+pub(crate) fn map_write_error(err: sqlx::Error) -> ServiceError {
+    match err {
+        sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+            ServiceError::Conflict("Email is already in use".to_string())
+        }
+        err => err.into(),
+    }
+}
+
+/// Computes the fields a patch produces from the user's current representation.
+fn apply_patch(user: &User, patch: &UserPatch) -> Result<UpdateUserRequest, ServiceError> {
+    let original = serde_json::json!(UserResponse::from(user.clone()));
+    let mut document = original.clone();
+
+    match patch {
+        UserPatch::Merge(merge_patch) => json_patch::merge(&mut document, merge_patch),
+        UserPatch::Json(json_patch) => json_patch::patch(&mut document, json_patch)
+            .map_err(|err| ServiceError::Unprocessable(format!("Patch could not be applied: {}", err)))?,
+    }
+
+    let Some(fields) = document.as_object() else {
+        return Err(ServiceError::Unprocessable("Patched user must be a JSON object".to_string()));
+    };
+    for field in READ_ONLY_FIELDS {
+        if fields.get(field) != original.get(field) {
+            return Err(ServiceError::Unprocessable(format!("Field '{}' is read-only", field)));
+        }
+    }
+    if let Some(unknown) = fields.keys().find(|key| original.get(key.as_str()).is_none()) {
+        return Err(ServiceError::Unprocessable(format!("Unknown field '{}'", unknown)));
+    }
+
+    let string_field = |name: &str| {
+        fields
+            .get(name)
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| ServiceError::Validation(format!("{} must be a string", name)))
+    };
+    let mut request = UpdateUserRequest {
+        email: string_field("email")?,
+        name: string_field("name")?,
+    };
+
+    request.email = validate_user_fields(&request.email, &request.name).map_err(ServiceError::Validation)?;
+    if email_key(&request.email) != email_key(&user.email) {
+        return Err(ServiceError::Unprocessable(EMAIL_CHANGE_REQUIRED.to_string()));
+    }
+    Ok(request)
+}
+
+/// Field rules shared by every write path. Returns the email in its normalized form, which is
+/// what gets stored.
+pub(crate) fn validate_user_fields(email: &str, name: &str) -> Result<String, String> {
+    if email.trim().is_empty() || name.is_empty() {
+        return Err("Email and name cannot be empty".to_string());
+    }
+
+    normalize_email(email)
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::models::user::CreateUserRequest;
+    use crate::services::memory_user_store::MemoryUserStore;
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_user_service_creation() {
         // This test verifies that UserService can be created
         // Note: We skip actual DAO creation since it requires database setup
@@ -70,4 +287,174 @@ mod tests {
         assert!(invalid_email_request.email.is_empty());
         assert!(invalid_name_request.name.is_empty());
     }
+
+    fn create_test_user() -> User {
+        let now = chrono::Utc::now();
+        User {
+            id: Uuid::new_v4(),
+            email: "test@example.com".to_string(),
+            name: "Test User".to_string(),
+            version: 3,
+            created_at: now,
+            updated_at: now,
+            suspended_at: None,
+        }
+    }
+
+    fn json_patch(operations: serde_json::Value) -> UserPatch {
+        UserPatch::Json(serde_json::from_value(operations).unwrap())
+    }
+
+    #[test]
+    fn test_merge_patch_updates_given_fields() {
+        let user = create_test_user();
+        let patch = UserPatch::Merge(serde_json::json!({"name": "Patched Name"}));
+
+        let request = apply_patch(&user, &patch).unwrap();
+        assert_eq!(request.name, "Patched Name");
+        assert_eq!(request.email, "test@example.com");
+    }
+
+    #[test]
+    fn test_merge_patch_null_removes_required_field() {
+        let user = create_test_user();
+        let patch = UserPatch::Merge(serde_json::json!({"email": null}));
+
+        assert!(matches!(apply_patch(&user, &patch), Err(ServiceError::Validation(_))));
+    }
+
+    #[test]
+    fn test_json_patch_with_test_operation() {
+        let user = create_test_user();
+
+        let matching = json_patch(serde_json::json!([
+            {"op": "test", "path": "/email", "value": "test@example.com"},
+            {"op": "replace", "path": "/name", "value": "New Name"}
+        ]));
+        assert_eq!(apply_patch(&user, &matching).unwrap().name, "New Name");
+
+        let failing = json_patch(serde_json::json!([
+            {"op": "test", "path": "/email", "value": "other@example.com"},
+            {"op": "replace", "path": "/name", "value": "New Name"}
+        ]));
+        assert!(matches!(apply_patch(&user, &failing), Err(ServiceError::Unprocessable(_))));
+    }
+
+    #[test]
+    fn test_patch_rejects_read_only_and_unknown_fields() {
+        let user = create_test_user();
+
+        let id_change = UserPatch::Merge(serde_json::json!({"id": Uuid::new_v4()}));
+        assert!(matches!(apply_patch(&user, &id_change), Err(ServiceError::Unprocessable(_))));
+
+        let version_change = json_patch(serde_json::json!([{"op": "replace", "path": "/version", "value": 9}]));
+        assert!(matches!(apply_patch(&user, &version_change), Err(ServiceError::Unprocessable(_))));
+
+        let unknown = UserPatch::Merge(serde_json::json!({"nickname": "tester"}));
+        assert!(matches!(apply_patch(&user, &unknown), Err(ServiceError::Unprocessable(_))));
+    }
+
+    #[test]
+    fn test_patch_result_is_validated_like_creation() {
+        let user = create_test_user();
+        let patch = UserPatch::Merge(serde_json::json!({"name": ""}));
+
+        assert!(matches!(apply_patch(&user, &patch), Err(ServiceError::Validation(_))));
+
+        let wrong_type = UserPatch::Merge(serde_json::json!({"name": 42}));
+        assert!(matches!(apply_patch(&user, &wrong_type), Err(ServiceError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_export_rejects_empty_date_range() {
+        let service = UserService::new(Arc::new(MemoryUserStore::new()));
+        let now = chrono::Utc::now();
+        let filter = UserFilter {
+            created_after: Some(now),
+            created_before: Some(now),
+            ..UserFilter::default()
+        };
+
+        assert!(matches!(service.export_users(&filter), Err(ServiceError::Validation(_))));
+    }
+
+    #[test]
+    fn test_batch_results_follow_request_order() {
+        let first = create_test_user();
+        let second = create_test_user();
+        let unknown = Uuid::new_v4();
+        let ids = unique_ids(vec![second.id, unknown, first.id, second.id]);
+        assert_eq!(ids.len(), 3);
+
+        let response = order_batch(&ids, vec![first.clone(), second.clone()]);
+        let returned: Vec<Uuid> = response.users.iter().map(|user| user.id).collect();
+        assert_eq!(returned, vec![second.id, first.id]);
+        assert_eq!(response.missing, vec![unknown]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_get_validates_id_count() {
+        let service = UserService::new(Arc::new(MemoryUserStore::new()));
+
+        let empty = service.batch_get_users(vec![]).await;
+        assert!(matches!(empty, Err(ServiceError::Validation(_))));
+
+        let too_many = (0..=MAX_BATCH_GET_IDS).map(|_| Uuid::new_v4()).collect();
+        let result = service.batch_get_users(too_many).await;
+        assert!(matches!(result, Err(ServiceError::Validation(message)) if message.contains("At most")));
+    }
+
+    #[test]
+    fn test_patched_email_is_normalized() {
+        let user = create_test_user();
+        let patch = UserPatch::Merge(serde_json::json!({"email": " Test@EXAMPLE.com "}));
+
+        assert_eq!(apply_patch(&user, &patch).unwrap().email, "Test@example.com");
+    }
+
+    #[test]
+    fn test_patch_cannot_move_to_a_new_address() {
+        let user = create_test_user();
+        let patch = UserPatch::Merge(serde_json::json!({"email": "new@example.com"}));
+
+        assert!(matches!(apply_patch(&user, &patch), Err(ServiceError::Unprocessable(_))));
+    }
+
+    #[tokio::test]
+    async fn test_update_explains_why_nothing_was_written() {
+        let service = UserService::new(Arc::new(MemoryUserStore::new()));
+        let created = service
+            .create_user(CreateUserRequest { email: "ada@example.com".to_string(), name: "Ada".to_string() })
+            .await
+            .unwrap();
+        let update = |email: &str| UpdateUserRequest { email: email.to_string(), name: "Ada L.".to_string() };
+
+        let updated = service.update_user(created.id, update("ADA@example.com"), Some(&[1])).await.unwrap();
+        assert_eq!(updated.version, 2);
+
+        let stale = service.update_user(created.id, update("ada@example.com"), Some(&[1])).await;
+        assert!(matches!(stale, Err(ServiceError::PreconditionFailed(_))));
+        let moved = service.update_user(created.id, update("new@example.com"), None).await;
+        assert!(matches!(moved, Err(ServiceError::Unprocessable(_))));
+        let missing = service.update_user(Uuid::new_v4(), update("ada@example.com"), None).await;
+        assert!(matches!(missing, Err(ServiceError::NotFound("User"))));
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_emails_conflict() {
+        let service = UserService::new(Arc::new(MemoryUserStore::new()));
+        let request = |email: &str| CreateUserRequest { email: email.to_string(), name: "Ada".to_string() };
+        service.create_user(request("ada@example.com")).await.unwrap();
+
+        let err = service.create_user(request("Ada@Example.com")).await.unwrap_err();
+        assert!(matches!(err, ServiceError::Conflict(_)));
+    }
+
+    #[test]
+    fn test_validate_user_fields() {
+        assert_eq!(validate_user_fields("test@Example.com", "Test User").unwrap(), "test@example.com");
+        assert!(validate_user_fields("not-an-email", "Test User").is_err());
+        assert!(validate_user_fields("", "Test User").is_err());
+        assert!(validate_user_fields("test@example.com", "").is_err());
+    }
 }
\ No newline at end of file
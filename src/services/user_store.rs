@@ -0,0 +1,132 @@
+use futures_util::future::BoxFuture;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use uuid::Uuid;
+use crate::dao::user_dao::{UserDao, UserPatchOutcome};
+use crate::errors::ServiceError;
+use crate::models::user::{CreateUserRequest, UpdateUserRequest, User};
+use crate::models::user_export::UserFilter;
+use crate::models::user_search::{prefix_tsquery, UserSearchMatch};
+
+/// Derives a user's new fields from its current state; see [`UserStore::patch_user`].
+pub type PatchFn<'a> = Box<dyn FnOnce(&User) -> Result<UpdateUserRequest, ServiceError> + Send + 'a>;
+
+/// Storage behind [`UserService`](super::user_service::UserService) and
+/// [`UserSearchService`](super::user_search_service::UserSearchService): Postgres through
+/// [`UserDao`] when serving, or memory in mock mode. Errors are `sqlx` errors either way, with
+/// duplicate emails reported as unique violations.
+pub trait UserStore: Send + Sync {
+    fn create_user(&self, request: CreateUserRequest) -> BoxFuture<'_, Result<User, sqlx::Error>>;
+
+    /// Replaces a user's fields if the email only changes in case and, with
+    /// `expected_versions`, the stored version is one of them; `None` otherwise.
+    fn update_user<'a>(
+        &'a self,
+        id: Uuid,
+        request: &'a UpdateUserRequest,
+        expected_versions: Option<&'a [i64]>,
+    ) -> BoxFuture<'a, Result<Option<User>, sqlx::Error>>;
+
+    /// Applies `apply` to the current user and writes the result, with no other change to the
+    /// user in between.
+    fn patch_user<'a>(
+        &'a self,
+        id: Uuid,
+        expected_versions: Option<&'a [i64]>,
+        apply: PatchFn<'a>,
+    ) -> BoxFuture<'a, Result<UserPatchOutcome<ServiceError>, sqlx::Error>>;
+
+    fn get_user_by_id(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>>;
+
+    fn get_users_by_ids<'a>(&'a self, ids: &'a [Uuid]) -> BoxFuture<'a, Result<Vec<User>, sqlx::Error>>;
+
+    /// Finds the user owning `email`, compared case-insensitively.
+    fn get_user_by_email<'a>(&'a self, email: &'a str) -> BoxFuture<'a, Result<Option<User>, sqlx::Error>>;
+
+    /// `None` when the user does not exist or is already in the requested state.
+    fn set_suspended(&self, id: Uuid, suspended: bool) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>>;
+
+    fn delete_user(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>>;
+
+    /// Every user, newest first.
+    fn get_all_users(&self) -> BoxFuture<'_, Result<Vec<User>, sqlx::Error>>;
+
+    /// Users matching `filter` in creation order, produced as they are read.
+    fn stream_users(&self, filter: &UserFilter) -> BoxStream<'static, Result<User, sqlx::Error>>;
+
+    /// Users whose name or email has a word starting with every one of `terms`, or resembling
+    /// `query` by at least `similarity_threshold`, best matches first.
+    fn search_users<'a>(
+        &'a self,
+        query: &'a str,
+        terms: &'a [String],
+        similarity_threshold: f64,
+        limit: i64,
+    ) -> BoxFuture<'a, Result<Vec<UserSearchMatch>, sqlx::Error>>;
+}
+
+impl UserStore for UserDao {
+    fn create_user(&self, request: CreateUserRequest) -> BoxFuture<'_, Result<User, sqlx::Error>> {
+        Box::pin(UserDao::create_user(self, request))
+    }
+
+    fn update_user<'a>(
+        &'a self,
+        id: Uuid,
+        request: &'a UpdateUserRequest,
+        expected_versions: Option<&'a [i64]>,
+    ) -> BoxFuture<'a, Result<Option<User>, sqlx::Error>> {
+        Box::pin(UserDao::update_user(self, id, request, expected_versions))
+    }
+
+    fn patch_user<'a>(
+        &'a self,
+        id: Uuid,
+        expected_versions: Option<&'a [i64]>,
+        apply: PatchFn<'a>,
+    ) -> BoxFuture<'a, Result<UserPatchOutcome<ServiceError>, sqlx::Error>> {
+        Box::pin(UserDao::patch_user(self, id, expected_versions, apply))
+    }
+
+    fn get_user_by_id(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(UserDao::get_user_by_id(self, id))
+    }
+
+    fn get_users_by_ids<'a>(&'a self, ids: &'a [Uuid]) -> BoxFuture<'a, Result<Vec<User>, sqlx::Error>> {
+        Box::pin(UserDao::get_users_by_ids(self, ids))
+    }
+
+    fn get_user_by_email<'a>(&'a self, email: &'a str) -> BoxFuture<'a, Result<Option<User>, sqlx::Error>> {
+        Box::pin(UserDao::get_user_by_email(self, email))
+    }
+
+    fn set_suspended(&self, id: Uuid, suspended: bool) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(UserDao::set_suspended(self, id, suspended))
+    }
+
+    fn delete_user(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(UserDao::delete_user(self, id))
+    }
+
+    fn get_all_users(&self) -> BoxFuture<'_, Result<Vec<User>, sqlx::Error>> {
+        Box::pin(UserDao::get_all_users(self))
+    }
+
+    fn stream_users(&self, filter: &UserFilter) -> BoxStream<'static, Result<User, sqlx::Error>> {
+        UserDao::stream_users(self, filter).boxed()
+    }
+
+    fn search_users<'a>(
+        &'a self,
+        query: &'a str,
+        terms: &'a [String],
+        similarity_threshold: f64,
+        limit: i64,
+    ) -> BoxFuture<'a, Result<Vec<UserSearchMatch>, sqlx::Error>> {
+        Box::pin(async move {
+            // An empty tsquery matches nothing, leaving similarity to decide
+            let tsquery = prefix_tsquery(terms).unwrap_or_default();
+            UserDao::search_users(self, query, &tsquery, similarity_threshold, limit).await
+        })
+    }
+}
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+use crate::config::WebhookConfig;
+use crate::dao::webhook_dao::{AttemptRecord, ClaimedDelivery, WebhookDao};
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+const SIGNATURE_VERSION: &str = "v1";
+const MAX_ERROR_LENGTH: usize = 1024;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs `"{timestamp}.{body}"` with HMAC-SHA256, returning the `X-Webhook-Signature` value.
+/// Binding the timestamp into the signature lets receivers reject replayed requests.
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let signature = hex::encode(signing_mac(secret, timestamp, body).finalize().into_bytes());
+    format!("{}={}", SIGNATURE_VERSION, signature)
+}
+
+/// Verifies a signature produced by [`sign_payload`] in constant time. Receivers should also
+/// reject timestamps too far from their own clock.
+pub fn verify_signature(secret: &str, timestamp: i64, body: &[u8], signature: &str) -> bool {
+    let Some(hex_signature) = signature
+        .strip_prefix(SIGNATURE_VERSION)
+        .and_then(|rest| rest.strip_prefix('='))
+    else {
+        return false;
+    };
+    let Ok(expected) = hex::decode(hex_signature) else {
+        return false;
+    };
+
+    signing_mac(secret, timestamp, body).verify_slice(&expected).is_ok()
+}
+
+fn signing_mac(secret: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// Delay before retrying after `attempt` failed attempts: exponential from
+/// `initial_backoff_secs`, capped at `max_backoff_secs`.
+pub fn backoff_delay(config: &WebhookConfig, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(32);
+    let delay = config
+        .initial_backoff_secs
+        .saturating_mul(1u64 << exponent)
+        .min(config.max_backoff_secs);
+    Duration::from_secs(delay)
+}
+
+/// Outcome of one HTTP delivery attempt.
+#[derive(Debug, Clone)]
+pub struct DeliveryOutcome {
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration: Duration,
+}
+
+impl DeliveryOutcome {
+    pub fn is_success(&self) -> bool {
+        matches!(self.status_code, Some(code) if (200..300).contains(&code))
+    }
+}
+
+#[derive(Clone)]
+pub struct WebhookSender {
+    client: reqwest::Client,
+}
+
+impl WebhookSender {
+    pub fn new(timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .user_agent(concat!("tangy-mango-webhooks/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("Failed to build webhook HTTP client");
+
+        Self { client }
+    }
+
+    /// POSTs a signed JSON payload. Never fails: transport errors are reported in the outcome.
+    pub async fn send(
+        &self,
+        url: &str,
+        secret: &str,
+        delivery_id: Uuid,
+        event_type: &str,
+        payload: &serde_json::Value,
+    ) -> DeliveryOutcome {
+        let body = payload.to_string().into_bytes();
+        let timestamp = Utc::now().timestamp();
+        let signature = sign_payload(secret, timestamp, &body);
+        let started = Instant::now();
+
+        let result = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, signature)
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(EVENT_HEADER, event_type)
+            .header(DELIVERY_HEADER, delivery_id.to_string())
+            .body(body)
+            .send()
+            .await;
+
+        match result {
+            Ok(response) => {
+                let status = response.status();
+                let error = if status.is_success() {
+                    None
+                } else {
+                    let text = response.text().await.unwrap_or_default();
+                    Some(truncate(&format!("HTTP {}: {}", status.as_u16(), text)))
+                };
+
+                DeliveryOutcome {
+                    status_code: Some(status.as_u16()),
+                    error,
+                    duration: started.elapsed(),
+                }
+            }
+            Err(err) => DeliveryOutcome {
+                status_code: None,
+                error: Some(truncate(&err.to_string())),
+                duration: started.elapsed(),
+            },
+        }
+    }
+}
+
+fn truncate(message: &str) -> String {
+    message.chars().take(MAX_ERROR_LENGTH).collect()
+}
+
+/// Background worker that relays outbox events into deliveries and sends due deliveries,
+/// retrying with exponential backoff and dead-lettering after `max_attempts`.
+pub struct WebhookDispatcher {
+    webhook_dao: WebhookDao,
+    sender: WebhookSender,
+    config: WebhookConfig,
+}
+
+impl WebhookDispatcher {
+    pub fn new(webhook_dao: WebhookDao, config: WebhookConfig) -> Self {
+        let sender = WebhookSender::new(Duration::from_secs(config.request_timeout_secs));
+        Self { webhook_dao, sender, config }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let poll_interval = Duration::from_millis(self.config.poll_interval_ms);
+        log::info!("Webhook dispatcher started");
+
+        loop {
+            if let Err(err) = self.tick().await {
+                log::error!("Webhook dispatcher tick failed: {}", err);
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Performs one relay-and-deliver pass, returning the number of attempts made.
+    pub async fn tick(&self) -> Result<usize, sqlx::Error> {
+        let relayed = self.webhook_dao.relay_pending_events(self.config.batch_size).await?;
+        if relayed > 0 {
+            log::debug!("Relayed {} user events to webhook deliveries", relayed);
+        }
+
+        // Deliveries in a batch are sent one after another, so the lease must outlive the whole
+        // batch timing out; otherwise another dispatcher could pick up a delivery still in flight
+        let lease = chrono::Duration::seconds(
+            self.config.request_timeout_secs as i64 * (self.config.batch_size + 1),
+        );
+        let claimed = self
+            .webhook_dao
+            .claim_due_deliveries(self.config.batch_size, Utc::now() + lease)
+            .await?;
+
+        let attempts = claimed.len();
+        for delivery in claimed {
+            // One failed write must not leave the rest of the batch leased but unsent
+            let id = delivery.delivery.id;
+            if let Err(err) = self.deliver(delivery).await {
+                log::error!("Failed to record webhook delivery {}: {}", id, err);
+            }
+        }
+
+        Ok(attempts)
+    }
+
+    async fn deliver(&self, claimed: ClaimedDelivery) -> Result<(), sqlx::Error> {
+        let delivery = &claimed.delivery;
+        let outcome = self
+            .sender
+            .send(&claimed.url, &claimed.secret, delivery.id, &delivery.event_type, &delivery.payload)
+            .await;
+
+        let attempt = delivery.attempts + 1;
+        let record = AttemptRecord {
+            delivery_id: delivery.id,
+            attempt,
+            status_code: outcome.status_code.map(i32::from),
+            error: outcome.error.clone(),
+            duration_ms: outcome.duration.as_millis() as i64,
+        };
+
+        if outcome.is_success() {
+            return self.webhook_dao.record_success(&record).await;
+        }
+
+        let retry_at = if (attempt as u32) < self.config.max_attempts {
+            let delay = backoff_delay(&self.config, attempt as u32);
+            Some(Utc::now() + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX))
+        } else {
+            log::warn!(
+                "Webhook delivery {} dead-lettered after {} attempts: {}",
+                delivery.id,
+                attempt,
+                outcome.error.as_deref().unwrap_or("unknown error")
+            );
+            None
+        };
+
+        self.webhook_dao.record_failure(&record, retry_at).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_round_trip() {
+        let body = br#"{"type":"user.created"}"#;
+        let signature = sign_payload("whsec_secret", 1_700_000_000, body);
+
+        assert!(signature.starts_with("v1="));
+        assert!(verify_signature("whsec_secret", 1_700_000_000, body, &signature));
+    }
+
+    #[test]
+    fn test_signature_rejects_tampering() {
+        let body = br#"{"type":"user.created"}"#;
+        let signature = sign_payload("whsec_secret", 1_700_000_000, body);
+
+        assert!(!verify_signature("whsec_other", 1_700_000_000, body, &signature));
+        assert!(!verify_signature("whsec_secret", 1_700_000_001, body, &signature));
+        assert!(!verify_signature("whsec_secret", 1_700_000_000, b"{}", &signature));
+        assert!(!verify_signature("whsec_secret", 1_700_000_000, body, "v1=zz"));
+        assert!(!verify_signature("whsec_secret", 1_700_000_000, body, "deadbeef"));
+    }
+
+    #[test]
+    fn test_signature_is_deterministic() {
+        let first = sign_payload("key", 42, b"payload");
+        let second = sign_payload("key", 42, b"payload");
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_backoff_grows_exponentially_and_caps() {
+        let config = WebhookConfig {
+            initial_backoff_secs: 10,
+            max_backoff_secs: 300,
+            ..WebhookConfig::default()
+        };
+
+        assert_eq!(backoff_delay(&config, 1), Duration::from_secs(10));
+        assert_eq!(backoff_delay(&config, 2), Duration::from_secs(20));
+        assert_eq!(backoff_delay(&config, 3), Duration::from_secs(40));
+        assert_eq!(backoff_delay(&config, 6), Duration::from_secs(300));
+        assert_eq!(backoff_delay(&config, 100), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn test_delivery_outcome_success() {
+        let outcome = |status_code| DeliveryOutcome {
+            status_code,
+            error: None,
+            duration: Duration::from_millis(5),
+        };
+
+        assert!(outcome(Some(200)).is_success());
+        assert!(outcome(Some(204)).is_success());
+        assert!(!outcome(Some(302)).is_success());
+        assert!(!outcome(Some(500)).is_success());
+        assert!(!outcome(None).is_success());
+    }
+}
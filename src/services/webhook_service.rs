@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+use crate::dao::webhook_dao::WebhookDao;
+use crate::errors::ServiceError;
+use crate::models::user_event::UserEventType;
+use crate::models::webhook::{
+    CreateWebhookRequest, CreatedWebhookResponse, DeadLetter, DeliveryHistoryEntry,
+    WebhookSubscriptionResponse, WILDCARD_EVENT_TYPE,
+};
+
+const MIN_SECRET_LENGTH: usize = 16;
+const HISTORY_LIMIT: i64 = 100;
+
+pub struct WebhookService {
+    webhook_dao: WebhookDao,
+}
+
+impl WebhookService {
+    pub fn new(webhook_dao: WebhookDao) -> Self {
+        Self { webhook_dao }
+    }
+
+    pub async fn create_subscription(
+        &self,
+        request: CreateWebhookRequest,
+    ) -> Result<CreatedWebhookResponse, ServiceError> {
+        validate_subscription_request(&request)?;
+
+        let secret = request.secret.unwrap_or_else(generate_secret);
+        let subscription = self
+            .webhook_dao
+            .create_subscription(&request.url, &secret, &request.event_types)
+            .await?;
+
+        Ok(CreatedWebhookResponse {
+            subscription: subscription.into(),
+            secret,
+        })
+    }
+
+    pub async fn get_subscription(&self, id: Uuid) -> Result<WebhookSubscriptionResponse, ServiceError> {
+        self.webhook_dao
+            .get_subscription(id)
+            .await?
+            .map(WebhookSubscriptionResponse::from)
+            .ok_or(ServiceError::NotFound("Webhook"))
+    }
+
+    pub async fn get_all_subscriptions(&self) -> Result<Vec<WebhookSubscriptionResponse>, ServiceError> {
+        let subscriptions = self.webhook_dao.get_all_subscriptions().await?;
+        Ok(subscriptions.into_iter().map(WebhookSubscriptionResponse::from).collect())
+    }
+
+    pub async fn delete_subscription(&self, id: Uuid) -> Result<(), ServiceError> {
+        if self.webhook_dao.delete_subscription(id).await? {
+            Ok(())
+        } else {
+            Err(ServiceError::NotFound("Webhook"))
+        }
+    }
+
+    /// Returns the most recent deliveries for a subscription, each with its attempt log.
+    pub async fn get_delivery_history(
+        &self,
+        subscription_id: Uuid,
+    ) -> Result<Vec<DeliveryHistoryEntry>, ServiceError> {
+        // Surface a 404 for unknown subscriptions rather than an empty history
+        self.get_subscription(subscription_id).await?;
+
+        let deliveries = self
+            .webhook_dao
+            .get_deliveries_for_subscription(subscription_id, HISTORY_LIMIT)
+            .await?;
+        let delivery_ids: Vec<Uuid> = deliveries.iter().map(|delivery| delivery.id).collect();
+
+        let mut attempts_by_delivery: HashMap<Uuid, Vec<_>> = HashMap::new();
+        for attempt in self.webhook_dao.get_attempts_for_deliveries(&delivery_ids).await? {
+            attempts_by_delivery.entry(attempt.delivery_id).or_default().push(attempt);
+        }
+
+        Ok(deliveries
+            .into_iter()
+            .map(|delivery| DeliveryHistoryEntry {
+                attempt_log: attempts_by_delivery.remove(&delivery.id).unwrap_or_default(),
+                delivery,
+            })
+            .collect())
+    }
+
+    pub async fn get_dead_letters(&self) -> Result<Vec<DeadLetter>, ServiceError> {
+        Ok(self.webhook_dao.get_dead_letters(HISTORY_LIMIT).await?)
+    }
+
+    pub async fn replay_dead_letter(&self, id: Uuid) -> Result<DeadLetter, ServiceError> {
+        self.webhook_dao
+            .replay_dead_letter(id)
+            .await?
+            .ok_or(ServiceError::NotFound("Dead letter"))
+    }
+}
+
+fn validate_subscription_request(request: &CreateWebhookRequest) -> Result<(), ServiceError> {
+    let url = reqwest::Url::parse(&request.url)
+        .map_err(|_| ServiceError::Validation("url must be an absolute URL".to_string()))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(ServiceError::Validation("url must use http or https".to_string()));
+    }
+
+    if request.event_types.is_empty() {
+        return Err(ServiceError::Validation("event_types cannot be empty".to_string()));
+    }
+    if let Some(unknown) = request.event_types.iter().find(|event_type| {
+        event_type.as_str() != WILDCARD_EVENT_TYPE && UserEventType::parse(event_type).is_none()
+    }) {
+        return Err(ServiceError::Validation(format!("Unknown event type: {}", unknown)));
+    }
+
+    if let Some(secret) = &request.secret {
+        if secret.len() < MIN_SECRET_LENGTH {
+            return Err(ServiceError::Validation(format!(
+                "secret must be at least {} characters",
+                MIN_SECRET_LENGTH
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn generate_secret() -> String {
+    format!("whsec_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_request() -> CreateWebhookRequest {
+        CreateWebhookRequest {
+            url: "https://partner.example.com/hooks".to_string(),
+            event_types: vec!["user.created".to_string()],
+            secret: None,
+        }
+    }
+
+    #[test]
+    fn test_valid_subscription_request() {
+        assert!(validate_subscription_request(&create_test_request()).is_ok());
+
+        let mut wildcard = create_test_request();
+        wildcard.event_types = vec![WILDCARD_EVENT_TYPE.to_string()];
+        assert!(validate_subscription_request(&wildcard).is_ok());
+    }
+
+    #[test]
+    fn test_subscription_request_rejects_bad_urls() {
+        let mut request = create_test_request();
+        request.url = "not a url".to_string();
+        assert!(matches!(validate_subscription_request(&request), Err(ServiceError::Validation(_))));
+
+        request.url = "ftp://partner.example.com/hooks".to_string();
+        assert!(matches!(validate_subscription_request(&request), Err(ServiceError::Validation(_))));
+    }
+
+    #[test]
+    fn test_subscription_request_rejects_unknown_event_types() {
+        let mut request = create_test_request();
+        request.event_types = vec![];
+        assert!(validate_subscription_request(&request).is_err());
+
+        request.event_types = vec!["user.exploded".to_string()];
+        let err = validate_subscription_request(&request).unwrap_err();
+        assert!(err.to_string().contains("user.exploded"));
+    }
+
+    #[test]
+    fn test_subscription_request_rejects_short_secret() {
+        let mut request = create_test_request();
+        request.secret = Some("short".to_string());
+        assert!(validate_subscription_request(&request).is_err());
+    }
+
+    #[test]
+    fn test_generated_secrets_are_unique() {
+        let first = generate_secret();
+        let second = generate_secret();
+
+        assert!(first.starts_with("whsec_"));
+        assert!(first.len() > MIN_SECRET_LENGTH);
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,349 @@
+//! Graceful shutdown. On SIGTERM or Ctrl-C readiness starts failing so load balancers stop
+//! routing here, then the server stops accepting connections and drains in-flight requests,
+//! background jobs are cancelled and the database pool is closed. Anything cut short on the
+//! way is logged.
+
+use std::error::Error as StdError;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::dev::{Server, ServerHandle, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::rt::task::JoinHandle;
+use actix_web::web::{self, Bytes};
+use crate::app::AppState;
+use crate::config::ShutdownConfig;
+use crate::db::DbPool;
+
+/// How long stopped workers get to drop the requests they cut off.
+const ABORT_SETTLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether the server is shutting down, and the requests it is still serving.
+pub struct Lifecycle {
+    config: ShutdownConfig,
+    shutting_down: AtomicBool,
+    in_flight: AtomicUsize,
+    aborted: AtomicUsize,
+}
+
+impl Lifecycle {
+    pub fn new(config: ShutdownConfig) -> Self {
+        Self {
+            config,
+            shutting_down: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            aborted: AtomicUsize::new(0),
+        }
+    }
+
+    /// False from the moment shutdown begins, so readiness fails before anything stops.
+    pub fn is_ready(&self) -> bool {
+        !self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    /// Requests whose response has not been fully sent yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Requests dropped unfinished since shutdown began.
+    pub fn aborted(&self) -> usize {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    /// How long in-flight requests get to finish once the server stops accepting connections.
+    fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.drain_timeout_secs)
+    }
+
+    fn readiness_grace(&self) -> Duration {
+        Duration::from_secs(self.config.readiness_grace_secs)
+    }
+
+    /// Waits until no request is in flight, for at most `timeout`. True if none is left.
+    async fn drained(&self, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.in_flight() > 0 {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        true
+    }
+
+    fn track(self: Arc<Self>, request: String) -> RequestGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        RequestGuard { lifecycle: self, request, finished: false }
+    }
+}
+
+/// Counts a request as in flight until its response body has been sent, or as aborted if it
+/// is dropped first during shutdown.
+struct RequestGuard {
+    lifecycle: Arc<Lifecycle>,
+    /// Method and path, to say what was aborted
+    request: String,
+    finished: bool,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.lifecycle.in_flight.fetch_sub(1, Ordering::SeqCst);
+        if !self.finished && !self.lifecycle.is_ready() {
+            self.lifecycle.aborted.fetch_add(1, Ordering::SeqCst);
+            log::warn!("Aborted in-flight request {} at shutdown", self.request);
+        }
+    }
+}
+
+/// A response body that finishes its request's [`RequestGuard`] once fully sent, so
+/// streamed responses such as exports count as in flight until their last byte.
+pub(crate) struct TrackedBody {
+    body: BoxBody,
+    guard: Option<RequestGuard>,
+}
+
+impl MessageBody for TrackedBody {
+    type Error = Box<dyn StdError>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.body).poll_next(cx);
+        // A failed body ends the response as surely as a complete one
+        if let Poll::Ready(None | Some(Err(_))) = polled {
+            if let Some(mut guard) = this.guard.take() {
+                guard.finished = true;
+            }
+        }
+        polled
+    }
+}
+
+/// Middleware tracking every request in the registered [`Lifecycle`].
+pub(crate) async fn track_requests(
+    request: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<TrackedBody>, actix_web::Error> {
+    let lifecycle = request.app_data::<web::Data<Lifecycle>>().cloned();
+    let guard = lifecycle.map(|lifecycle| {
+        lifecycle
+            .into_inner()
+            .track(format!("{} {}", request.method(), request.path()))
+    });
+
+    let response = next.call(request).await?;
+    Ok(response.map_body(|_, body| {
+        let mut guard = guard;
+        // Bodies known to be empty may never be polled
+        if matches!(body.size(), BodySize::None | BodySize::Sized(0)) {
+            if let Some(guard) = &mut guard {
+                guard.finished = true;
+            }
+        }
+        TrackedBody { body: body.boxed(), guard }
+    }))
+}
+
+/// Background tasks to cancel at shutdown, by name.
+#[derive(Default)]
+pub struct BackgroundJobs {
+    jobs: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl BackgroundJobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self, name: &'static str, job: impl Future<Output = ()> + 'static) {
+        self.jobs.push((name, actix_web::rt::spawn(job)));
+    }
+
+    /// Cancels every job still running and returns their names. Jobs stop at their next await
+    /// point; all of them tolerate that, as they also must when an instance crashes.
+    pub async fn cancel(self) -> Vec<&'static str> {
+        let mut cancelled = Vec::new();
+        for (name, handle) in self.jobs {
+            if handle.is_finished() {
+                log::warn!("Background job {} had already stopped", name);
+                continue;
+            }
+            handle.abort();
+            let _ = handle.await;
+            log::info!("Cancelled background job {}", name);
+            cancelled.push(name);
+        }
+        cancelled
+    }
+}
+
+/// What shutdown had to cut short.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Requests still unfinished when the drain timeout ran out
+    pub aborted_requests: usize,
+    /// Background jobs that were still running
+    pub cancelled_jobs: Vec<&'static str>,
+}
+
+/// Resolves on the first SIGTERM or Ctrl-C.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = terminate.recv() => log::info!("Received SIGTERM"),
+                _ = tokio::signal::ctrl_c() => log::info!("Received Ctrl-C"),
+            },
+            Err(err) => {
+                log::warn!("Cannot listen for SIGTERM ({}); stopping on Ctrl-C only", err);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Runs `server` until `shutdown` resolves, then shuts down in order: readiness fails for the
+/// configured grace period, the server drains, `jobs` are cancelled and `pool` is closed.
+pub async fn serve(
+    server: Server,
+    state: &AppState,
+    jobs: BackgroundJobs,
+    pool: Option<DbPool>,
+    shutdown: impl Future<Output = ()>,
+) -> std::io::Result<ShutdownReport> {
+    let handle = server.handle();
+    let mut running = actix_web::rt::spawn(server);
+    let lifecycle = &state.lifecycle;
+
+    let stopped = tokio::select! {
+        stopped = &mut running => {
+            log::warn!("Server stopped unexpectedly");
+            // Nothing is left to drain, and the finished task must not be awaited again
+            stopped.map_err(std::io::Error::other).and_then(|result| result)
+        }
+        _ = shutdown => stop_serving(state, &handle, &mut running).await,
+    };
+
+    let report = ShutdownReport {
+        aborted_requests: lifecycle.aborted(),
+        cancelled_jobs: jobs.cancel().await,
+    };
+    if report.aborted_requests > 0 {
+        log::warn!("Aborted {} requests still running after the drain timeout", report.aborted_requests);
+    }
+    if let Some(pool) = pool {
+        pool.close().await;
+        log::info!("Database pool closed");
+    }
+    stopped?;
+    log::info!("Shutdown complete");
+    Ok(report)
+}
+
+/// Fails readiness for the grace period, then drains and stops the server.
+async fn stop_serving(
+    state: &AppState,
+    handle: &ServerHandle,
+    running: &mut JoinHandle<std::io::Result<()>>,
+) -> std::io::Result<()> {
+    let lifecycle = &state.lifecycle;
+    lifecycle.begin_shutdown();
+    log::info!("Shutting down; failing readiness for {}s first", lifecycle.readiness_grace().as_secs());
+    tokio::time::sleep(lifecycle.readiness_grace()).await;
+
+    log::info!(
+        "Draining {} in-flight requests for up to {}s",
+        lifecycle.in_flight(),
+        lifecycle.drain_timeout().as_secs()
+    );
+    // Feed subscribers would otherwise hold the drain open; they resume elsewhere
+    if let Some(services) = &state.postgres {
+        services.user_event_stream.close();
+    }
+    // Draining is done here rather than by a graceful `stop`: actix workers can exit as soon as
+    // the accept loop closes, cutting connections, and idle keep-alive connections would hold a
+    // graceful stop open until they time out
+    handle.pause().await;
+    if !lifecycle.drained(lifecycle.drain_timeout()).await {
+        log::warn!("Drain timeout reached with {} requests in flight", lifecycle.in_flight());
+    }
+    handle.stop(false).await;
+    running.await.map_err(std::io::Error::other)??;
+    // Workers drop the requests they cut off on their own threads, possibly after the server
+    // has returned; wait for that so the report counts them
+    lifecycle.drained(ABORT_SETTLE_TIMEOUT).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lifecycle() -> Arc<Lifecycle> {
+        Arc::new(Lifecycle::new(ShutdownConfig::default()))
+    }
+
+    #[test]
+    fn test_only_unfinished_requests_during_shutdown_count_as_aborted() {
+        let lifecycle = lifecycle();
+
+        let guard = lifecycle.clone().track("GET /api/v1/users".to_string());
+        assert_eq!(lifecycle.in_flight(), 1);
+        // A client going away during normal operation is not an abort
+        drop(guard);
+        assert_eq!((lifecycle.in_flight(), lifecycle.aborted()), (0, 0));
+
+        let mut finished = lifecycle.clone().track("GET /api/v1/users".to_string());
+        let unfinished = lifecycle.clone().track("GET /api/v1/users/stream".to_string());
+        lifecycle.begin_shutdown();
+        assert!(!lifecycle.is_ready());
+        finished.finished = true;
+        drop(finished);
+        drop(unfinished);
+        assert_eq!((lifecycle.in_flight(), lifecycle.aborted()), (0, 1));
+    }
+
+    #[actix_web::test]
+    async fn test_tracked_body_finishes_when_sent() {
+        let lifecycle = lifecycle();
+        let body = TrackedBody {
+            body: BoxBody::new("done"),
+            guard: Some(lifecycle.clone().track("GET /".to_string())),
+        };
+        lifecycle.begin_shutdown();
+
+        let bytes = actix_web::body::to_bytes(body).await.unwrap();
+        assert_eq!(bytes, "done");
+        assert_eq!((lifecycle.in_flight(), lifecycle.aborted()), (0, 0));
+    }
+
+    #[actix_web::test]
+    async fn test_cancel_reports_running_jobs() {
+        let mut jobs = BackgroundJobs::new();
+        jobs.spawn("finished", async {});
+        jobs.spawn("forever", std::future::pending());
+        tokio::task::yield_now().await;
+
+        assert_eq!(jobs.cancel().await, ["forever"]);
+    }
+}
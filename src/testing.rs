@@ -0,0 +1,411 @@
+//! Database fixtures for tests
+//!
+//! [`db_test!`](crate::db_test) declares a test that gets either a connection inside a
+//! transaction rolled back after the test, or a pool on a database of its own copied from a
+//! migrated template. Both live next to the database named in `Config.toml`, with `_test` and
+//! `_test_template` appended to its name, and are created and migrated once per test binary.
+//!
+//! Fixtures are files under `fixtures/`, loaded in order before the test body runs. A `.sql`
+//! fixture is executed as is. A `.yaml` fixture maps table names to lists of rows:
+//!
+//! ```yaml
+//! users:
+//!   - email: ada@example.com
+//!     name: Ada Lovelace
+//!     created_at: 2024-01-15T09:30:00Z
+//! ```
+//!
+//! Each row is inserted with only the columns it names, so the others take their defaults.
+//! Values are converted by Postgres from their JSON form, which means timestamps and UUIDs are
+//! written as strings and nested mappings fill `JSONB` columns.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context};
+use serde_json::{Map, Number, Value};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Executor, PgConnection, PgPool, Postgres, Transaction};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+use yaml_rust2::{Yaml, YamlLoader};
+
+use crate::config::Settings;
+use crate::db::{self, AdvisoryLock};
+
+/// Serializes creating, migrating and copying the test databases across test binaries.
+const DATABASES_LOCK: &str = "test-databases";
+
+/// A fixture's file name, relative to `fixtures/`, and its contents.
+pub type Fixture = (&'static str, &'static str);
+
+/// Declares an async test against the test database. The test passes without running when
+/// there is no `Config.toml` or its server is unreachable.
+///
+/// With `conn: &mut PgConnection` the body runs on one connection inside a transaction that is
+/// rolled back afterwards; DAO writes on it use savepoints, so they behave as if committed.
+/// With `pool: PgPool` the body gets a fresh database instead, for code that commits, listens
+/// for notifications or needs several connections. Fixtures are read at compile time:
+///
+/// ```ignore
+/// db_test!(async fn finds_users_by_email(conn: &mut PgConnection) fixtures("users.yaml") {
+///     let user = UserQueries::new(conn).get_user_by_email("ada@example.com").await.unwrap();
+///     assert!(user.is_some());
+/// });
+/// ```
+#[macro_export]
+macro_rules! db_test {
+    (
+        $(#[$attr:meta])*
+        async fn $name:ident($conn:ident: &mut PgConnection) $(fixtures($($fixture:literal),+ $(,)?))?
+        $body:block
+    ) => {
+        $(#[$attr])*
+        #[::tokio::test]
+        async fn $name() {
+            let fixtures: &[$crate::testing::Fixture] = $crate::db_test!(@fixtures [$($($fixture),+)?]);
+            let Some(mut tx) = $crate::testing::begin_test_transaction(fixtures).await else {
+                return;
+            };
+            let $conn: &mut ::sqlx::PgConnection = &mut tx;
+            $body
+            tx.rollback().await.expect("Failed to roll back test transaction");
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        async fn $name:ident($pool:ident: PgPool) $(fixtures($($fixture:literal),+ $(,)?))?
+        $body:block
+    ) => {
+        $(#[$attr])*
+        #[::tokio::test]
+        async fn $name() {
+            let fixtures: &[$crate::testing::Fixture] = $crate::db_test!(@fixtures [$($($fixture),+)?]);
+            let Some(database) = $crate::testing::TestDatabase::create(fixtures).await else {
+                return;
+            };
+            let $pool: ::sqlx::PgPool = database.pool.clone();
+            $body
+        }
+    };
+    (@fixtures [$($fixture:literal),*]) => {
+        &[$(($fixture, include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/", $fixture)))),*]
+    };
+}
+
+/// Connection options for a prepared test database, and for the main one used to manage it.
+#[derive(Clone)]
+struct PreparedDatabase {
+    admin: PgConnectOptions,
+    options: PgConnectOptions,
+    name: String,
+}
+
+static TEST_DATABASE: OnceCell<Option<PreparedDatabase>> = OnceCell::const_new();
+static TEMPLATE_DATABASE: OnceCell<Option<PreparedDatabase>> = OnceCell::const_new();
+
+/// Creates the database named in `Config.toml` plus `suffix` unless it exists, and migrates
+/// it. Returns `None`, after saying why, when there is no configuration or server.
+async fn prepare_database(suffix: &str) -> Option<PreparedDatabase> {
+    let settings = match Settings::new() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("Skipping database test, no configuration: {}", err);
+            return None;
+        }
+    };
+    let admin_options = PgConnectOptions::from_str(&settings.database_url()).expect("Invalid database URL");
+    let admin = match PgPoolOptions::new().max_connections(1).connect_with(admin_options.clone()).await {
+        Ok(pool) => pool,
+        Err(err) => {
+            eprintln!("Skipping database test, database unavailable: {}", err);
+            return None;
+        }
+    };
+
+    let name = format!("{}{}", settings.database.database_name, suffix);
+    let lock = AdvisoryLock::acquire(&admin, DATABASES_LOCK).await.expect("Failed to lock test databases");
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1)")
+        .bind(&name)
+        .fetch_one(&admin)
+        .await
+        .expect("Failed to look up test database");
+    if !exists {
+        sqlx::query(&format!("CREATE DATABASE {}", quote_identifier(&name).unwrap()))
+            .execute(&admin)
+            .await
+            .expect("Failed to create test database");
+    }
+
+    let options = admin_options.clone().database(&name);
+    let pool = PgPoolOptions::new().max_connections(1).connect_with(options.clone()).await.unwrap();
+    db::run_migrations(&pool).await.expect("Failed to migrate test database");
+    // A template cannot be copied while anyone is connected to it
+    pool.close().await;
+    lock.release().await;
+    admin.close().await;
+
+    Some(PreparedDatabase { admin: admin_options, options, name })
+}
+
+/// Begins a transaction on the shared test database and loads `fixtures` inside it. Returns
+/// `None` when no database is available.
+pub async fn begin_test_transaction(fixtures: &[Fixture]) -> Option<Transaction<'static, Postgres>> {
+    let database = TEST_DATABASE.get_or_init(|| prepare_database("_test")).await.clone()?;
+
+    // Each test has a runtime of its own, which its connections cannot outlive, so pools are
+    // not shared between tests
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(database.options)
+        .await
+        .expect("Failed to connect to test database");
+    let mut tx = pool.begin().await.expect("Failed to begin test transaction");
+    load_fixtures(&mut tx, fixtures).await.unwrap_or_else(|err| panic!("{:#}", err));
+    Some(tx)
+}
+
+/// A database copied from the migrated template for one test, dropped with this value.
+pub struct TestDatabase {
+    pub pool: PgPool,
+    name: String,
+    admin: PgConnectOptions,
+}
+
+impl TestDatabase {
+    /// Creates the database and loads `fixtures` into it, committed. Returns `None` when no
+    /// database is available.
+    pub async fn create(fixtures: &[Fixture]) -> Option<TestDatabase> {
+        let template = TEMPLATE_DATABASE.get_or_init(|| prepare_database("_test_template")).await.clone()?;
+
+        let name = format!("test_{}", Uuid::new_v4().simple());
+        let admin = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_with(template.admin.clone())
+            .await
+            .expect("Failed to connect to database");
+        let lock = AdvisoryLock::acquire(&admin, DATABASES_LOCK).await.expect("Failed to lock test databases");
+        sqlx::query(&format!(
+            "CREATE DATABASE {} TEMPLATE {}",
+            quote_identifier(&name).unwrap(),
+            quote_identifier(&template.name).unwrap()
+        ))
+        .execute(&admin)
+        .await
+        .expect("Failed to create test database");
+        lock.release().await;
+        admin.close().await;
+
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(template.options.clone().database(&name))
+            .await
+            .expect("Failed to connect to test database");
+        let database = TestDatabase { pool, name, admin: template.admin };
+
+        let mut tx = database.pool.begin().await.expect("Failed to begin fixture transaction");
+        load_fixtures(&mut tx, fixtures).await.unwrap_or_else(|err| panic!("{:#}", err));
+        tx.commit().await.expect("Failed to commit fixtures");
+
+        Some(database)
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        // Drop runs outside any async context, so the cleanup gets a runtime of its own.
+        // FORCE disconnects whatever the test left open in the pool.
+        let (admin, name) = (self.admin.clone(), self.name.clone());
+        let cleanup = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                let admin = PgPoolOptions::new().max_connections(1).connect_with(admin).await?;
+                sqlx::query(&format!("DROP DATABASE {} WITH (FORCE)", quote_identifier(&name).unwrap()))
+                    .execute(&admin)
+                    .await?;
+                admin.close().await;
+                Ok::<_, sqlx::Error>(())
+            })
+        });
+        if let Ok(Err(err)) = cleanup.join() {
+            eprintln!("Failed to drop test database {}: {}", self.name, err);
+        }
+    }
+}
+
+/// Loads fixtures on `conn` in order, telling `.sql` from `.yaml` files by extension.
+pub async fn load_fixtures(conn: &mut PgConnection, fixtures: &[Fixture]) -> anyhow::Result<()> {
+    for (name, contents) in fixtures {
+        load_fixture(conn, name, contents)
+            .await
+            .with_context(|| format!("Failed to load fixture {}", name))?;
+    }
+    Ok(())
+}
+
+async fn load_fixture(conn: &mut PgConnection, name: &str, contents: &str) -> anyhow::Result<()> {
+    if name.ends_with(".sql") {
+        // A plain string runs as a simple query, which may hold several statements
+        conn.execute(contents).await?;
+    } else if name.ends_with(".yaml") || name.ends_with(".yml") {
+        for (table, rows) in parse_yaml_fixture(contents)? {
+            for row in rows {
+                insert_row(conn, &table, row).await?;
+            }
+        }
+    } else {
+        bail!("unknown fixture type, expected a .sql or .yaml file");
+    }
+    Ok(())
+}
+
+/// A table name and the rows to insert into it, each mapping column names to values.
+pub type FixtureTable = (String, Vec<Map<String, Value>>);
+
+/// Rows of a YAML fixture, grouped by table in file order.
+pub fn parse_yaml_fixture(contents: &str) -> anyhow::Result<Vec<FixtureTable>> {
+    let mut tables = Vec::new();
+    for document in YamlLoader::load_from_str(contents)? {
+        let hash = match document {
+            Yaml::Hash(hash) => hash,
+            Yaml::Null => continue,
+            _ => bail!("expected a mapping of table names to rows"),
+        };
+        for (table, rows) in &hash {
+            let table = yaml_key(table)?;
+            let Yaml::Array(rows) = rows else {
+                bail!("expected a list of rows for table {}", table);
+            };
+            let rows = rows
+                .iter()
+                .map(|row| match yaml_to_json(row)? {
+                    Value::Object(row) => Ok(row),
+                    _ => bail!("expected each row of table {} to be a mapping", table),
+                })
+                .collect::<anyhow::Result<_>>()?;
+            tables.push((table, rows));
+        }
+    }
+    Ok(tables)
+}
+
+async fn insert_row(conn: &mut PgConnection, table: &str, row: Map<String, Value>) -> anyhow::Result<()> {
+    let table = quote_identifier(table)?;
+    let sql = if row.is_empty() {
+        format!("INSERT INTO {} DEFAULT VALUES", table)
+    } else {
+        let columns = row
+            .keys()
+            .map(|column| quote_identifier(column))
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .join(", ");
+        // Postgres converts each value to its column's type, as it would a JSON document
+        format!(
+            "INSERT INTO {table} ({columns}) SELECT {columns} FROM jsonb_populate_record(NULL::{table}, $1)",
+            table = table,
+            columns = columns
+        )
+    };
+
+    sqlx::query(&sql).bind(Value::Object(row)).execute(conn).await?;
+    Ok(())
+}
+
+fn yaml_key(yaml: &Yaml) -> anyhow::Result<String> {
+    match yaml {
+        Yaml::String(key) => Ok(key.clone()),
+        Yaml::Integer(key) => Ok(key.to_string()),
+        _ => bail!("expected a string key, found {:?}", yaml),
+    }
+}
+
+fn yaml_to_json(yaml: &Yaml) -> anyhow::Result<Value> {
+    Ok(match yaml {
+        Yaml::Null => Value::Null,
+        Yaml::Boolean(value) => Value::Bool(*value),
+        Yaml::Integer(value) => Value::from(*value),
+        Yaml::Real(text) => yaml
+            .as_f64()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+            .ok_or_else(|| anyhow!("unsupported number {}", text))?,
+        Yaml::String(value) => Value::String(value.clone()),
+        Yaml::Array(items) => Value::Array(items.iter().map(yaml_to_json).collect::<anyhow::Result<_>>()?),
+        Yaml::Hash(hash) => Value::Object(
+            hash.iter()
+                .map(|(key, value)| Ok((yaml_key(key)?, yaml_to_json(value)?)))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        Yaml::Alias(_) | Yaml::BadValue => bail!("YAML aliases are not supported"),
+    })
+}
+
+/// Quotes a table, column or database name, refusing anything but plain identifiers.
+fn quote_identifier(name: &str) -> anyhow::Result<String> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        bail!("invalid identifier {:?}", name);
+    }
+    Ok(format!("\"{}\"", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_yaml_fixture_groups_rows_by_table_in_order() {
+        let tables = parse_yaml_fixture(
+            r#"
+users:
+  - email: ada@example.com
+    name: Ada
+    version: 3
+  - email: alan@example.com
+    suspended_at: null
+webhooks:
+  - url: https://example.com/hook
+    events: [user.created]
+    active: true
+    retry: {backoff: 1.5}
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0].0, "users");
+        assert_eq!(
+            tables[0].1,
+            vec![
+                json!({"email": "ada@example.com", "name": "Ada", "version": 3}).as_object().unwrap().clone(),
+                json!({"email": "alan@example.com", "suspended_at": null}).as_object().unwrap().clone(),
+            ]
+        );
+        assert_eq!(tables[1].0, "webhooks");
+        assert_eq!(
+            Value::Object(tables[1].1[0].clone()),
+            json!({"url": "https://example.com/hook", "events": ["user.created"], "active": true, "retry": {"backoff": 1.5}})
+        );
+    }
+
+    #[test]
+    fn test_parse_yaml_fixture_rejects_malformed_files() {
+        assert!(parse_yaml_fixture("").unwrap().is_empty());
+        assert!(parse_yaml_fixture("- users").is_err());
+        assert!(parse_yaml_fixture("users: {email: ada@example.com}").is_err());
+        assert!(parse_yaml_fixture("users: [ada@example.com]").is_err());
+        assert!(parse_yaml_fixture("users: [{email: *missing}]").is_err());
+    }
+
+    #[test]
+    fn test_quote_identifier() {
+        assert_eq!(quote_identifier("users").unwrap(), "\"users\"");
+        assert_eq!(quote_identifier("_test_2").unwrap(), "\"_test_2\"");
+        assert!(quote_identifier("").is_err());
+        assert!(quote_identifier("2users").is_err());
+        assert!(quote_identifier("users\"; DROP TABLE users; --").is_err());
+        assert!(quote_identifier("public.users").is_err());
+    }
+}
@@ -0,0 +1,397 @@
+//! HTTPS for `serve`. The certificate and key are reloaded when their files change, so renewals
+//! need no restart; actix-web offers HTTP/2 and HTTP/1.1 through ALPN. Internal callers can
+//! authenticate with client certificates issued by a configured CA, and handlers read who they
+//! are with [`ClientIdentity::of`].
+
+use std::any::Any;
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use actix_tls::accept::rustls_0_21::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use actix_web::HttpRequest;
+use anyhow::{anyhow, bail, Context};
+use ring::signature;
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig, SignatureScheme};
+use sha2::{Digest, Sha256};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use crate::config::TlsConfig;
+
+/// Builds the rustls configuration for `config`, serving whatever certificate `certs` holds.
+pub fn server_config(config: &TlsConfig, certs: Arc<CertReloader>) -> anyhow::Result<ServerConfig> {
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let roots = load_roots(path)?;
+            if config.require_client_cert {
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            } else {
+                builder.with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed())
+            }
+        }
+        None => builder.with_no_client_auth(),
+    };
+    Ok(builder.with_cert_resolver(certs))
+}
+
+/// The server certificate, swapped for a new one when the files on disk change. A change that
+/// does not load, such as a certificate written before its key, leaves the current one in use.
+pub struct CertReloader {
+    config: TlsConfig,
+    current: RwLock<LoadedCert>,
+}
+
+struct LoadedCert {
+    /// The certificate and key files as read, to tell when either changes
+    files: (Vec<u8>, Vec<u8>),
+    key: Arc<CertifiedKey>,
+}
+
+impl CertReloader {
+    pub fn load(config: &TlsConfig) -> anyhow::Result<Arc<Self>> {
+        let files = read_pair(config)?;
+        let key = certified_key(&files.0, &files.1)?;
+        log_loaded(config, &key);
+        Ok(Arc::new(Self {
+            config: config.clone(),
+            current: RwLock::new(LoadedCert { files, key }),
+        }))
+    }
+
+    /// Loads the files again if either changed since the last load. True if the certificate
+    /// was replaced.
+    pub fn reload_if_changed(&self) -> anyhow::Result<bool> {
+        let files = read_pair(&self.config)?;
+        if files == self.current.read().unwrap().files {
+            return Ok(false);
+        }
+        let key = certified_key(&files.0, &files.1)?;
+        log_loaded(&self.config, &key);
+        *self.current.write().unwrap() = LoadedCert { files, key };
+        Ok(true)
+    }
+
+    /// Checks the files every `reload_interval_secs`. Polling rather than file notifications
+    /// also catches the symlink swaps Kubernetes uses to update mounted secrets.
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.reload_interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(err) = self.reload_if_changed() {
+                log::warn!("Keeping the current TLS certificate: {:#}", err);
+            }
+        }
+    }
+
+    fn certified_key(&self) -> Arc<CertifiedKey> {
+        self.current.read().unwrap().key.clone()
+    }
+}
+
+impl ResolvesServerCert for CertReloader {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key())
+    }
+}
+
+fn read_pair(config: &TlsConfig) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    let read = |path: &Path| fs::read(path).with_context(|| format!("Failed to read {}", path.display()));
+    Ok((read(&config.cert_path)?, read(&config.key_path)?))
+}
+
+fn log_loaded(config: &TlsConfig, key: &CertifiedKey) {
+    if let Ok((_, cert)) = x509_parser::parse_x509_certificate(&key.cert[0].0) {
+        log::info!(
+            "Loaded TLS certificate {} for {}, valid until {}",
+            config.cert_path.display(),
+            cert.subject(),
+            cert.validity().not_after
+        );
+    }
+}
+
+fn certified_key(cert_pem: &[u8], key_pem: &[u8]) -> anyhow::Result<Arc<CertifiedKey>> {
+    let chain: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(cert_pem))
+        .context("Invalid certificate PEM")?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let Some(leaf) = chain.first() else {
+        bail!("No certificate found in the certificate file");
+    };
+
+    let mut reader = BufReader::new(key_pem);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader).context("Invalid private key PEM")? {
+            Some(rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::RSAKey(key) | rustls_pemfile::Item::ECKey(key)) => {
+                break PrivateKey(key)
+            }
+            Some(_) => continue,
+            None => bail!("No private key found in the key file"),
+        }
+    };
+    let signing_key = rustls::sign::any_supported_type(&key).map_err(|_| anyhow!("Unsupported private key type"))?;
+
+    let (_, cert) = x509_parser::parse_x509_certificate(&leaf.0).map_err(|err| anyhow!("Invalid certificate: {}", err))?;
+    if !key_matches(signing_key.as_ref(), &cert) {
+        bail!("The private key does not match the certificate");
+    }
+    Ok(Arc::new(CertifiedKey::new(chain, signing_key)))
+}
+
+/// Whether `key` belongs to `cert`, by checking a signature made with the key against the
+/// certificate's public key.
+fn key_matches(key: &dyn rustls::sign::SigningKey, cert: &X509Certificate) -> bool {
+    const SCHEMES: [SignatureScheme; 4] = [
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        SignatureScheme::ED25519,
+        SignatureScheme::RSA_PSS_SHA256,
+    ];
+    let Some(signer) = key.choose_scheme(&SCHEMES) else {
+        return false;
+    };
+    let algorithm: &dyn signature::VerificationAlgorithm = match signer.scheme() {
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &signature::ECDSA_P256_SHA256_ASN1,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &signature::ECDSA_P384_SHA384_ASN1,
+        SignatureScheme::ED25519 => &signature::ED25519,
+        SignatureScheme::RSA_PSS_SHA256 => &signature::RSA_PSS_2048_8192_SHA256,
+        _ => return false,
+    };
+
+    let message = b"tangy-mango key check";
+    let Ok(signed) = signer.sign(message) else {
+        return false;
+    };
+    signature::UnparsedPublicKey::new(algorithm, &cert.public_key().subject_public_key.data)
+        .verify(message, &signed)
+        .is_ok()
+}
+
+fn load_roots(path: &Path) -> anyhow::Result<RootCertStore> {
+    let pem = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(&pem[..]))
+        .with_context(|| format!("Invalid CA bundle {}", path.display()))?;
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        roots
+            .add(&Certificate(cert))
+            .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+    }
+    if roots.is_empty() {
+        bail!("No CA certificates found in {}", path.display());
+    }
+    Ok(roots)
+}
+
+/// Who is calling, from the verified client certificate of the connection a request came in on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// The certificate's subject, such as `O=tangy-mango, CN=billing-service`
+    pub subject: String,
+    pub common_name: Option<String>,
+    /// DNS names from the subject alternative name extension
+    pub dns_names: Vec<String>,
+    /// URIs from the subject alternative name extension, such as SPIFFE IDs
+    pub uris: Vec<String>,
+    /// SHA-256 of the DER certificate, lowercase hex, for pinning a particular certificate
+    pub fingerprint: String,
+}
+
+impl ClientIdentity {
+    /// The identity of `request`'s client, if it presented a certificate. rustls has verified
+    /// it against `client_ca_path` by the time any request arrives.
+    pub fn of(request: &HttpRequest) -> Option<&ClientIdentity> {
+        request.conn_data::<ClientIdentity>()
+    }
+
+    fn from_der(der: &[u8]) -> anyhow::Result<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).map_err(|err| anyhow!("Invalid certificate: {}", err))?;
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|name| name.as_str().ok())
+            .map(str::to_string);
+
+        let (mut dns_names, mut uris) = (Vec::new(), Vec::new());
+        if let Ok(Some(names)) = cert.subject_alternative_name() {
+            for name in &names.value.general_names {
+                match name {
+                    GeneralName::DNSName(dns) => dns_names.push(dns.to_string()),
+                    GeneralName::URI(uri) => uris.push(uri.to_string()),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Self {
+            subject: cert.subject().to_string(),
+            common_name,
+            dns_names,
+            uris,
+            fingerprint: hex::encode(Sha256::digest(der)),
+        })
+    }
+}
+
+/// Stores the [`ClientIdentity`] of each TLS connection that presented a certificate, for
+/// `HttpServer::on_connect`.
+pub(crate) fn capture_client_identity(connection: &dyn Any, data: &mut Extensions) {
+    let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+    let (_, session) = stream.get_ref();
+    let Some(leaf) = session.peer_certificates().and_then(|certs| certs.first()) else {
+        return;
+    };
+    match ClientIdentity::from_der(&leaf.0) {
+        Ok(identity) => {
+            data.insert(identity);
+        }
+        Err(err) => log::warn!("Cannot read the client certificate: {:#}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use actix_web::{web, App, HttpServer};
+    use uuid::Uuid;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/tls").join(name)
+    }
+
+    fn fixture_config() -> TlsConfig {
+        TlsConfig {
+            cert_path: fixture("server.pem"),
+            key_path: fixture("server.key"),
+            ..TlsConfig::default()
+        }
+    }
+
+    fn config(dir: &Path) -> TlsConfig {
+        TlsConfig {
+            cert_path: dir.join("tls.crt"),
+            key_path: dir.join("tls.key"),
+            ..TlsConfig::default()
+        }
+    }
+
+    /// Copies a fixture certificate and key to where `config` expects them.
+    fn install(config: &TlsConfig, cert: &str, key: &str) {
+        fs::copy(fixture(cert), &config.cert_path).unwrap();
+        fs::copy(fixture(key), &config.key_path).unwrap();
+    }
+
+    fn serial(reloader: &CertReloader) -> String {
+        let key = reloader.certified_key();
+        let (_, cert) = x509_parser::parse_x509_certificate(&key.cert[0].0).unwrap();
+        cert.raw_serial_as_string()
+    }
+
+    #[test]
+    fn test_reload_replaces_the_certificate_only_when_valid() {
+        let dir = std::env::temp_dir().join(format!("tangy-mango-tls-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let config = config(&dir);
+        install(&config, "server.pem", "server.key");
+
+        let reloader = CertReloader::load(&config).unwrap();
+        let original = serial(&reloader);
+        assert!(!reloader.reload_if_changed().unwrap());
+
+        // A renewed certificate written before its key does not load
+        fs::copy(fixture("server-renewed.pem"), &config.cert_path).unwrap();
+        let err = reloader.reload_if_changed().unwrap_err();
+        assert_eq!(err.to_string(), "The private key does not match the certificate");
+        assert_eq!(serial(&reloader), original);
+
+        fs::copy(fixture("server-renewed.key"), &config.key_path).unwrap();
+        assert!(reloader.reload_if_changed().unwrap());
+        assert_ne!(serial(&reloader), original);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_client_identity_from_certificate() {
+        let pem = fs::read(fixture("client.pem")).unwrap();
+        let der = rustls_pemfile::certs(&mut BufReader::new(&pem[..])).unwrap().remove(0);
+
+        let identity = ClientIdentity::from_der(&der).unwrap();
+        assert_eq!(identity.subject, "O=tangy-mango, CN=billing-service");
+        assert_eq!(identity.common_name.as_deref(), Some("billing-service"));
+        assert_eq!(identity.dns_names, ["billing.internal"]);
+        assert!(identity.uris.is_empty());
+        assert_eq!(identity.fingerprint, hex::encode(Sha256::digest(&der)));
+    }
+
+    #[test]
+    fn test_client_ca_bundle_must_hold_certificates() {
+        let mut config = TlsConfig { client_ca_path: Some(fixture("client.key")), ..fixture_config() };
+        let certs = CertReloader::load(&config).unwrap();
+        let err = server_config(&config, certs.clone()).unwrap_err();
+        assert!(err.to_string().starts_with("No CA certificates found in"));
+
+        config.client_ca_path = Some(fixture("ca.pem"));
+        config.require_client_cert = true;
+        assert!(server_config(&config, certs).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_handlers_see_the_client_identity() {
+        let config = TlsConfig { client_ca_path: Some(fixture("ca.pem")), ..fixture_config() };
+        let tls = server_config(&config, CertReloader::load(&config).unwrap()).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("https://localhost:{}/", listener.local_addr().unwrap().port());
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::get().to(|request: HttpRequest| async move {
+                    match ClientIdentity::of(&request) {
+                        Some(identity) => identity.common_name.clone().unwrap_or_default(),
+                        None => "anonymous".to_string(),
+                    }
+                }),
+            )
+        })
+        .on_connect(capture_client_identity)
+        .workers(1)
+        .listen_rustls_0_21(listener, tls)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let client = |identity: bool| {
+            let ca = reqwest::Certificate::from_pem(&fs::read(fixture("ca.pem")).unwrap()).unwrap();
+            let mut builder = reqwest::Client::builder().use_rustls_tls().add_root_certificate(ca);
+            if identity {
+                let pem = [fs::read(fixture("client.pem")).unwrap(), fs::read(fixture("client.key")).unwrap()].concat();
+                builder = builder.identity(reqwest::Identity::from_pem(&pem).unwrap());
+            }
+            builder.build().unwrap()
+        };
+        let caller = |identity: bool| {
+            let request = client(identity).get(&url);
+            async move { request.send().await.unwrap().text().await.unwrap() }
+        };
+
+        assert_eq!(caller(true).await, "billing-service");
+        // Without `require_client_cert`, callers without a certificate are served anonymously
+        assert_eq!(caller(false).await, "anonymous");
+        handle.stop(false).await;
+    }
+}
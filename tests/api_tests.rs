@@ -0,0 +1,778 @@
+//! HTTP-level tests of the user endpoints
+//!
+//! Every test starts the real application on its own Postgres schema (see `common`) and
+//! talks to it over HTTP. Without a reachable database the tests are skipped, except those
+//! of mock mode, which need none.
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use common::{assert_json_include, send, MockApp, TestApp};
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+use tangy_mango::app::{self, AppState};
+use tangy_mango::chaos::{Faults, Latency};
+use tangy_mango::config::{Settings, ShutdownConfig, TlsConfig};
+use tangy_mango::shutdown::{self, BackgroundJobs, ShutdownReport};
+use tangy_mango::loadgen::{self, LoadOptions, Scenario};
+use tangy_mango::rate_limit::{RateLimitKey, RateLimitRule, RateLimitStoreKind};
+use tangy_mango::services::memory_user_store::MemoryUserStore;
+
+macro_rules! spawn_app {
+    () => {
+        match TestApp::spawn().await {
+            Some(app) => app,
+            None => return,
+        }
+    };
+    (with $configure:expr) => {
+        match TestApp::spawn_with($configure).await {
+            Some(app) => app,
+            None => return,
+        }
+    };
+    (mock $store:expr, $faults:expr) => {
+        match MockApp::spawn($store, $faults) {
+            Some(app) => app,
+            None => return,
+        }
+    };
+}
+
+#[tokio::test]
+async fn test_create_and_get_user() {
+    let app = spawn_app!();
+
+    let created = app.create_user(" Ada@Example.COM ", "Ada Lovelace").await;
+    assert_json_include(&created, &json!({"email": "Ada@example.com", "name": "Ada Lovelace", "version": 1}));
+    let id = created["id"].as_str().unwrap();
+
+    let response = send(app.get(&format!("/api/v1/users/{}", id))).await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.header("ETag"), Some("\"1\""));
+    assert!(response.header("Last-Modified").is_some());
+    assert_eq!(response.json(), created);
+
+    let response = send(app.get(&format!("/api/v1/users/{}", id)).header("If-None-Match", "\"1\"")).await;
+    response.assert_status(StatusCode::NOT_MODIFIED);
+
+    let response = send(app.get(&format!("/api/v1/users/{}", uuid::Uuid::new_v4()))).await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    assert_json_include(&response.json(), &json!({"error": "User not found"}));
+}
+
+#[tokio::test]
+async fn test_create_user_rejects_invalid_users() {
+    let app = spawn_app!();
+
+    for body in [
+        json!({"email": "", "name": "Nobody"}),
+        json!({"email": "not-an-email", "name": "Nobody"}),
+    ] {
+        let response = send(app.post("/api/v1/users").json(&body)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_create_user_conflicts_on_duplicate_email() {
+    let app = spawn_app!();
+    app.create_user("ada@example.com", "Ada").await;
+
+    let response = send(app.post("/api/v1/users").json(&json!({"email": "ADA@example.com", "name": "Duplicate"}))).await;
+    response.assert_status(StatusCode::CONFLICT);
+    assert_eq!(response.json(), json!({"error": "Email is already in use"}));
+}
+
+#[tokio::test]
+async fn test_create_user_is_idempotent_with_a_key() {
+    let app = spawn_app!();
+    let body = json!({"email": "ada@example.com", "name": "Ada"});
+
+    let first = send(app.post("/api/v1/users").header("Idempotency-Key", "k1").json(&body)).await;
+    first.assert_status(StatusCode::CREATED);
+    assert_eq!(first.header("Idempotent-Replayed"), None);
+
+    let replay = send(app.post("/api/v1/users").header("Idempotency-Key", "k1").json(&body)).await;
+    replay.assert_status(StatusCode::CREATED);
+    assert_eq!(replay.header("Idempotent-Replayed"), Some("true"));
+    assert_eq!(replay.json(), first.json());
+
+    let mismatch = send(
+        app.post("/api/v1/users")
+            .header("Idempotency-Key", "k1")
+            .json(&json!({"email": "grace@example.com", "name": "Grace"})),
+    )
+    .await;
+    mismatch.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_list_lookup_and_batch_get() {
+    let app = spawn_app!();
+    let ada = app.create_user("ada@example.com", "Ada").await;
+    let grace = app.create_user("grace@example.com", "Grace").await;
+
+    let response = send(app.get("/api/v1/users")).await;
+    response.assert_status(StatusCode::OK);
+    // Newest first
+    assert_json_include(&response.json(), &json!([{"name": "Grace"}, {"name": "Ada"}]));
+
+    let response = send(app.get("/api/v1/users/lookup?email=ADA@EXAMPLE.com")).await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.json()["id"], ada["id"]);
+    send(app.get("/api/v1/users/lookup?email=nobody@example.com"))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let missing = uuid::Uuid::new_v4();
+    let response = send(
+        app.post("/api/v1/users:batchGet")
+            .json(&json!({"ids": [grace["id"], missing, ada["id"], grace["id"]]})),
+    )
+    .await;
+    response.assert_status(StatusCode::OK);
+    assert_json_include(
+        &response.json(),
+        &json!({"users": [{"id": grace["id"]}, {"id": ada["id"]}], "missing": [missing]}),
+    );
+
+    send(app.post("/api/v1/users:batchGet").json(&json!({"ids": []})))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_update_user_requires_a_current_etag() {
+    let app = spawn_app!();
+    let user = app.create_user("ada@example.com", "Ada").await;
+    let path = format!("/api/v1/users/{}", user["id"].as_str().unwrap());
+    let body = json!({"email": "ADA@example.com", "name": "Ada King"});
+
+    send(app.put(&path).json(&body)).await.assert_status(StatusCode::PRECONDITION_REQUIRED);
+
+    let response = send(app.put(&path).header("If-Match", "\"1\"").json(&body)).await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.header("ETag"), Some("\"2\""));
+    assert_json_include(&response.json(), &json!({"email": "ADA@example.com", "name": "Ada King", "version": 2}));
+
+    send(app.put(&path).header("If-Match", "\"1\"").json(&body))
+        .await
+        .assert_status(StatusCode::PRECONDITION_FAILED);
+
+    // A different address has to go through the email change flow
+    send(app.put(&path).header("If-Match", "*").json(&json!({"email": "new@example.com", "name": "Ada"})))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_patch_user() {
+    let app = spawn_app!();
+    let user = app.create_user("ada@example.com", "Ada").await;
+    let path = format!("/api/v1/users/{}", user["id"].as_str().unwrap());
+
+    let response = send(
+        app.patch(&path)
+            .header("Content-Type", "application/merge-patch+json")
+            .header("If-Match", "\"1\"")
+            .body(r#"{"name": "Countess of Lovelace"}"#),
+    )
+    .await;
+    response.assert_status(StatusCode::OK);
+    assert_json_include(&response.json(), &json!({"name": "Countess of Lovelace", "version": 2}));
+
+    let response = send(
+        app.patch(&path)
+            .header("Content-Type", "application/json-patch+json")
+            .header("If-Match", "\"2\"")
+            .body(r#"[{"op": "replace", "path": "/version", "value": 9}]"#),
+    )
+    .await;
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = send(app.patch(&path).header("If-Match", "\"2\"").json(&json!({"name": "Ada"}))).await;
+    response.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(response.header("Accept-Patch").unwrap().contains("application/merge-patch+json"));
+}
+
+#[tokio::test]
+async fn test_import_users() {
+    let app = spawn_app!();
+    app.create_user("taken@example.com", "Taken").await;
+    let csv = "email,name\nada@example.com,Ada\ngrace@example.com,Grace\ntaken@example.com,Again\n";
+
+    let response = send(
+        app.post("/api/v1/users/import?mode=per_row")
+            .header("Content-Type", "text/csv")
+            .body(csv),
+    )
+    .await;
+    response.assert_status(StatusCode::OK);
+    assert_json_include(&response.json(), &json!({"total_rows": 3, "imported_rows": 2, "failed_rows": 1}));
+
+    let response = send(
+        app.post("/api/v1/users/import")
+            .header("Content-Type", "application/x-ndjson")
+            .body("{\"email\": \"x@example.com\", \"name\": \"X\"}\n{\"email\": \"ada@example.com\", \"name\": \"Ada\"}\n"),
+    )
+    .await;
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    assert_json_include(&response.json(), &json!({"imported_rows": 0, "failed_rows": 1}));
+
+    send(app.post("/api/v1/users/import").header("Content-Type", "text/plain").body(csv))
+        .await
+        .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[tokio::test]
+async fn test_export_users() {
+    let app = spawn_app!();
+    app.create_user("ada@example.com", "Ada").await;
+    app.create_user("grace@example.com", "Grace").await;
+
+    let response = send(app.get("/api/v1/users/export?fields=email,name").header("Accept", "text/csv")).await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.text(), "email,name\r\nada@example.com,Ada\r\ngrace@example.com,Grace\r\n");
+
+    let response = send(
+        app.get("/api/v1/users/export?name_contains=GRA")
+            .header("Accept", "application/x-ndjson"),
+    )
+    .await;
+    response.assert_status(StatusCode::OK);
+    let lines: Vec<serde_json::Value> = response
+        .text()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 1);
+    assert_json_include(&lines[0], &json!({"email": "grace@example.com"}));
+
+    send(app.get("/api/v1/users/export?fields=password"))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_search_users() {
+    let app = spawn_app!();
+    app.create_user("ada@example.com", "Ada Lovelace").await;
+    app.create_user("grace@example.com", "Grace Hopper").await;
+
+    let response = send(app.get("/api/v1/users/search?q=lovel")).await;
+    response.assert_status(StatusCode::OK);
+    let hits = response.json();
+    assert_eq!(hits.as_array().unwrap().len(), 1);
+    assert_json_include(&hits[0], &json!({"user": {"name": "Ada Lovelace"}}));
+
+    send(app.get("/api/v1/users/search?q=%40%40"))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_stream_replays_user_events() {
+    let app = spawn_app!();
+    let user = app.create_user("ada@example.com", "Ada").await;
+
+    let mut response = app.get("/api/v1/users/stream?last_event_id=0").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "text/event-stream");
+
+    // The stream never ends, so read until the replayed event shows up
+    let mut received = String::new();
+    while !received.contains("event: user.created") {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), response.chunk())
+            .await
+            .expect("no event within 5 seconds")
+            .unwrap()
+            .expect("stream ended");
+        received.push_str(&String::from_utf8_lossy(&chunk));
+    }
+    assert!(received.starts_with("retry: "));
+    assert!(received.contains(user["id"].as_str().unwrap()));
+
+    send(app.get("/api/v1/users/stream").header("Last-Event-ID", "soon"))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_email_change_flow() {
+    let app = spawn_app!();
+    app.create_user("taken@example.com", "Taken").await;
+    let user = app.create_user("ada@example.com", "Ada").await;
+    let path = format!("/api/v1/users/{}/email-change", user["id"].as_str().unwrap());
+
+    send(app.get(&path)).await.assert_status(StatusCode::NOT_FOUND);
+    send(app.post(&path).json(&json!({"email": "Taken@example.com"})))
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    let response = send(app.post(&path).json(&json!({"email": "ada@lovelace.org"}))).await;
+    response.assert_status(StatusCode::ACCEPTED);
+    assert_json_include(&response.json(), &json!({"new_email": "ada@lovelace.org", "status": "pending"}));
+
+    let response = send(app.get(&path)).await;
+    response.assert_status(StatusCode::OK);
+    assert_json_include(&response.json(), &json!({"new_email": "ada@lovelace.org"}));
+
+    // Tokens only travel by email, so a guessed one finds nothing
+    send(app.post("/api/v1/email-changes/confirm").json(&json!({"token": "guess"})))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_assert_json_include() {
+    let actual = json!({"a": 1, "b": {"c": [1, {"d": 2, "e": 3}]}});
+    assert_json_include(&actual, &json!({"b": {"c": [1, {"d": 2}]}}));
+    assert!(std::panic::catch_unwind(|| assert_json_include(&actual, &json!({"a": 2}))).is_err());
+    assert!(std::panic::catch_unwind(|| assert_json_include(&actual, &json!({"b": {"c": [1]}}))).is_err());
+}
+
+#[tokio::test]
+async fn test_mock_mode_serves_users_from_memory() {
+    let app = spawn_app!(mock MemoryUserStore::new(), Faults::default());
+
+    let response = send(app.post("/api/v1/users").json(&json!({"email": "ada@example.com", "name": "Ada"}))).await;
+    response.assert_status(StatusCode::CREATED);
+    let id = response.json()["id"].as_str().unwrap().to_string();
+
+    // Without a key store, retries with the same key create again and so conflict on the email
+    let response = send(
+        app.post("/api/v1/users")
+            .header("Idempotency-Key", "retry")
+            .json(&json!({"email": "ADA@example.com", "name": "Ada"})),
+    )
+    .await;
+    response.assert_status(StatusCode::CONFLICT);
+
+    let response = send(
+        app.request(Method::PATCH, &format!("/api/v1/users/{}", id))
+            .header("Content-Type", "application/merge-patch+json")
+            .header("If-Match", "\"1\"")
+            .body(r#"{"name": "Ada Lovelace"}"#),
+    )
+    .await;
+    response.assert_status(StatusCode::OK);
+    assert_json_include(&response.json(), &json!({"name": "Ada Lovelace", "version": 2}));
+
+    let response = send(app.get("/api/v1/users/search?q=lovel")).await;
+    response.assert_status(StatusCode::OK);
+    assert_json_include(&response.json(), &json!([{"user": {"id": id}}]));
+    let response = send(app.get("/api/v1/users/lookup?email=ADA@example.com")).await;
+    assert_json_include(&response.json(), &json!({"id": id}));
+}
+
+#[tokio::test]
+async fn test_mock_mode_rejects_routes_that_need_postgres() {
+    let app = spawn_app!(mock MemoryUserStore::new(), Faults::default());
+
+    for (method, path) in [
+        (Method::GET, "/api/v1/webhooks"),
+        (Method::POST, "/api/v1/users/import"),
+        (Method::GET, "/api/v1/users/stream"),
+        (Method::POST, "/api/v1/email-changes/confirm"),
+    ] {
+        let response = send(app.request(method, path)).await;
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        assert_json_include(&response.json(), &json!({"error": "Not available in mock mode"}));
+    }
+}
+
+#[tokio::test]
+async fn test_mock_mode_injects_latency_and_failures() {
+    let faults = Faults { latency: Some("150".parse::<Latency>().unwrap()), error_rate: 1.0, drop_rate: 0.0 };
+    let app = spawn_app!(mock MemoryUserStore::new(), faults);
+
+    let started = std::time::Instant::now();
+    let response = send(app.get("/api/v1/users")).await;
+    assert!(started.elapsed() >= Duration::from_millis(150));
+    response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    assert_json_include(&response.json(), &json!({"error": "Internal server error"}));
+}
+
+#[tokio::test]
+async fn test_chaos_fails_store_calls_until_disabled() {
+    let app = spawn_app!(with |settings| {
+        settings.chaos.enabled = true;
+        settings.chaos.admin_token = Some("chaos-token".to_string());
+        settings.chaos.rules.active = true;
+        settings.chaos.rules.store.error_rate = 1.0;
+    });
+
+    let response = send(app.get("/api/v1/users")).await;
+    response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    assert_json_include(&response.json(), &json!({"error": "Internal server error"}));
+
+    send(app.post("/admin/chaos/disable")).await.assert_status(StatusCode::UNAUTHORIZED);
+    let response = send(app.post("/admin/chaos/disable").bearer_auth("chaos-token")).await;
+    response.assert_status(StatusCode::OK);
+    assert_json_include(&response.json(), &json!({"active": false, "store": {"error_rate": 1.0}}));
+
+    send(app.get("/api/v1/users")).await.assert_status(StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_chaos_route_rules_and_dropped_connections() {
+    let app = spawn_app!(mock MemoryUserStore::new(), Faults::default());
+
+    let rules = json!({
+        "active": true,
+        "routes": [
+            {"method": "GET", "path": "/api/v1/users/search", "faults": {"error_rate": 1.0}},
+            {"path": "/api/v1/users/{id}", "faults": {"drop_rate": 1.0}},
+        ],
+    });
+    let response = send(app.put("/admin/chaos").json(&rules)).await;
+    response.assert_status(StatusCode::OK);
+    assert_json_include(&response.json(), &rules);
+
+    send(app.get("/api/v1/users")).await.assert_status(StatusCode::OK);
+    send(app.get("/api/v1/users/search?q=ada")).await.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    let dropped = app.get(&format!("/api/v1/users/{}", uuid::Uuid::new_v4())).send().await;
+    assert!(dropped.is_err() || dropped.unwrap().bytes().await.is_err());
+
+    let response = send(app.put("/admin/chaos").json(&json!({"http": {"error_rate": 0.6, "drop_rate": 0.6}}))).await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_json_include(
+        &response.json(),
+        &json!({"error": "http.error_rate and http.drop_rate must not add up to more than 1"}),
+    );
+
+    send(app.post("/admin/chaos/disable")).await.assert_status(StatusCode::OK);
+    send(app.get("/api/v1/users/search?q=ada")).await.assert_status(StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_load_generator_reports_every_request() {
+    let app = spawn_app!(mock MemoryUserStore::new(), Faults::default());
+
+    let options = LoadOptions {
+        base_url: app.address.clone(),
+        scenario: Scenario::Mixed,
+        concurrency: 4,
+        duration: Duration::from_secs(30),
+        max_requests: Some(200),
+    };
+    let report = loadgen::run(&options).await.unwrap();
+
+    assert_eq!(report.requests, 200);
+    assert!(report.failures.is_empty(), "failures: {:?}", report.failures);
+    assert!(report.throughput > 0.0);
+    let latency = &report.latency_ms;
+    assert!(latency.min <= latency.p50 && latency.p50 <= latency.p99 && latency.p99 <= latency.max);
+
+    // Setup users plus one create in ten
+    let users = send(app.get("/api/v1/users")).await.json();
+    assert_eq!(users.as_array().unwrap().len(), 100 + 20);
+}
+
+/// Serves mock mode with every request delayed by `latency`, shuts down once a request is in
+/// flight, and returns what the request and shutdown produced, checking readiness on the way.
+async fn shutdown_during_request(config: ShutdownConfig, latency: &str) -> Option<(bool, ShutdownReport)> {
+    let mut settings = match Settings::new() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("Skipping API test, no configuration: {}", err);
+            return None;
+        }
+    };
+    settings.shutdown = config;
+    let faults = Faults { latency: Some(latency.parse().unwrap()), ..Faults::default() };
+    let state = AppState::mock(&settings, MemoryUserStore::new(), faults);
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    let server = app::run_server(state.clone(), listener, Some(1), None).unwrap();
+    let lifecycle = state.lifecycle.clone();
+    let (trigger, triggered) = tokio::sync::oneshot::channel::<()>();
+    let serving = actix_web::rt::spawn(async move {
+        shutdown::serve(server, &state, BackgroundJobs::new(), None, async {
+            let _ = triggered.await;
+        })
+        .await
+    });
+
+    let client = reqwest::Client::new();
+    let ready = format!("{}/health/ready", address);
+    assert_eq!(client.get(&ready).send().await.unwrap().status(), StatusCode::OK);
+
+    let request = client.get(format!("{}/api/v1/users", address)).send();
+    let in_flight = actix_web::rt::spawn(async move {
+        match request.await {
+            Ok(response) => response.status() == StatusCode::OK && response.bytes().await.is_ok(),
+            Err(_) => false,
+        }
+    });
+    // Shut down only once the request is being served, however slowly the client got it there
+    while lifecycle.in_flight() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    trigger.send(()).unwrap();
+
+    if settings.shutdown.readiness_grace_secs > 0 {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let response = send(client.get(&ready)).await;
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        assert_json_include(&response.json(), &json!({"status": "shutting_down"}));
+    }
+
+    let report = serving.await.unwrap().unwrap();
+    Some((in_flight.await.unwrap(), report))
+}
+
+#[actix_web::test]
+async fn test_shutdown_fails_readiness_then_drains_requests() {
+    let config = ShutdownConfig { readiness_grace_secs: 1, drain_timeout_secs: 10 };
+    let Some((completed, report)) = shutdown_during_request(config, "1500").await else {
+        return;
+    };
+
+    assert!(completed);
+    assert_eq!(report, ShutdownReport::default());
+}
+
+#[actix_web::test]
+async fn test_shutdown_aborts_requests_after_drain_timeout() {
+    let config = ShutdownConfig { readiness_grace_secs: 0, drain_timeout_secs: 1 };
+    let Some((completed, report)) = shutdown_during_request(config, "10000").await else {
+        return;
+    };
+
+    assert!(!completed);
+    assert_eq!(report.aborted_requests, 1);
+}
+
+#[actix_web::test]
+async fn test_serve_returns_when_the_server_stops_by_itself() {
+    let Ok(settings) = Settings::new() else {
+        eprintln!("Skipping API test, no configuration");
+        return;
+    };
+    let state = AppState::mock(&settings, MemoryUserStore::new(), Faults::default());
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = app::run_server(state.clone(), listener, Some(1), None).unwrap();
+    let handle = server.handle();
+    // Never connects; closing it needs no database
+    let pool = sqlx::PgPool::connect_lazy(&settings.database_url()).unwrap();
+    let serving = actix_web::rt::spawn({
+        let pool = pool.clone();
+        async move { shutdown::serve(server, &state, BackgroundJobs::new(), Some(pool), std::future::pending()).await }
+    });
+
+    handle.stop(true).await;
+    let report = tokio::time::timeout(Duration::from_secs(5), serving).await.unwrap().unwrap().unwrap();
+    assert_eq!(report, ShutdownReport::default());
+    assert!(pool.is_closed());
+}
+
+fn tls_fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/tls").join(name)
+}
+
+/// TLS settings serving the fixture certificate for localhost from a fresh directory, where a
+/// test can replace it.
+fn tls_config() -> TlsConfig {
+    let dir = std::env::temp_dir().join(format!("tangy-mango-tls-{}", uuid::Uuid::new_v4()));
+    fs::create_dir(&dir).unwrap();
+    let config = TlsConfig { cert_path: dir.join("tls.crt"), key_path: dir.join("tls.key"), ..TlsConfig::default() };
+    fs::copy(tls_fixture("server.pem"), &config.cert_path).unwrap();
+    fs::copy(tls_fixture("server.key"), &config.key_path).unwrap();
+    config
+}
+
+/// A client trusting the test CA, presenting the fixture client certificate if `identity`.
+fn tls_client(identity: bool) -> reqwest::Client {
+    let ca = reqwest::Certificate::from_pem(&fs::read(tls_fixture("ca.pem")).unwrap()).unwrap();
+    let mut builder = reqwest::Client::builder().use_rustls_tls().add_root_certificate(ca).tls_info(true);
+    if identity {
+        let pem = [fs::read(tls_fixture("client.pem")).unwrap(), fs::read(tls_fixture("client.key")).unwrap()].concat();
+        builder = builder.identity(reqwest::Identity::from_pem(&pem).unwrap());
+    }
+    builder.build().unwrap()
+}
+
+fn fixture_der(name: &str) -> Vec<u8> {
+    let pem = fs::read(tls_fixture(name)).unwrap();
+    rustls_pemfile::certs(&mut &pem[..]).unwrap().remove(0)
+}
+
+#[tokio::test]
+async fn test_https_negotiates_http2_and_serves_renewed_certificates() {
+    let config = tls_config();
+    let Some((app, certs)) = MockApp::spawn_tls(MemoryUserStore::new(), &config, tls_client(false)) else {
+        return;
+    };
+    let url = format!("{}/health/ready", app.address.replace("127.0.0.1", "localhost"));
+    let peer_certificate = |response: &reqwest::Response| {
+        response.extensions().get::<reqwest::tls::TlsInfo>().unwrap().peer_certificate().unwrap().to_vec()
+    };
+
+    let response = tls_client(false).get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+    assert_eq!(peer_certificate(&response), fixture_der("server.pem"));
+
+    fs::copy(tls_fixture("server-renewed.pem"), &config.cert_path).unwrap();
+    fs::copy(tls_fixture("server-renewed.key"), &config.key_path).unwrap();
+    assert!(certs.reload_if_changed().unwrap());
+
+    // New connections get the renewed certificate
+    let response = tls_client(false).get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(peer_certificate(&response), fixture_der("server-renewed.pem"));
+
+    drop(app);
+    fs::remove_dir_all(config.cert_path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn test_required_client_certificates_refuse_anonymous_callers() {
+    let mut config = tls_config();
+    config.client_ca_path = Some(tls_fixture("ca.pem"));
+    config.require_client_cert = true;
+    let Some((app, _)) = MockApp::spawn_tls(MemoryUserStore::new(), &config, tls_client(true)) else {
+        return;
+    };
+
+    send(app.get("/api/v1/users")).await.assert_status(StatusCode::OK);
+
+    let anonymous = tls_client(false).get(format!("{}/api/v1/users", app.address)).send().await;
+    assert!(anonymous.is_err(), "served a client without a certificate: {:?}", anonymous);
+
+    drop(app);
+    fs::remove_dir_all(config.cert_path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn test_rate_limits_share_postgres_buckets_per_api_key() {
+    let app = spawn_app!(with |settings| {
+        settings.rate_limit.enabled = true;
+        settings.rate_limit.store = RateLimitStoreKind::Postgres;
+        settings.rate_limit.routes = vec![RateLimitRule {
+            method: Some("POST".to_string()),
+            path: "/api/v1/users".to_string(),
+            key: RateLimitKey::ApiKey,
+            requests: 2,
+            window_secs: 60,
+        }];
+        settings.rate_limit.api_keys = vec!["billing".to_string(), "reports".to_string()];
+    });
+    let create = |api_key: &str, n: u32| {
+        app.post("/api/v1/users")
+            .header("X-Api-Key", api_key)
+            .json(&json!({"email": format!("{}-{}@example.com", api_key, n), "name": "Rate Limited"}))
+    };
+
+    let first = send(create("billing", 1)).await;
+    first.assert_status(StatusCode::CREATED);
+    assert_eq!(first.header("RateLimit-Limit"), Some("2"));
+    assert_eq!(first.header("RateLimit-Remaining"), Some("1"));
+    assert_eq!(first.header("RateLimit-Policy"), Some("2;w=60"));
+    assert_eq!(first.header("RateLimit-Reset"), Some("30"));
+    assert!(first.header("Retry-After").is_none());
+    send(create("billing", 2)).await.assert_status(StatusCode::CREATED);
+
+    let refused = send(create("billing", 3)).await;
+    refused.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(refused.header("RateLimit-Remaining"), Some("0"));
+    let retry_after: u64 = refused.header("Retry-After").unwrap().parse().unwrap();
+    assert!((1..=30).contains(&retry_after), "Retry-After: {}", retry_after);
+    assert_json_include(&refused.json(), &json!({"error": "Rate limit exceeded"}));
+
+    // Other keys and other routes are unaffected
+    send(create("reports", 1)).await.assert_status(StatusCode::CREATED);
+    // while keys nobody configured share one bucket for the client's address
+    send(create("guess-1", 1)).await.assert_status(StatusCode::CREATED);
+    send(create("guess-2", 1)).await.assert_status(StatusCode::CREATED);
+    send(create("guess-3", 1)).await.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    let list = send(app.get("/api/v1/users")).await;
+    list.assert_status(StatusCode::OK);
+    assert!(list.header("RateLimit-Limit").is_none());
+}
+
+#[tokio::test]
+async fn test_requests_fail_fast_while_the_pool_is_exhausted() {
+    let app = spawn_app!(with |settings| {
+        settings.load_shedding.enabled = true;
+        settings.load_shedding.max_pool_wait_ms = 50;
+        settings.load_shedding.pool_sample_interval_ms = 20;
+        settings.load_shedding.retry_after_secs = 2;
+    });
+    send(app.get("/api/v1/users")).await.assert_status(StatusCode::OK);
+
+    let mut held = Vec::new();
+    for _ in 0..app.pool.options().get_max_connections() {
+        held.push(app.pool.acquire().await.unwrap());
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let started = std::time::Instant::now();
+    let refused = send(app.get("/api/v1/users")).await;
+    refused.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+    assert_eq!(refused.header("Retry-After"), Some("2"));
+    send(app.get("/health/ready")).await.assert_status(StatusCode::OK);
+
+    let metrics = send(app.get("/metrics")).await;
+    metrics.assert_status(StatusCode::OK);
+    let metrics = metrics.text();
+    assert!(metrics.contains("tangy_mango_db_pool_under_pressure 1"), "{}", metrics);
+    assert!(metrics.contains("tangy_mango_db_pool_connections{state=\"idle\"} 0"), "{}", metrics);
+    assert!(
+        metrics.contains("tangy_mango_shed_requests_total{route=\"GET /api/v1/users\",reason=\"pool_pressure\"} 1"),
+        "{}",
+        metrics
+    );
+
+    drop(held);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    send(app.get("/api/v1/users")).await.assert_status(StatusCode::OK);
+    let metrics = send(app.get("/metrics")).await.text();
+    assert!(metrics.contains("tangy_mango_db_pool_under_pressure 0"), "{}", metrics);
+    assert!(metrics.contains("tangy_mango_route_concurrency_limit{route=\"GET /api/v1/users\"}"), "{}", metrics);
+}
+
+#[tokio::test]
+async fn test_postgres_rate_limits_do_not_hold_up_load_shedding() {
+    let app = spawn_app!(with |settings| {
+        settings.load_shedding.enabled = true;
+        settings.load_shedding.max_pool_wait_ms = 50;
+        settings.load_shedding.pool_sample_interval_ms = 20;
+        settings.rate_limit.enabled = true;
+        settings.rate_limit.store = RateLimitStoreKind::Postgres;
+        settings.rate_limit.routes = vec![RateLimitRule {
+            method: None,
+            path: "/api/v1/users".to_string(),
+            key: RateLimitKey::Ip,
+            requests: 10,
+            window_secs: 60,
+        }];
+    });
+    send(app.get("/api/v1/users")).await.assert_status(StatusCode::OK);
+
+    let mut held = Vec::new();
+    for _ in 0..app.pool.options().get_max_connections() {
+        held.push(app.pool.acquire().await.unwrap());
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // The bucket is still counted, on the limiter's own connections, before the request is shed
+    let started = std::time::Instant::now();
+    let refused = send(app.get("/api/v1/users")).await;
+    refused.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+    assert_eq!(refused.header("RateLimit-Remaining"), Some("8"));
+
+    drop(held);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let served = send(app.get("/api/v1/users")).await;
+    served.assert_status(StatusCode::OK);
+    assert_eq!(served.header("RateLimit-Remaining"), Some("7"));
+}
@@ -0,0 +1,291 @@
+//! HTTP test harness shared by the API tests
+//!
+//! [`TestApp::spawn`] starts the real application, built by `app::run_server` exactly as
+//! `serve` builds it, on an ephemeral port. Each app gets its own Postgres schema, migrated
+//! from scratch and dropped when the app goes out of scope, so tests can run in parallel
+//! against the database named in `Config.toml` without seeing each other's rows.
+//! [`MockApp::spawn`] starts it in `serve --mock` mode instead, which needs no database.
+
+#![allow(dead_code)]
+
+use std::net::TcpListener;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use actix_web::dev::ServerHandle;
+use reqwest::header::HeaderMap;
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde_json::Value;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::PgPool;
+use tangy_mango::app::{self, AppState};
+use tangy_mango::chaos::Faults;
+use tangy_mango::config::{Settings, TlsConfig};
+use tangy_mango::db::{self, AdvisoryLock};
+use tangy_mango::services::memory_user_store::MemoryUserStore;
+use tangy_mango::tls::CertReloader;
+use uuid::Uuid;
+
+pub struct TestApp {
+    pub address: String,
+    /// Connections to this app's schema, for arranging data the API cannot
+    pub pool: PgPool,
+    client: reqwest::Client,
+    schema: String,
+    database_url: String,
+    server: ServerHandle,
+    monitor: tokio::task::JoinHandle<()>,
+}
+
+impl TestApp {
+    /// Starts the app against a fresh schema. Returns `None`, after saying why, when there is
+    /// no `Config.toml` or its database is unreachable, so the suite passes on machines
+    /// without Postgres.
+    pub async fn spawn() -> Option<TestApp> {
+        Self::spawn_with(|_| {}).await
+    }
+
+    /// Like [`spawn`](Self::spawn), with `configure` adjusting the settings first.
+    pub async fn spawn_with(configure: impl FnOnce(&mut Settings)) -> Option<TestApp> {
+        let mut settings = match Settings::new() {
+            Ok(settings) => settings,
+            Err(err) => {
+                eprintln!("Skipping API test, no configuration: {}", err);
+                return None;
+            }
+        };
+        configure(&mut settings);
+        let database_url = settings.database_url();
+        let admin = match PgPoolOptions::new().max_connections(1).connect(&database_url).await {
+            Ok(pool) => pool,
+            Err(err) => {
+                eprintln!("Skipping API test, database unavailable: {}", err);
+                return None;
+            }
+        };
+
+        // Extensions are per database; installed in public, every schema sees them
+        let lock = AdvisoryLock::acquire(&admin, "test-extensions").await.unwrap();
+        sqlx::query("CREATE EXTENSION IF NOT EXISTS pg_trgm SCHEMA public")
+            .execute(&admin)
+            .await
+            .unwrap();
+        lock.release().await;
+
+        let schema = format!("test_{}", Uuid::new_v4().simple());
+        sqlx::query(&format!("CREATE SCHEMA {}", schema)).execute(&admin).await.unwrap();
+        admin.close().await;
+
+        let options = PgConnectOptions::from_str(&database_url)
+            .unwrap()
+            .options([("search_path", format!("{},public", schema))]);
+        let pool = PgPoolOptions::new().max_connections(5).connect_with(options).await.unwrap();
+        db::run_migrations(&pool).await.expect("Failed to migrate test schema");
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let state = AppState::new(&settings, &pool);
+        // As `serve` does, so load shedding sees pool pressure
+        let monitor = tokio::spawn(state.pool_monitor.clone().unwrap().run());
+        let server = app::run_server(state, listener, Some(1), None).unwrap();
+        let handle = server.handle();
+        tokio::spawn(server);
+
+        Some(TestApp {
+            address,
+            pool,
+            client: reqwest::Client::new(),
+            schema,
+            database_url,
+            server: handle,
+            monitor,
+        })
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.client.request(method, format!("{}{}", self.address, path))
+    }
+
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    pub fn put(&self, path: &str) -> RequestBuilder {
+        self.request(Method::PUT, path)
+    }
+
+    pub fn patch(&self, path: &str) -> RequestBuilder {
+        self.request(Method::PATCH, path)
+    }
+
+    /// Creates a user through the API and returns its JSON representation.
+    pub async fn create_user(&self, email: &str, name: &str) -> Value {
+        let response = send(self.post("/api/v1/users").json(&serde_json::json!({"email": email, "name": name}))).await;
+        response.assert_status(StatusCode::CREATED);
+        response.json()
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        // Stopping only needs the command sent; the returned future just waits for completion
+        drop(self.server.stop(false));
+        self.monitor.abort();
+
+        // Drop runs outside any async context, so the cleanup gets a runtime of its own
+        let (database_url, schema) = (self.database_url.clone(), self.schema.clone());
+        let cleanup = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                let admin = PgPoolOptions::new().max_connections(1).connect(&database_url).await?;
+                sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema)).execute(&admin).await?;
+                admin.close().await;
+                Ok::<_, sqlx::Error>(())
+            })
+        });
+        if let Ok(Err(err)) = cleanup.join() {
+            eprintln!("Failed to drop test schema {}: {}", self.schema, err);
+        }
+    }
+}
+
+/// The app in mock mode, serving users from memory.
+pub struct MockApp {
+    pub address: String,
+    client: reqwest::Client,
+    server: ServerHandle,
+}
+
+impl MockApp {
+    /// Starts the app over `store`. Returns `None`, after saying why, without a `Config.toml`.
+    pub fn spawn(store: MemoryUserStore, faults: Faults) -> Option<MockApp> {
+        Self::start(store, faults, None, reqwest::Client::new())
+    }
+
+    /// Like [`spawn`](Self::spawn), serving HTTPS as `tls` says to requests sent with `client`.
+    /// Also returns the certificate holder, to reload it without waiting for the interval.
+    pub fn spawn_tls(store: MemoryUserStore, tls: &TlsConfig, client: reqwest::Client) -> Option<(MockApp, Arc<CertReloader>)> {
+        let certs = CertReloader::load(tls).unwrap();
+        let config = tangy_mango::tls::server_config(tls, certs.clone()).unwrap();
+        Some((Self::start(store, Faults::default(), Some(config), client)?, certs))
+    }
+
+    fn start(
+        store: MemoryUserStore,
+        faults: Faults,
+        tls: Option<rustls::ServerConfig>,
+        client: reqwest::Client,
+    ) -> Option<MockApp> {
+        let settings = match Settings::new() {
+            Ok(settings) => settings,
+            Err(err) => {
+                eprintln!("Skipping API test, no configuration: {}", err);
+                return None;
+            }
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        let address = format!("{}://{}", scheme, listener.local_addr().unwrap());
+        let state = AppState::mock(&settings, store, faults);
+        let server = app::run_server(state, listener, Some(1), tls).unwrap();
+        let handle = server.handle();
+        tokio::spawn(server);
+
+        Some(MockApp { address, client, server: handle })
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.client.request(method, format!("{}{}", self.address, path))
+    }
+
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    pub fn put(&self, path: &str) -> RequestBuilder {
+        self.request(Method::PUT, path)
+    }
+}
+
+impl Drop for MockApp {
+    fn drop(&mut self) {
+        drop(self.server.stop(false));
+    }
+}
+
+/// A fully read response.
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    #[track_caller]
+    pub fn assert_status(&self, expected: StatusCode) -> &Self {
+        assert_eq!(self.status, expected, "unexpected status; body: {}", self.text());
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    #[track_caller]
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or_else(|err| panic!("invalid JSON ({}): {}", err, self.text()))
+    }
+}
+
+/// Sends a request and reads the whole body.
+pub async fn send(request: RequestBuilder) -> TestResponse {
+    let response = request.send().await.expect("request failed");
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.bytes().await.expect("failed to read body").to_vec();
+    TestResponse { status, headers, body }
+}
+
+/// Asserts that `actual` contains everything in `expected`: objects may have extra keys,
+/// while arrays and scalars must match exactly (arrays element by element, with the same
+/// subset rule inside).
+#[track_caller]
+pub fn assert_json_include(actual: &Value, expected: &Value) {
+    if let Err(path) = json_includes(actual, expected, "$") {
+        panic!("JSON mismatch at {}\nexpected to include: {}\nactual: {}", path, expected, actual);
+    }
+}
+
+fn json_includes(actual: &Value, expected: &Value, path: &str) -> Result<(), String> {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => {
+            for (key, expected) in expected {
+                let path = format!("{}.{}", path, key);
+                let actual = actual.get(key).ok_or_else(|| path.clone())?;
+                json_includes(actual, expected, &path)?;
+            }
+            Ok(())
+        }
+        (Value::Array(actual), Value::Array(expected)) if actual.len() == expected.len() => {
+            for (index, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+                json_includes(actual, expected, &format!("{}[{}]", path, index))?;
+            }
+            Ok(())
+        }
+        (actual, expected) if actual == expected => Ok(()),
+        _ => Err(path.to_string()),
+    }
+}
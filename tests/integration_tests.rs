@@ -1,9 +1,11 @@
 //! Integration tests for the tangy-mango web service
 //! 
-//! These tests demonstrate how the different components work together.
-//! For actual database integration tests, you would need a test database setup.
+//! These tests demonstrate how the different components work together. Those declared with
+//! `db_test!` run against the test database, skipped when it is unavailable.
 
-use tangy_mango::{CreateUserRequest, UserResponse};
+use std::sync::Arc;
+
+use tangy_mango::{db_test, CreateUserRequest, UserDao, UserResponse, UserService};
 use uuid::Uuid;
 use chrono::Utc;
 
@@ -25,8 +27,10 @@ fn test_user_workflow() {
         id: Uuid::new_v4(),
         email: request.email.clone(),
         name: request.name.clone(),
+        version: 1,
         created_at: Utc::now(),
         updated_at: Utc::now(),
+        suspended_at: None,
     };
 
     // Verify the response matches the request
@@ -84,8 +88,10 @@ fn test_json_serialization_deserialization() {
         id: Uuid::new_v4(),
         email: "response@test.com".to_string(),
         name: "Response Test User".to_string(),
+        version: 1,
         created_at: Utc::now(),
         updated_at: Utc::now(),
+        suspended_at: None,
     };
 
     let response_json = serde_json::to_string(&response).unwrap();
@@ -96,12 +102,13 @@ fn test_json_serialization_deserialization() {
 #[test]
 fn test_configuration_integration() {
     // Test configuration components work together
-    use tangy_mango::config::{Settings, ServerConfig, DatabaseConfig};
+    use tangy_mango::config::{Settings, ServerConfig, DatabaseConfig, WebhookConfig, EventStreamConfig, IdempotencyConfig, ImportConfig, SearchConfig, EmailChangeConfig, LeaderElectionConfig, ChaosConfig, ShutdownConfig, RateLimitConfig, LoadSheddingConfig};
 
     let settings = Settings {
         server: ServerConfig {
             host: "0.0.0.0".to_string(),
             port: 8080,
+            tls: None,
         },
         database: DatabaseConfig {
             host: "localhost".to_string(),
@@ -111,6 +118,17 @@ fn test_configuration_integration() {
             database_name: "test_database".to_string(),
             max_connections: 10,
         },
+        webhooks: WebhookConfig::default(),
+        event_stream: EventStreamConfig::default(),
+        idempotency: IdempotencyConfig::default(),
+        import: ImportConfig::default(),
+        search: SearchConfig::default(),
+        email_change: EmailChangeConfig::default(),
+        leader_election: LeaderElectionConfig::default(),
+        chaos: ChaosConfig::default(),
+        shutdown: ShutdownConfig::default(),
+        rate_limit: RateLimitConfig::default(),
+        load_shedding: LoadSheddingConfig::default(),
     };
 
     // Test database URL generation
@@ -124,4 +142,19 @@ fn test_configuration_integration() {
     // Test server configuration
     assert_eq!(settings.server.host, "0.0.0.0");
     assert_eq!(settings.server.port, 8080);
-}
\ No newline at end of file
+}
+
+db_test!(async fn test_suspending_a_fixture_user_through_the_service(pool: PgPool) fixtures("users.yaml") {
+    let service = UserService::new(Arc::new(UserDao::new(pool)));
+    let ada = Uuid::parse_str("01890a5d-ac96-774b-bcce-b302099a8057").unwrap();
+
+    let suspended = service.set_suspended(ada, true).await.unwrap();
+    assert!(suspended.suspended_at.is_some());
+    assert_eq!(suspended.version, 2);
+
+    // Grace is suspended by the fixture already
+    let grace = Uuid::parse_str("018914aa-6496-7f10-9a8b-7c6d5e4f3a2b").unwrap();
+    let lifted = service.set_suspended(grace, false).await.unwrap();
+    assert_eq!(lifted.suspended_at, None);
+    assert_eq!(lifted.version, 3);
+});
@@ -0,0 +1,137 @@
+//! Webhook delivery tests against a local HTTP receiver
+//!
+//! These tests start a throwaway actix server on an ephemeral port and point the
+//! webhook sender at it, so signing and header handling are verified end to end
+//! without a database.
+
+use std::sync::{Arc, Mutex};
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use tangy_mango::services::webhook_dispatcher::{
+    verify_signature, WebhookSender, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+};
+use uuid::Uuid;
+
+#[derive(Clone, Debug)]
+struct ReceivedRequest {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl ReceivedRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+type Received = Arc<Mutex<Vec<ReceivedRequest>>>;
+
+/// Starts a receiver that records every request and answers with `status`.
+fn start_receiver(status: u16) -> (String, Received) {
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let state = received.clone();
+
+    let server = HttpServer::new(move || {
+        let state = state.clone();
+        App::new().default_service(web::to(move |request: HttpRequest, body: web::Bytes| {
+            let state = state.clone();
+            async move {
+                state.lock().unwrap().push(ReceivedRequest {
+                    headers: request
+                        .headers()
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.to_str().unwrap_or_default().to_string()))
+                        .collect(),
+                    body: body.to_vec(),
+                });
+                HttpResponse::build(actix_web::http::StatusCode::from_u16(status).unwrap()).body("receiver says hi")
+            }
+        }))
+    })
+    .workers(1)
+    .bind("127.0.0.1:0")
+    .unwrap();
+
+    let address = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+
+    (format!("http://{}/hooks", address), received)
+}
+
+fn sample_payload() -> serde_json::Value {
+    serde_json::json!({
+        "id": 1,
+        "type": "user.created",
+        "data": {"email": "webhook@test.com", "name": "Webhook Test User"}
+    })
+}
+
+#[actix_web::test]
+async fn test_signed_delivery_is_verifiable_by_receiver() {
+    let (url, received) = start_receiver(200);
+    let sender = WebhookSender::new(std::time::Duration::from_secs(5));
+    let delivery_id = Uuid::new_v4();
+
+    let outcome = sender
+        .send(&url, "whsec_integration_secret", delivery_id, "user.created", &sample_payload())
+        .await;
+
+    assert!(outcome.is_success(), "unexpected outcome: {:?}", outcome);
+    assert_eq!(outcome.status_code, Some(200));
+    assert!(outcome.error.is_none());
+
+    let requests = received.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0];
+
+    assert_eq!(request.header(EVENT_HEADER), Some("user.created"));
+    assert_eq!(request.header(DELIVERY_HEADER), Some(delivery_id.to_string().as_str()));
+    assert_eq!(request.header("content-type"), Some("application/json"));
+
+    let timestamp: i64 = request.header(TIMESTAMP_HEADER).unwrap().parse().unwrap();
+    let signature = request.header(SIGNATURE_HEADER).unwrap();
+    assert!(verify_signature("whsec_integration_secret", timestamp, &request.body, signature));
+    assert!(!verify_signature("whsec_wrong_secret", timestamp, &request.body, signature));
+
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body, sample_payload());
+}
+
+#[actix_web::test]
+async fn test_receiver_error_is_reported_as_failure() {
+    let (url, received) = start_receiver(503);
+    let sender = WebhookSender::new(std::time::Duration::from_secs(5));
+
+    let outcome = sender
+        .send(&url, "whsec_integration_secret", Uuid::new_v4(), "user.created", &sample_payload())
+        .await;
+
+    assert!(!outcome.is_success());
+    assert_eq!(outcome.status_code, Some(503));
+    assert!(outcome.error.unwrap().contains("receiver says hi"));
+    assert_eq!(received.lock().unwrap().len(), 1);
+}
+
+#[actix_web::test]
+async fn test_unreachable_receiver_is_reported_as_failure() {
+    // Bind and immediately drop a listener to get a port nothing is listening on
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let sender = WebhookSender::new(std::time::Duration::from_secs(2));
+
+    let outcome = sender
+        .send(
+            &format!("http://127.0.0.1:{}/hooks", port),
+            "whsec_integration_secret",
+            Uuid::new_v4(),
+            "user.created",
+            &sample_payload(),
+        )
+        .await;
+
+    assert!(!outcome.is_success());
+    assert_eq!(outcome.status_code, None);
+    assert!(outcome.error.is_some());
+}